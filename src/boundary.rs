@@ -0,0 +1,273 @@
+// With `--boundary` parts end only where a record ends, so every part can be processed on its own.
+// Sizes planned as usual are the targets: every part is cut at the last boundary before its target end
+// and never grows over its limit. Parts are still plain slices of the file, so they are merged as usual.
+// With `--csv-header` the first row is repeated at the beginning of every other part,
+// the manifest records its size and merge leaves it out.
+// `--boundary=zip` and `--boundary=tar` make every part an archive of its own, see `zip.rs` and `tar.rs`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Bytes which are read at once while a boundary is looked for
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Boundary {
+    // Anywhere, like without `--boundary`
+    Byte,
+    // After `\n`
+    Line,
+    // After `\n` which is not inside a quoted field
+    Csv,
+    // After `\n`, JSON Lines keep newlines of values escaped
+    Jsonl,
+    // After the given bytes
+    Delimiter(Vec<u8>),
+    // Between entries of a zip archive
+    Zip,
+    // Between members of a tar archive
+    Tar,
+}
+
+pub enum BoundaryError {
+    Io(io::Error),
+    // Offset of the record and the limit of the part which cannot hold it
+    RecordIsTooLong(u64, u64),
+}
+
+impl fmt::Display for BoundaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundaryError::Io(err) => write!(f, "{}", err),
+            BoundaryError::RecordIsTooLong(offset, limit) => write!(f, "record at byte {} doesn't fit into a part of {} bytes", offset, limit),
+        }
+    }
+}
+
+impl Boundary {
+    // `line`, `csv`, `jsonl`, `zip`, `tar` or `delimiter:<bytes>`, where bytes may be escaped like `\n`, `\t`, `\0` or `\x1e`
+    pub fn from_name(name: &str) -> Option<Boundary> {
+        return match name {
+            "byte" => Some(Boundary::Byte),
+            "line" => Some(Boundary::Line),
+            "csv" => Some(Boundary::Csv),
+            "jsonl" => Some(Boundary::Jsonl),
+            "zip" => Some(Boundary::Zip),
+            "tar" => Some(Boundary::Tar),
+            _ => {
+                let delimiter = unescape(name.strip_prefix("delimiter:")?)?;
+                if delimiter.is_empty() {
+                    return None;
+                }
+                Some(Boundary::Delimiter(delimiter))
+            },
+        };
+    }
+
+    // Sizes of parts which end on boundaries. `targets` are sizes planned without boundaries,
+    // `limit` is the largest size of the part at a position and `header_size` is added to every part but the first
+    pub fn part_sizes(&self, file: &mut File, file_size: u64, targets: &[u64], limit: impl Fn(usize) -> u64, header_size: u64) -> Result<Vec<u64>, BoundaryError> {
+        let mut sizes = Vec::new();
+        let mut start = 0u64;
+        let mut target_end = 0u64;
+        while start < file_size {
+            let position = sizes.len();
+            let header = if position == 0 { 0 } else { header_size };
+            let max_size = limit(position).saturating_sub(header);
+            // Parts after the planned ones are as large as they may be
+            target_end += targets.get(position).copied().unwrap_or(max_size);
+            let end = target_end.min(start + max_size).max(start + 1).min(file_size);
+
+            let cut = if end == file_size { Some(end) } else { self.last_boundary(file, start, end).map_err(BoundaryError::Io)? };
+            let Some(cut) = cut else {
+                return Err(BoundaryError::RecordIsTooLong(start, max_size));
+            };
+            sizes.push(cut - start);
+            start = cut;
+        }
+        return Ok(sizes);
+    }
+
+    // End of the last record which starts at `start` or later and ends at `end` or earlier
+    fn last_boundary(&self, file: &mut File, start: u64, end: u64) -> io::Result<Option<u64>> {
+        return match self {
+            // Parts of archives are planned from their own structure instead
+            Boundary::Byte | Boundary::Zip | Boundary::Tar => Ok(Some(end)),
+            Boundary::Line | Boundary::Jsonl => last_delimiter_end(file, start, end, b"\n"),
+            Boundary::Delimiter(delimiter) => last_delimiter_end(file, start, end, delimiter),
+            Boundary::Csv => last_csv_row_end(file, start, end, None),
+        };
+    }
+}
+
+// Size of the first CSV row together with its line end
+pub fn csv_header_size(file: &mut File, file_size: u64) -> io::Result<Option<u64>> {
+    return last_csv_row_end(file, 0, file_size, Some(1));
+}
+
+// Searches backwards, only the end of the part is read
+fn last_delimiter_end(file: &mut File, start: u64, end: u64, delimiter: &[u8]) -> io::Result<Option<u64>> {
+    let length = delimiter.len() as u64;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut window_end = end;
+    while window_end >= start + length {
+        let window_start = window_end.saturating_sub(CHUNK_SIZE as u64).max(start);
+        let window = &mut buffer[..(window_end - window_start) as usize];
+        file.seek(SeekFrom::Start(window_start))?;
+        file.read_exact(window)?;
+        if let Some(position) = window.windows(delimiter.len()).rposition(|bytes| bytes == delimiter) {
+            return Ok(Some(window_start + position as u64 + length));
+        }
+        if window_start == start {
+            break;
+        }
+        // A delimiter may lie across the start of the window
+        window_end = window_start + length - 1;
+    }
+    return Ok(None);
+}
+
+// Quotes can only be told apart from the beginning of a row, so the part is read forwards.
+// `stop_after` rows are enough when only the first ones are needed
+fn last_csv_row_end(file: &mut File, start: u64, end: u64, stop_after: Option<usize>) -> io::Result<Option<u64>> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut is_quoted = false;
+    let mut last_end = None;
+    let mut rows = 0;
+    let mut position = start;
+    file.seek(SeekFrom::Start(start))?;
+    while position < end {
+        let window = &mut buffer[..(end - position).min(CHUNK_SIZE as u64) as usize];
+        file.read_exact(window)?;
+        for (i, &byte) in window.iter().enumerate() {
+            match byte {
+                b'"' => is_quoted = !is_quoted,
+                b'\n' if !is_quoted => {
+                    last_end = Some(position + i as u64 + 1);
+                    rows += 1;
+                    if stop_after == Some(rows) {
+                        return Ok(last_end);
+                    }
+                },
+                _ => { },
+            }
+        }
+        position += window.len() as u64;
+    }
+    return Ok(last_end);
+}
+
+fn unescape(string: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut encoded = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            '0' => bytes.push(0),
+            '\\' => bytes.push(b'\\'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            },
+            _ => return None,
+        }
+    }
+    return Some(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn sizes(boundary: &Boundary, bytes: &[u8], targets: &[u64], limit: u64, header_size: u64) -> Result<Vec<u64>, BoundaryError> {
+        let directory = TempDir::new();
+        let mut file = directory.file("input", bytes);
+        return boundary.part_sizes(&mut file, bytes.len() as u64, targets, |_| limit, header_size);
+    }
+
+    fn ok(result: Result<Vec<u64>, BoundaryError>) -> Vec<u64> {
+        return result.unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Boundary::from_name("line"), Some(Boundary::Line));
+        assert_eq!(Boundary::from_name("csv"), Some(Boundary::Csv));
+        assert_eq!(Boundary::from_name("jsonl"), Some(Boundary::Jsonl));
+        assert_eq!(Boundary::from_name("zip"), Some(Boundary::Zip));
+        assert_eq!(Boundary::from_name("tar"), Some(Boundary::Tar));
+        assert_eq!(Boundary::from_name("delimiter:;"), Some(Boundary::Delimiter(b";".to_vec())));
+        assert_eq!(Boundary::from_name("delimiter:\\r\\n"), Some(Boundary::Delimiter(b"\r\n".to_vec())));
+        assert_eq!(Boundary::from_name("delimiter:\\x1e"), Some(Boundary::Delimiter(vec![0x1e])));
+        assert_eq!(Boundary::from_name("delimiter:\\0\\t\\\\"), Some(Boundary::Delimiter(b"\0\t\\".to_vec())));
+        assert_eq!(Boundary::from_name("delimiter:"), None);
+        assert_eq!(Boundary::from_name("delimiter:\\q"), None);
+        assert_eq!(Boundary::from_name("delimiter:\\xz1"), None);
+        assert_eq!(Boundary::from_name("delimiter:\\"), None);
+        assert_eq!(Boundary::from_name("lines"), None);
+    }
+
+    #[test]
+    fn lines() {
+        let bytes = b"aaa\nbbb\nccc\n";
+        assert_eq!(ok(sizes(&Boundary::Line, bytes, &[5, 5, 2], 5, 0)), vec![4, 4, 4]);
+        assert_eq!(ok(sizes(&Boundary::Jsonl, bytes, &[5, 5, 2], 5, 0)), vec![4, 4, 4]);
+        // The last part may end without a newline
+        assert_eq!(ok(sizes(&Boundary::Line, b"aaa\nbbb\ncc", &[5, 5], 5, 0)), vec![4, 4, 2]);
+        assert_eq!(ok(sizes(&Boundary::Byte, bytes, &[5, 5, 2], 5, 0)), vec![5, 5, 2]);
+    }
+
+    #[test]
+    fn record_is_too_long() {
+        assert!(matches!(sizes(&Boundary::Line, b"aaaaaaa\nb", &[5, 4], 5, 0), Err(BoundaryError::RecordIsTooLong(0, 5))));
+        assert!(matches!(sizes(&Boundary::Line, b"a\nbbbbbbbbb\n", &[5, 5, 2], 5, 0), Err(BoundaryError::RecordIsTooLong(2, 5))));
+    }
+
+    #[test]
+    fn csv_rows() {
+        // The second newline is inside a quoted field
+        let bytes = b"a,b\n\"x\ny\",z\n";
+        assert_eq!(ok(sizes(&Boundary::Line, bytes, &[8, 4], 8, 0)), vec![7, 5]);
+        assert_eq!(ok(sizes(&Boundary::Csv, bytes, &[8, 4], 8, 0)), vec![4, 8]);
+        // Escaped quotes are two quotes in a row and keep the field quoted
+        assert_eq!(ok(sizes(&Boundary::Csv, b"\"a\"\"\nb\"\nc\n", &[9, 9], 9, 0)), vec![8, 2]);
+    }
+
+    #[test]
+    fn csv_header() {
+        let directory = TempDir::new();
+        let mut file = directory.file("header", b"id,name\n1,x\n");
+        assert_eq!(csv_header_size(&mut file, 12).unwrap(), Some(8));
+        let mut file = directory.file("quoted", b"\"a\nb\",c\n1,2\n");
+        assert_eq!(csv_header_size(&mut file, 12).unwrap(), Some(8));
+        let mut file = directory.file("no-newline", b"id,name");
+        assert_eq!(csv_header_size(&mut file, 7).unwrap(), None);
+
+        // Every part but the first leaves room for the repeated header
+        assert_eq!(ok(sizes(&Boundary::Csv, b"h,h\n1,1\n2,2\n3,3\n", &[8, 8], 8, 4)), vec![8, 4, 4]);
+    }
+
+    #[test]
+    fn delimiters() {
+        let delimiter = Boundary::Delimiter(b"||".to_vec());
+        // The delimiter which is cut by the end of the part is not a boundary
+        assert_eq!(ok(sizes(&delimiter, b"ab||cd||ef", &[7, 3], 7, 0)), vec![4, 6]);
+
+        // A delimiter across the border of two read chunks is still found
+        let mut bytes = vec![b'a'; CHUNK_SIZE + 34465];
+        let border = bytes.len() - 1 - CHUNK_SIZE;
+        bytes[border - 1] = b'|';
+        bytes[border] = b'|';
+        let limit = bytes.len() as u64 - 1;
+        assert_eq!(ok(sizes(&delimiter, &bytes, &[limit, limit], limit, 0)), vec![border as u64 + 1, bytes.len() as u64 - border as u64 - 1]);
+    }
+}
@@ -0,0 +1,750 @@
+// Description of every command and option of splimer.
+// `--help`, shell completions and the man page are all generated from here,
+// so this is the only place where the interface should be documented.
+
+use crate::parser::SIZE_PRESETS;
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arguments: &'static str,
+    pub about: &'static str,
+}
+
+pub struct OptionSpec {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Option<&'static str>,
+    // Commands which accept this option, empty means old flat syntax only
+    pub commands: &'static [&'static str],
+    // First line is a summary, used by completions
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "split",
+        arguments: "(input_filename)",
+        about: "Splits file `input_filename` into `filename.ext_[N].splm` parts",
+    },
+    CommandSpec {
+        name: "merge",
+        arguments: "(input_filename)",
+        about: "Merges `filename.ext_[N].splm` parts back into the original file",
+    },
+    CommandSpec {
+        name: "verify",
+        arguments: "(input_filename)",
+        about: "Checks that parts are complete and match the original file, if it is still there",
+    },
+    CommandSpec {
+        name: "info",
+        arguments: "(input_filename)",
+        about: "Shows what is known about a set of parts",
+    },
+    CommandSpec {
+        name: "list",
+        arguments: "(input_filename)",
+        about: "Lists parts of a set",
+    },
+    CommandSpec {
+        name: "upload",
+        arguments: "(input_filename)",
+        about: "Sends parts and the manifest of a set to a Telegram chat",
+    },
+    CommandSpec {
+        name: "download",
+        arguments: "(input_filename)",
+        about: "Fetches a set back from a Telegram chat by its pinned manifest, ready to merge",
+    },
+    CommandSpec {
+        name: "watch",
+        arguments: "(directory)",
+        about: "Splits every file which is dropped into `directory`, once it stops changing",
+    },
+    CommandSpec {
+        name: "completions",
+        arguments: "(bash|zsh|fish)",
+        about: "Prints shell completion script",
+    },
+    CommandSpec {
+        name: "man",
+        arguments: "",
+        about: "Prints man page",
+    },
+    CommandSpec {
+        name: "help",
+        arguments: "[command]",
+        about: "Shows help message of a command",
+    },
+];
+
+pub const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        short: Some('S'),
+        long: "fragment-size",
+        value: Some("memory-value"),
+        commands: &["split", "watch"],
+        help: "Size of one output fragment; can be float number
+with suffixes `b`, `k`, `m`, `g`, `t` (binary),
+`kB`, `MB`, `GB`, `TB` (decimal) or `KiB`, `MiB`, `GiB`, `TiB`,
+ex. `1m` or `1MiB` is 1048576 bytes, `1MB` is 1000000 bytes;
+a capital `B` makes any of them decimal, `1mB` is 1000000 bytes too,
+and with a small `b` they are binary as before, `1mb` and `1Mb` are 1048576 bytes;
+digits can be grouped as `1_000_000` or `1 000 000`,
+fractions are written with a dot, `1.5k`, a comma separates sizes of a list
+(by default is `1g`, 1073741824 bytes)
+Also can be one of named upload targets:
+{presets}
+A list like `4g,4g,1g` gives every part its own size, the last one is repeated,
+or ends with `rest` for one part with everything that is left",
+    },
+    OptionSpec {
+        short: Some('n'),
+        long: "parts",
+        value: Some("number"),
+        commands: &["split", "watch"],
+        help: "Number of output parts; should be more than 1.
+Makes all output files equal size.
+With `--fragment-size` parts are also kept under its sizes",
+    },
+    OptionSpec {
+        short: None,
+        long: "strategy",
+        value: Some("strategy"),
+        commands: &["split", "watch"],
+        help: "How bytes are put into parts: `fixed` (by default) fills every part up to its size,
+`balanced` spreads them so parts of the same size differ by one byte at most,
+`min-last` puts a last part of less than half the size into the previous one
+if that one stays under its size, otherwise the last two parts are evened out,
+so `-S 100m` of a 210m file gives parts of 100m, 55m and 55m",
+    },
+    OptionSpec {
+        short: None,
+        long: "boundary",
+        value: Some("boundary"),
+        commands: &["split", "watch"],
+        help: "End every part where a record ends, still under its size:
+`line` or `jsonl` after a newline, `csv` after a newline outside quotes,
+`delimiter:<bytes>` after the given bytes, which may be escaped like `\\n`, `\\0` or `\\x1e`,
+`zip` between entries of a zip archive, every part then opens as a zip archive of its own,
+`tar` between members of a tar archive, a member bigger than a part goes on in GNU multi-volume parts",
+    },
+    OptionSpec {
+        short: None,
+        long: "csv-header",
+        value: None,
+        commands: &["split", "watch"],
+        help: "With `--boundary=csv` repeat the header row at the beginning of every part,
+merge leaves the copies out",
+    },
+    OptionSpec {
+        short: Some('N'),
+        long: "part-number",
+        value: Some("number"),
+        commands: &["split"],
+        help: "Sequential number of part to make.
+It allows to make e.g. 4th part
+skipping previous 3, which takes less storage
+than making all at once",
+    },
+    OptionSpec {
+        short: Some('o'),
+        long: "output-directory",
+        value: Some("output_directory"),
+        commands: &["split", "merge", "download"],
+        help: "Output directory
+(by default it is a directory, where input file lies)
+For merge it is where the merged file goes, parts are
+always searched next to `input_filename`.
+For download it is where parts are put",
+    },
+    OptionSpec {
+        short: None,
+        long: "output-file",
+        value: Some("output_file"),
+        commands: &["merge"],
+        help: "Path of the merged file
+(by default it is the original file name from the manifest,
+or `filename_[merged].ext` if there is no manifest)",
+    },
+    OptionSpec {
+        short: None,
+        long: "force",
+        value: None,
+        commands: &["split", "merge"],
+        help: "Overwrite existing files, even parts
+which were made from another file
+(by default split overwrites only its own parts
+and merge doesn't overwrite anything)",
+    },
+    OptionSpec {
+        short: None,
+        long: "no-clobber",
+        value: None,
+        commands: &["split", "merge"],
+        help: "Stop if any output file already exists",
+    },
+    OptionSpec {
+        short: None,
+        long: "backup",
+        value: None,
+        commands: &["split", "merge"],
+        help: "Overwrite existing files, but keep them as `name~`",
+    },
+    OptionSpec {
+        short: None,
+        long: "consume-source",
+        value: None,
+        commands: &["split"],
+        help: "Cut parts from the end of the file and shrink it
+after every part, so only one fragment of free space is needed.
+The file is gone when split is done! An interrupted split
+is continued by running it again",
+    },
+    OptionSpec {
+        short: None,
+        long: "consume-parts",
+        value: None,
+        commands: &["merge"],
+        help: "Remove every part as soon as it is safely in the merged file,
+so only one fragment of free space is needed.
+Parts are gone when merge is done! An interrupted merge
+is continued by running it again",
+    },
+    OptionSpec {
+        short: None,
+        long: "undo",
+        value: None,
+        commands: &["split"],
+        help: "Put parts of an interrupted `--consume-source` split
+back into the file",
+    },
+    OptionSpec {
+        short: None,
+        long: "json",
+        value: None,
+        commands: &["split", "info"],
+        help: "Print output as JSON instead of a table
+(for split only together with `--dry-run`)",
+    },
+    OptionSpec {
+        short: None,
+        long: "output-format",
+        value: Some("format"),
+        commands: &["split", "merge", "upload", "download", "watch"],
+        help: "`text` (by default) or `jsonl`, which prints
+one JSON event per line: `part_started`, `progress`,
+`part_finished`, `part_uploaded`, `warning`, `error` and `done`,
+messages for people go to stderr then",
+    },
+    OptionSpec {
+        short: None,
+        long: "io",
+        value: Some("backend"),
+        commands: &["split", "merge", "watch"],
+        help: "`sync` (by default) reads and writes one buffer at a time,
+`uring` keeps several reads and writes in flight with io_uring instead of copying inside the kernel,
+if splimer is built with `--features io-uring` and the kernel has it",
+    },
+    OptionSpec {
+        short: None,
+        long: "no-zero-copy",
+        value: None,
+        commands: &["split", "merge", "watch"],
+        help: "Copy every byte through the program's own buffer
+(by default Linux copies inside the kernel with reflinks,
+`copy_file_range` or `sendfile` when it can).
+The source is read once more for its SHA-256 after a kernel copy,
+so it saves writes and space, but not reads",
+    },
+    OptionSpec {
+        short: None,
+        long: "fsync",
+        value: Some("when"),
+        commands: &["split", "merge", "watch"],
+        help: "When written files are synced to disk: `never`,
+`per-part` (by default) before the next part is started, or `end` once, after all of them",
+    },
+    OptionSpec {
+        short: None,
+        long: "direct",
+        value: None,
+        commands: &["split", "merge", "watch"],
+        help: "Read and write with `O_DIRECT`, so a big copy doesn't fill the page cache
+(Linux only, file systems without it are used as usual)",
+    },
+    OptionSpec {
+        short: None,
+        long: "buffer-size",
+        value: Some("size"),
+        commands: &["split", "merge", "watch"],
+        help: "Size of the copy buffer, 1m by default, at least 4k",
+    },
+    OptionSpec {
+        short: None,
+        long: "store",
+        value: Some("location"),
+        commands: &["split", "merge", "verify", "info", "list", "upload", "download"],
+        help: "Where parts and the manifest are kept instead of the directory of the set:
+another directory, `s3://bucket/prefix` (credentials from `AWS_*` variables,
+`AWS_ENDPOINT_URL` for MinIO and other compatible stores), `webdav://host/path`,
+`webdavs://host/path` or `sftp://user@host/path` (through `ssh`, `SPLIMER_SSH` overrides it).
+Parts are uploaded and downloaded directly, without local copies",
+    },
+    OptionSpec {
+        short: None,
+        long: "on-part-done",
+        value: Some("command"),
+        commands: &["split"],
+        help: "Shell command which is run for every part when it is written,
+`{path}`, `{index}` and `{total}` in it are replaced with the quoted path of the part,
+its number and the number of all parts, like `--on-part-done 'rclone copy {path} remote:'`.
+Next parts are written while it runs, split stops when it fails",
+    },
+    OptionSpec {
+        short: None,
+        long: "wait-for-hook",
+        value: None,
+        commands: &["split"],
+        help: "Write the next part only when the `--on-part-done` command
+of the previous one succeeds, same as `--max-pending 0`",
+    },
+    OptionSpec {
+        short: None,
+        long: "delete-after-hook",
+        value: None,
+        commands: &["split"],
+        help: "Remove every part when its `--on-part-done` command succeeds,
+so the disk never holds the whole split",
+    },
+    OptionSpec {
+        short: None,
+        long: "max-pending",
+        value: Some("number"),
+        commands: &["split"],
+        help: "How many written parts may wait for their `--on-part-done` commands
+while the next one is written (by default any number)",
+    },
+    OptionSpec {
+        short: None,
+        long: "telegram",
+        value: None,
+        commands: &["upload", "download"],
+        help: "Send parts to a chat through a Telegram bot, or fetch them back.
+The token of the bot is taken from `SPLIMER_TELEGRAM_TOKEN`.
+Sent parts are written into the manifest, so an interrupted upload
+goes on where it stopped; the manifest is sent last and pinned",
+    },
+    OptionSpec {
+        short: None,
+        long: "chat",
+        value: Some("chat"),
+        commands: &["upload", "download"],
+        help: "Id of the chat, like `-1001234567890`, or `@channel`,
+the bot should be allowed to post and pin messages there",
+    },
+    OptionSpec {
+        short: None,
+        long: "telegram-api",
+        value: Some("url"),
+        commands: &["upload", "download"],
+        help: "Base URL of the Bot API (by default `https://api.telegram.org`),
+like a local Bot API server, which takes parts of up to 2000 MB.
+api.telegram.org gives back at most 20 MB, so bigger parts are refused there",
+    },
+    OptionSpec {
+        short: None,
+        long: "manifest-message",
+        value: Some("id"),
+        commands: &["download"],
+        help: "Id of the message with the manifest, which upload tells.
+By default the manifest is the pinned message of the chat,
+this fetches a set whose manifest is not pinned anymore",
+    },
+    OptionSpec {
+        short: None,
+        long: "outbox",
+        value: Some("directory"),
+        commands: &["watch"],
+        help: "Where parts of watched files go (by default `outbox` in the watched directory).
+A set is moved there only when all of its parts are written",
+    },
+    OptionSpec {
+        short: None,
+        long: "state-file",
+        value: Some("file"),
+        commands: &["watch"],
+        help: "Where handled files are remembered (by default `.splimer-watch`
+in the watched directory), so after a restart only new and changed files are split",
+    },
+    OptionSpec {
+        short: None,
+        long: "status-file",
+        value: Some("file"),
+        commands: &["watch"],
+        help: "File which is rewritten with what watch is doing,
+how many files are done, failed or waiting, for monitoring",
+    },
+    OptionSpec {
+        short: None,
+        long: "settle",
+        value: Some("duration"),
+        commands: &["watch"],
+        help: "How long a file should not change before it is split,
+like `30s`, `500ms` or `2m` (by default 5 seconds)",
+    },
+    OptionSpec {
+        short: None,
+        long: "follow",
+        value: None,
+        commands: &["split", "merge"],
+        help: "Keep going while the file is still written, like `tail -f`:
+split writes every part as soon as it is full and finishes when the writer
+closes the file, merge appends parts as they appear and finishes when
+the whole set is there. The manifest is updated after every part",
+    },
+    OptionSpec {
+        short: None,
+        long: "wait",
+        value: None,
+        commands: &["merge"],
+        help: "Same as `--follow`, wait for parts which are not there yet",
+    },
+    OptionSpec {
+        short: None,
+        long: "follow-timeout",
+        value: Some("duration"),
+        commands: &["split", "merge"],
+        help: "How long `--follow` waits when nothing new comes, like `10m`
+(by default 1 minute for split and 10 minutes for merge,
+where a part which grows is something new too)",
+    },
+    OptionSpec {
+        short: None,
+        long: "dry-run",
+        value: None,
+        commands: &["split"],
+        help: "Print which parts would be written, where they start,
+how large they are and how much space they need,
+without writing anything",
+    },
+    OptionSpec {
+        short: Some('s'),
+        long: "split",
+        value: None,
+        commands: &[],
+        help: "Splits file `input_filename`, same as `splimer split`
+(by default is true)",
+    },
+    OptionSpec {
+        short: Some('m'),
+        long: "merge",
+        value: None,
+        commands: &[],
+        help: "Merges files, same as `splimer merge`
+(by default false, ignores -n and -S arguments)",
+    },
+    OptionSpec {
+        short: Some('h'),
+        long: "help",
+        value: None,
+        commands: &["split", "merge", "verify", "info", "list", "upload", "download", "watch"],
+        help: "Show help message",
+    },
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+pub fn find_option(key: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|option| {
+        key.strip_prefix("--") == Some(option.long) ||
+            option.short.is_some_and(|short| key.strip_prefix('-') == Some(short.encode_utf8(&mut [0; 4])))
+    })
+}
+
+fn options_of(command: Option<&str>) -> impl Iterator<Item = &'static OptionSpec> + '_ {
+    OPTIONS.iter().filter(move |option| match command {
+        Some(command) => option.commands.contains(&command),
+        None => true,
+    })
+}
+
+fn option_help(option: &OptionSpec) -> String {
+    let presets = SIZE_PRESETS.iter()
+        .map(|(name, _, description)| format!("{:<20}{}", format!("`{}`", name), description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    option.help.replace("{presets}", &presets)
+}
+
+fn option_usage(option: &OptionSpec) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(short) = option.short {
+        lines.push(match option.value {
+            Some(value) => format!("-{} ({})", short, value),
+            None => format!("-{}", short),
+        });
+    }
+    lines.push(match option.value {
+        Some(value) => format!("--{}=({})", option.long, value),
+        None => format!("--{}", option.long),
+    });
+    lines
+}
+
+fn render_options<'a>(options: impl Iterator<Item = &'a OptionSpec>) -> String {
+    let options: Vec<&OptionSpec> = options.collect();
+    let width = options.iter()
+        .flat_map(|option| option_usage(option))
+        .map(|usage| usage.len())
+        .max()
+        .unwrap_or(0) + 2;
+
+    let mut text = String::new();
+    for option in options {
+        let usage = option_usage(option);
+        let help = option_help(option);
+        for (i, line) in usage.iter().enumerate() {
+            if i + 1 < usage.len() {
+                text += &format!("    {}\n", line);
+            } else {
+                text += &format!("    {:<width$}", line, width = width);
+            }
+        }
+        for (i, line) in help.lines().enumerate() {
+            if i == 0 {
+                text += &format!("{}\n", line);
+            } else {
+                text += &format!("    {:<width$}{}\n", "", line, width = width);
+            }
+        }
+        text += "\n";
+    }
+    text
+}
+
+pub fn help(command: Option<&str>) -> String {
+    if let Some(spec) = command.and_then(|name| COMMANDS.iter().find(|spec| spec.name == name)) {
+        let mut text = format!("\n{}\n\nsplimer {} {}\n", spec.about, spec.name, spec.arguments);
+        let options = render_options(options_of(Some(spec.name)));
+        if !options.is_empty() {
+            text += &format!("\n{}", options);
+        }
+        return text.trim_end().to_string();
+    }
+
+    let mut text = String::from("\nOk, this is how to use this piece of code:\n\nsplimer (command) [options]\n\nCommands:\n");
+    for spec in COMMANDS {
+        text += &format!("    {:<16}{}\n", spec.name, spec.about);
+    }
+    text += "\nRun `splimer help (command)` to see options of a command.\n";
+    text += "\nOld syntax still works, it splits by default:\n\nsplimer (input_filename) [options]\n\n";
+    text += &render_options(OPTIONS.iter());
+    text.trim_end().to_string()
+}
+
+fn command_names() -> Vec<&'static str> {
+    COMMANDS.iter().map(|spec| spec.name).collect()
+}
+
+fn option_flags(command: &str) -> Vec<String> {
+    options_of(Some(command))
+        .flat_map(|option| {
+            let mut flags: Vec<String> = option.short.map(|short| format!("-{}", short)).into_iter().collect();
+            flags.push(format!("--{}", option.long));
+            flags
+        })
+        .collect()
+}
+
+fn summary(option: &OptionSpec) -> &'static str {
+    option.help.lines().next().unwrap_or("")
+}
+
+pub fn completions(shell: &str) -> String {
+    match shell {
+        "bash" => bash_completions(),
+        "zsh" => zsh_completions(),
+        "fish" => fish_completions(),
+        _ => String::new(),
+    }
+}
+
+fn bash_completions() -> String {
+    let mut cases = String::new();
+    for spec in COMMANDS {
+        let words = match spec.name {
+            "completions" => SHELLS.join(" "),
+            "help" => command_names().join(" "),
+            name => option_flags(name).join(" "),
+        };
+        cases += &format!("        {}) opts=\"{}\" ;;\n", spec.name, words);
+    }
+
+    format!(r#"# bash completion for splimer
+_splimer() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local command="" opts=""
+
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=( $(compgen -W "{commands}" -- "$cur") $(compgen -f -- "$cur") )
+        return
+    fi
+
+    command="${{COMP_WORDS[1]}}"
+    case "$command" in
+{cases}        *) opts="" ;;
+    esac
+
+    if [[ "$cur" == -* || "$command" == "completions" || "$command" == "help" ]]; then
+        COMPREPLY=( $(compgen -W "$opts" -- "$cur") )
+    else
+        COMPREPLY=( $(compgen -f -- "$cur") )
+    fi
+}}
+complete -o filenames -F _splimer splimer
+"#, commands = command_names().join(" "), cases = cases)
+}
+
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh_completions() -> String {
+    let commands = COMMANDS.iter()
+        .map(|spec| format!("        '{}:{}'\n", spec.name, zsh_escape(spec.about)))
+        .collect::<String>();
+
+    let mut cases = String::new();
+    for spec in COMMANDS {
+        let arguments = match spec.name {
+            "completions" => format!("'1:shell:({})'", SHELLS.join(" ")),
+            "help" => format!("'1:command:({})'", command_names().join(" ")),
+            "man" => String::new(),
+            name => {
+                let mut arguments = Vec::new();
+                for option in options_of(Some(name)) {
+                    let value = option.value.map(|value| format!(":{}:", value)).unwrap_or_default();
+                    if let Some(short) = option.short {
+                        let suffix = if option.value.is_some() { "+" } else { "" };
+                        arguments.push(format!("'-{}{}[{}]{}'", short, suffix, zsh_escape(summary(option)), value));
+                    }
+                    let suffix = if option.value.is_some() { "=" } else { "" };
+                    arguments.push(format!("'--{}{}[{}]{}'", option.long, suffix, zsh_escape(summary(option)), value));
+                }
+                arguments.push("'1:input file:_files'".to_string());
+                arguments.join(" \\\n                ")
+            }
+        };
+        cases += &format!("        {})\n            _arguments {}\n            ;;\n", spec.name, arguments);
+    }
+
+    format!(r#"#compdef splimer
+
+_splimer() {{
+    local -a commands
+    commands=(
+{commands}    )
+
+    if (( CURRENT == 2 )); then
+        _describe 'command' commands
+        _files
+        return
+    fi
+
+    shift words
+    (( CURRENT-- ))
+    case $words[1] in
+{cases}    esac
+}}
+
+_splimer "$@"
+"#, commands = commands, cases = cases)
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish_completions() -> String {
+    let mut text = String::from("# fish completion for splimer\n");
+    for spec in COMMANDS {
+        text += &format!(
+            "complete -c splimer -n '__fish_use_subcommand' -f -a {} -d '{}'\n",
+            spec.name, fish_escape(spec.about)
+        );
+    }
+    text += &format!(
+        "complete -c splimer -n '__fish_seen_subcommand_from completions' -f -a '{}'\n",
+        SHELLS.join(" ")
+    );
+    text += &format!(
+        "complete -c splimer -n '__fish_seen_subcommand_from help' -f -a '{}'\n",
+        command_names().join(" ")
+    );
+    for option in OPTIONS.iter().filter(|option| !option.commands.is_empty()) {
+        text += &format!("complete -c splimer -n '__fish_seen_subcommand_from {}'", option.commands.join(" "));
+        if let Some(short) = option.short {
+            text += &format!(" -s {}", short);
+        }
+        text += &format!(" -l {}", option.long);
+        if option.value.is_some() {
+            text += " -r";
+        }
+        text += &format!(" -d '{}'\n", fish_escape(summary(option)));
+    }
+    text
+}
+
+fn roff_escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+fn roff_option(option: &OptionSpec) -> String {
+    let mut text = String::from(".TP\n");
+    let value = option.value.map(|value| format!(" \\fI{}\\fR", roff_escape(value))).unwrap_or_default();
+    if let Some(short) = option.short {
+        text += &format!("\\fB\\-{}\\fR{}, ", short, value);
+    }
+    let value = option.value.map(|value| format!("=\\fI{}\\fR", roff_escape(value))).unwrap_or_default();
+    text += &format!("\\fB\\-\\-{}\\fR{}\n", roff_escape(option.long), value);
+    for line in option_help(option).lines() {
+        text += &format!("{}\n.br\n", roff_escape(line));
+    }
+    if !option.commands.is_empty() {
+        text += &format!("Used by: {}\n", option.commands.join(", "));
+    }
+    text
+}
+
+pub fn man_page() -> String {
+    let mut text = format!(
+        ".TH SPLIMER 1 \"\" \"splimer {}\" \"User Commands\"\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    text += ".SH NAME\nsplimer \\- splits files into parts and merges them back\n";
+    text += ".SH SYNOPSIS\n.B splimer\n\\fIcommand\\fR [\\fIoptions\\fR] \\fIinput_filename\\fR\n.br\n";
+    text += ".B splimer\n\\fIinput_filename\\fR [\\fIoptions\\fR]\n";
+    text += ".SH DESCRIPTION\nsplimer splits a file into \\fIfilename.ext_[N].splm\\fR parts \
+of a given size and merges them back into a working file.\n";
+    text += ".SH COMMANDS\n";
+    for spec in COMMANDS {
+        text += &format!(".TP\n\\fB{}\\fR {}\n{}\n", spec.name, roff_escape(spec.arguments), roff_escape(spec.about));
+    }
+    text += ".SH OPTIONS\n";
+    for option in OPTIONS.iter().filter(|option| !option.commands.is_empty()) {
+        text += &roff_option(option);
+    }
+    text += ".SH OLD SYNTAX\nWhen the first argument is not a command, \
+it is treated as \\fIinput_filename\\fR and all options are accepted. \
+These flags choose what to do:\n";
+    for option in OPTIONS.iter().filter(|option| option.commands.is_empty()) {
+        text += &roff_option(option);
+    }
+    text
+}
@@ -0,0 +1,518 @@
+// Bytes are moved between files inside the kernel when it can do that: a reflink shares the blocks
+// of the source (btrfs, XFS), so a copy on the same file system takes no space at all,
+// `copy_file_range` and `sendfile` copy without going through our buffer.
+// Everything else goes through the buffer. `--io=uring` is asked for explicitly, so it goes first,
+// through io_uring instead of the kernel copy, and the kernel copy is tried only when there is no io_uring.
+// Hashes are taken in any case, after a kernel copy the source is read once more for them:
+// it still saves the writes through the buffer, and a reflink saves the space, but not the reads.
+
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+use crate::sha256::Sha256;
+use crate::uring;
+
+// `O_DIRECT` needs buffers, offsets and lengths aligned to the block size, this one fits every usual disk
+pub const DIRECT_ALIGNMENT: usize = 4096;
+
+// Zeroed buffer which starts at `DIRECT_ALIGNMENT`
+pub struct Buffer {
+    pointer: *mut u8,
+    length: usize,
+}
+
+impl Buffer {
+    pub fn new(length: usize) -> Buffer {
+        let length = length.max(1);
+        let layout = Layout::from_size_align(length, DIRECT_ALIGNMENT).unwrap();
+        // SAFETY: `layout` has a non-zero size
+        let pointer = unsafe { alloc::alloc_zeroed(layout) };
+        if pointer.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        return Buffer{ pointer, length };
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `pointer` is a live allocation of `length` initialized bytes
+        return unsafe { std::slice::from_raw_parts(self.pointer, self.length) };
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: `pointer` is a live allocation of `length` initialized bytes, borrowed uniquely
+        return unsafe { std::slice::from_raw_parts_mut(self.pointer, self.length) };
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // SAFETY: `pointer` was allocated with this very layout
+        unsafe { alloc::dealloc(self.pointer, Layout::from_size_align(self.length, DIRECT_ALIGNMENT).unwrap()) };
+    }
+}
+
+// Opens with `O_DIRECT` when it is asked and the file system has it, so big copies don't fill the page cache
+#[cfg(target_os = "linux")]
+pub fn open(options: &OpenOptions, path: &Path, direct: bool) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if direct {
+        let mut direct_options = options.clone();
+        direct_options.custom_flags(libc::O_DIRECT);
+        if let Ok(file) = direct_options.open(path) {
+            return Ok(file);
+        }
+    }
+    return options.open(path);
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open(options: &OpenOptions, path: &Path, _direct: bool) -> io::Result<File> {
+    return options.open(path);
+}
+
+// The unaligned end of a file cannot be read or written with `O_DIRECT`, it goes through the page cache then
+#[cfg(target_os = "linux")]
+pub fn clear_direct(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor is open
+    unsafe {
+        let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_DIRECT) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn clear_direct(_file: &File) -> io::Result<()> {
+    return Ok(());
+}
+
+pub fn is_misaligned(err: &io::Error) -> bool {
+    return cfg!(target_os = "linux") && err.kind() == io::ErrorKind::InvalidInput;
+}
+
+fn read_exact_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    match file.read_exact(buffer) {
+        Err(err) if is_misaligned(&err) => {
+            clear_direct(file)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return file.read_exact(buffer);
+        },
+        result => return result,
+    }
+}
+
+pub fn write_all_at(file: &mut File, offset: u64, buffer: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    match file.write_all(buffer) {
+        Err(err) if is_misaligned(&err) => {
+            clear_direct(file)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return file.write_all(buffer);
+        },
+        result => return result,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IoBackend {
+    // Plain `read` and `write`, one at a time
+    Sync,
+    // Several requests in flight with io_uring
+    Uring,
+}
+
+impl IoBackend {
+    pub fn from_name(name: &str) -> Option<IoBackend> {
+        return match name {
+            "sync" => Some(IoBackend::Sync),
+            "uring" => Some(IoBackend::Uring),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CopyOptions {
+    pub zero_copy: bool,
+    pub io: IoBackend,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CopyMethod {
+    Reflink,
+    CopyFileRange,
+    Sendfile,
+    Uring,
+    Buffered,
+}
+
+impl CopyMethod {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            CopyMethod::Reflink => "reflink",
+            CopyMethod::CopyFileRange => "copy_file_range",
+            CopyMethod::Sendfile => "sendfile",
+            CopyMethod::Uring => "io_uring",
+            CopyMethod::Buffered => "buffered",
+        };
+    }
+}
+
+// Copies `length` bytes of `source` from `source_offset` to `target` at `target_offset`,
+// `target` is left positioned right after them. `progress` gets the number of bytes done so far
+#[allow(clippy::too_many_arguments)]
+pub fn copy_range(
+    source: &mut File,
+    source_offset: u64,
+    target: &mut File,
+    target_offset: u64,
+    length: u64,
+    options: CopyOptions,
+    buffer: &mut [u8],
+    hashes: &mut [&mut Sha256],
+    mut progress: impl FnMut(u64)
+) -> io::Result<CopyMethod> {
+    if options.io == IoBackend::Uring {
+        match uring::copy_range(source, source_offset, target, target_offset, length, buffer.len(), hashes, &mut progress) {
+            Ok(()) => {
+                target.seek(SeekFrom::Start(target_offset + length))?;
+                return Ok(CopyMethod::Uring);
+            },
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => { },
+            Err(err) => return Err(err),
+        }
+    }
+
+    if options.zero_copy {
+        match kernel_copy(source, source_offset, target, target_offset, length) {
+            Ok(method) => {
+                target.seek(SeekFrom::Start(target_offset + length))?;
+                if !hashes.is_empty() {
+                    read_range(source, source_offset, length, buffer, |bytes, done| {
+                        for hash in hashes.iter_mut() {
+                            hash.update(bytes);
+                        }
+                        progress(done);
+                    })?;
+                } else {
+                    progress(length);
+                }
+                return Ok(method);
+            },
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => { },
+            Err(err) => return Err(err),
+        }
+    }
+
+    let mut write_error = None;
+    read_range(source, source_offset, length, buffer, |bytes, done| {
+        for hash in hashes.iter_mut() {
+            hash.update(bytes);
+        }
+        // The closure cannot return an error, so it is kept for later
+        if write_error.is_none() {
+            if let Err(err) = write_all_at(target, target_offset + done - bytes.len() as u64, bytes) {
+                write_error = Some(err);
+            }
+        }
+        progress(done);
+    })?;
+    if let Some(err) = write_error {
+        return Err(err);
+    }
+    return Ok(CopyMethod::Buffered);
+}
+
+// Copies `length` bytes of `source` from `source_offset` into a stream, like an upload
+#[allow(clippy::too_many_arguments)]
+pub fn copy_range_to_stream(
+    source: &mut File,
+    source_offset: u64,
+    target: &mut dyn Write,
+    length: u64,
+    buffer: &mut [u8],
+    hashes: &mut [&mut Sha256],
+    mut progress: impl FnMut(u64)
+) -> io::Result<()> {
+    let mut write_error = None;
+    read_range(source, source_offset, length, buffer, |bytes, done| {
+        for hash in hashes.iter_mut() {
+            hash.update(bytes);
+        }
+        if write_error.is_none() {
+            if let Err(err) = target.write_all(bytes) {
+                write_error = Some(err);
+            }
+        }
+        progress(done);
+    })?;
+    return write_error.map_or(Ok(()), Err);
+}
+
+// Copies `length` bytes of a stream, like a download, to `target` at `target_offset`.
+// The buffer is filled before it is written, so only the end can be unaligned for `O_DIRECT`
+#[allow(clippy::too_many_arguments)]
+pub fn copy_stream_to_range(
+    source: &mut dyn Read,
+    target: &mut File,
+    target_offset: u64,
+    length: u64,
+    buffer: &mut [u8],
+    hashes: &mut [&mut Sha256],
+    mut progress: impl FnMut(u64)
+) -> io::Result<()> {
+    let mut done = 0;
+    while done < length {
+        let how_many = (length - done).min(buffer.len() as u64) as usize;
+        source.read_exact(&mut buffer[..how_many])?;
+        for hash in hashes.iter_mut() {
+            hash.update(&buffer[..how_many]);
+        }
+        write_all_at(target, target_offset + done, &buffer[..how_many])?;
+        done += how_many as u64;
+        progress(done);
+    }
+    return Ok(());
+}
+
+fn read_range(source: &mut File, offset: u64, length: u64, buffer: &mut [u8], mut on_read: impl FnMut(&[u8], u64)) -> io::Result<()> {
+    let mut done = 0;
+    while done < length {
+        let how_many = (length - done).min(buffer.len() as u64) as usize;
+        read_exact_at(source, offset + done, &mut buffer[..how_many])?;
+        done += how_many as u64;
+        on_read(&buffer[..how_many], done);
+    }
+    return Ok(());
+}
+
+// Errors which mean that this way of copying is not available for these files
+#[cfg(target_os = "linux")]
+fn is_unsupported(err: &io::Error) -> bool {
+    return matches!(
+        err.raw_os_error(),
+        Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP | libc::EBADF | libc::EPERM)
+    );
+}
+
+// Files of procfs, sysfs and some FUSE file systems are copied as empty, and a file which is shorter
+// than expected ends early. The buffered copy does it again from the start, it reads the real bytes or tells the real error
+#[cfg(target_os = "linux")]
+fn nothing_copied() -> io::Error {
+    return io::Error::new(io::ErrorKind::Unsupported, "the kernel copies nothing");
+}
+
+#[cfg(target_os = "linux")]
+fn kernel_copy(source: &File, source_offset: u64, target: &File, target_offset: u64, length: u64) -> io::Result<CopyMethod> {
+    use std::os::unix::io::AsRawFd;
+
+    if length == 0 {
+        return Ok(CopyMethod::CopyFileRange);
+    }
+
+    let range = libc::file_clone_range{
+        src_fd: source.as_raw_fd() as i64,
+        src_offset: source_offset,
+        src_length: length,
+        dest_offset: target_offset,
+    };
+    // SAFETY: both descriptors are open and `range` lives through the call
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONERANGE, &range) } == 0 {
+        return Ok(CopyMethod::Reflink);
+    }
+
+    // With `O_DIRECT` the unaligned end is refused, then it goes through the page cache
+    let mut is_direct_cleared = false;
+    let mut done = 0u64;
+    while done < length {
+        let mut source_position = (source_offset + done) as libc::loff_t;
+        let mut target_position = (target_offset + done) as libc::loff_t;
+        // SAFETY: both descriptors are open and the positions live through the call
+        let copied = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                &mut source_position,
+                target.as_raw_fd(),
+                &mut target_position,
+                (length - done) as usize,
+                0
+            )
+        };
+        if copied > 0 {
+            done += copied as u64;
+            continue;
+        }
+        if copied == 0 {
+            return Err(nothing_copied());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if is_misaligned(&err) && !is_direct_cleared {
+            clear_direct(source)?;
+            clear_direct(target)?;
+            is_direct_cleared = true;
+            continue;
+        }
+        if done == 0 && is_unsupported(&err) {
+            break;
+        }
+        return Err(err);
+    }
+    if done == length {
+        return Ok(CopyMethod::CopyFileRange);
+    }
+
+    // `sendfile` writes where the target is positioned
+    let mut target = target;
+    target.seek(SeekFrom::Start(target_offset))?;
+    while done < length {
+        let mut source_position = (source_offset + done) as libc::off_t;
+        // SAFETY: both descriptors are open and the position lives through the call
+        let copied = unsafe {
+            libc::sendfile(target.as_raw_fd(), source.as_raw_fd(), &mut source_position, (length - done) as usize)
+        };
+        if copied > 0 {
+            done += copied as u64;
+            continue;
+        }
+        if copied == 0 {
+            return Err(nothing_copied());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if is_misaligned(&err) && !is_direct_cleared {
+            clear_direct(source)?;
+            clear_direct(target)?;
+            is_direct_cleared = true;
+            continue;
+        }
+        if done == 0 && is_unsupported(&err) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, err));
+        }
+        return Err(err);
+    }
+    return Ok(CopyMethod::Sendfile);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_copy(_source: &File, _source_offset: u64, _target: &File, _target_offset: u64, _length: u64) -> io::Result<CopyMethod> {
+    return Err(io::Error::from(io::ErrorKind::Unsupported));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use std::fs;
+
+    fn hex(bytes: &[u8]) -> String {
+        let mut hash = Sha256::new();
+        hash.update(bytes);
+        return hash.finish_hex();
+    }
+
+    // Copies bytes 1000..51000 of a file to the beginning of another one
+    fn copy(options: CopyOptions, buffer_size: usize) -> CopyMethod {
+        let directory = TempDir::new();
+        let data = testing::data(100_000, 5);
+        let mut source = directory.file("source", &data);
+        let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(directory.join("target")).unwrap();
+        let mut hash = Sha256::new();
+        let mut last_progress = 0;
+        let method = copy_range(&mut source, 1000, &mut target, 0, 50_000, options, &mut Buffer::new(buffer_size), &mut [&mut hash], |done| {
+            assert!(done >= last_progress);
+            last_progress = done;
+        }).unwrap();
+        assert_eq!(last_progress, 50_000);
+        assert_eq!(target.stream_position().unwrap(), 50_000);
+        assert!(fs::read(directory.join("target")).unwrap() == data[1000..51000]);
+        assert_eq!(hash.finish_hex(), hex(&data[1000..51000]));
+        return method;
+    }
+
+    #[test]
+    fn uring_goes_before_the_kernel_copy() {
+        let method = copy(CopyOptions{ zero_copy: true, io: IoBackend::Uring }, 4096);
+        assert_eq!(method == CopyMethod::Uring, uring::is_available());
+    }
+
+    #[test]
+    fn copy_methods() {
+        // A buffer which doesn't divide the length leaves a short read at the end
+        assert_eq!(copy(CopyOptions{ zero_copy: false, io: IoBackend::Sync }, 4096), CopyMethod::Buffered);
+        assert_eq!(copy(CopyOptions{ zero_copy: false, io: IoBackend::Sync }, 3000), CopyMethod::Buffered);
+        let method = copy(CopyOptions{ zero_copy: true, io: IoBackend::Sync }, 4096);
+        if cfg!(target_os = "linux") {
+            assert!(matches!(method, CopyMethod::Reflink | CopyMethod::CopyFileRange | CopyMethod::Sendfile), "{:?}", method);
+        }
+    }
+
+    // procfs files have no size, depending on the kernel `copy_file_range` copies nothing of them
+    // or refuses them, either way they are copied whole
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn files_which_the_kernel_copies_as_empty() {
+        let directory = TempDir::new();
+        let data = fs::read("/proc/self/cmdline").unwrap();
+        let mut source = File::open("/proc/self/cmdline").unwrap();
+        let mut target = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(directory.join("target")).unwrap();
+        let options = CopyOptions{ zero_copy: true, io: IoBackend::Sync };
+        let mut hash = Sha256::new();
+        copy_range(&mut source, 0, &mut target, 0, data.len() as u64, options, &mut Buffer::new(4096), &mut [&mut hash], |_| { }).unwrap();
+        assert!(fs::read(directory.join("target")).unwrap() == data);
+        assert_eq!(hash.finish_hex(), hex(&data));
+    }
+
+    #[test]
+    fn source_which_is_too_short() {
+        let directory = TempDir::new();
+        let mut source = directory.file("source", &testing::data(1000, 1));
+        let mut target = OpenOptions::new().write(true).create(true).truncate(true).open(directory.join("target")).unwrap();
+        for zero_copy in [false, true] {
+            let options = CopyOptions{ zero_copy, io: IoBackend::Sync };
+            let err = copy_range(&mut source, 500, &mut target, 0, 1000, options, &mut Buffer::new(4096), &mut [], |_| { }).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let directory = TempDir::new();
+        let data = testing::data(10_000, 2);
+        let mut source = directory.file("source", &data);
+        let mut uploaded = Vec::new();
+        let mut hash = Sha256::new();
+        copy_range_to_stream(&mut source, 100, &mut uploaded, 9000, &mut Buffer::new(4096), &mut [&mut hash], |_| { }).unwrap();
+        assert!(uploaded == data[100..9100]);
+        assert_eq!(hash.finish_hex(), hex(&data[100..9100]));
+
+        let mut target = OpenOptions::new().write(true).create(true).truncate(true).open(directory.join("target")).unwrap();
+        let mut last_progress = 0;
+        copy_stream_to_range(&mut uploaded.as_slice(), &mut target, 50, 9000, &mut Buffer::new(4096), &mut [], |done| last_progress = done).unwrap();
+        assert_eq!(last_progress, 9000);
+        let written = fs::read(directory.join("target")).unwrap();
+        assert!(written[..50].iter().all(|&byte| byte == 0) && written[50..] == data[100..9100]);
+        // A download which ends early is not a whole part
+        let err = copy_stream_to_range(&mut &data[..10], &mut target, 0, 100, &mut Buffer::new(4096), &mut [], |_| { }).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,198 @@
+// Hooks which run after every part of split is written, like an upload of the part somewhere else.
+// `--on-part-done` is a shell command, `{path}`, `{index}` and `{total}` in it are replaced with
+// quoted values, which are also given in `SPLIMER_PART_PATH`, `SPLIMER_PART_INDEX` and `SPLIMER_PART_TOTAL`.
+// Commands run while next parts are written, at most `--max-pending` of them at once,
+// or one by one with `--wait-for-hook`. Split stops when a command fails.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+pub struct FinishedPart {
+    // Where the part is, a URL for remote stores
+    pub path: PathBuf,
+    // Name of the part in the store
+    pub name: OsString,
+    pub index: usize,
+    // 0 with `split --follow`, where the number of parts is not known until the end
+    pub total: usize,
+}
+
+// Called after every part, split stops when it returns an error
+pub type PartCallback = Box<dyn FnMut(&FinishedPart) -> io::Result<()>>;
+
+pub struct PartHook {
+    command: Option<String>,
+    callback: Option<PartCallback>,
+    // How many commands may run while the next part is written, `None` for any number
+    max_pending: Option<usize>,
+    // Parts whose commands are not done yet, the oldest first
+    running: VecDeque<(FinishedPart, Child)>,
+    // Output of commands goes to stderr, when stdout is for events
+    quiet_stdout: bool,
+}
+
+pub struct HookError {
+    pub part: FinishedPart,
+    pub reason: String,
+}
+
+impl PartHook {
+    pub fn new(command: Option<String>, max_pending: Option<usize>, quiet_stdout: bool) -> PartHook {
+        return PartHook{ command, callback: None, max_pending, running: VecDeque::new(), quiet_stdout };
+    }
+
+    pub fn set_callback(&mut self, callback: PartCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub fn is_set(&self) -> bool {
+        return self.command.is_some() || self.callback.is_some();
+    }
+
+    // Runs hooks of a part which is written, parts whose hooks are done are returned
+    pub fn start(&mut self, part: FinishedPart) -> Result<Vec<FinishedPart>, HookError> {
+        if let Some(callback) = &mut self.callback {
+            if let Err(err) = callback(&part) {
+                return Err(HookError{ part, reason: err.to_string() });
+            }
+        }
+        let Some(command) = &self.command else {
+            return Ok(vec![part]);
+        };
+
+        let mut process = shell(&expand(command, &part));
+        process
+            .env("SPLIMER_PART_PATH", &part.path)
+            .env("SPLIMER_PART_INDEX", part.index.to_string())
+            .env("SPLIMER_PART_TOTAL", part.total.to_string())
+            .stdin(Stdio::null());
+        if self.quiet_stdout {
+            process.stdout(io::stderr());
+        }
+        let child = match process.spawn() {
+            Ok(child) => child,
+            Err(err) => return Err(HookError{ part, reason: format!("command cannot be started, {}", err) }),
+        };
+        self.running.push_back((part, child));
+
+        let mut done = Vec::new();
+        while self.running.len() > self.max_pending.unwrap_or(usize::MAX) {
+            done.push(self.wait_oldest()?);
+        }
+        // Commands which are already done don't count as pending
+        while let Some((_, child)) = self.running.front_mut() {
+            match child.try_wait() {
+                Ok(Some(_)) => done.push(self.wait_oldest()?),
+                _ => break,
+            }
+        }
+        return Ok(done);
+    }
+
+    // Waits for all commands, parts whose hooks are done are returned
+    pub fn finish(&mut self) -> Result<Vec<FinishedPart>, HookError> {
+        let mut done = Vec::new();
+        while !self.running.is_empty() {
+            done.push(self.wait_oldest()?);
+        }
+        return Ok(done);
+    }
+
+    fn wait_oldest(&mut self) -> Result<FinishedPart, HookError> {
+        let (part, mut child) = self.running.pop_front().unwrap();
+        return match child.wait() {
+            Ok(status) if status.success() => Ok(part),
+            Ok(status) => Err(HookError{ part, reason: describe(status) }),
+            Err(err) => Err(HookError{ part, reason: err.to_string() }),
+        };
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    return match status.code() {
+        Some(code) => format!("command exits with {}", code),
+        None => "command is killed".to_string(),
+    };
+}
+
+fn expand(command: &str, part: &FinishedPart) -> String {
+    return command
+        .replace("{path}", &quote(&part.path.to_string_lossy()))
+        .replace("{index}", &part.index.to_string())
+        .replace("{total}", &part.total.to_string());
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("sh");
+    process.arg("-c").arg(command);
+    return process;
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("cmd");
+    process.arg("/C").arg(command);
+    return process;
+}
+
+// `it's` -> `'it'\''s'`, so the shell takes it as one word
+#[cfg(unix)]
+fn quote(text: &str) -> String {
+    return format!("'{}'", text.replace('\'', "'\\''"));
+}
+
+#[cfg(not(unix))]
+fn quote(text: &str) -> String {
+    return format!("\"{}\"", text);
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn part(directory: &TempDir, index: usize) -> FinishedPart {
+        let name = format!("it's [{}]", index);
+        return FinishedPart{ path: directory.join(&name), name: OsString::from(name), index, total: 3 };
+    }
+
+    fn indices(parts: Vec<FinishedPart>) -> Vec<usize> {
+        return parts.iter().map(|part| part.index).collect();
+    }
+
+    #[test]
+    fn max_pending_waits_for_the_oldest_command() {
+        let directory = TempDir::new();
+        let mut hook = PartHook::new(Some("sleep 0.1; touch {path}".to_string()), Some(1), false);
+        hook.start(part(&directory, 1)).ok().unwrap();
+        // The second command is one too many, so the first one is waited for
+        let mut done = indices(hook.start(part(&directory, 2)).ok().unwrap());
+        assert_eq!(done.first(), Some(&1));
+        assert!(directory.join("it's [1]").exists());
+        done.extend(indices(hook.finish().ok().unwrap()));
+        assert_eq!(done, vec![1, 2]);
+        assert!(directory.join("it's [2]").exists());
+    }
+
+    #[test]
+    fn no_pending_commands_wait_for_every_part() {
+        let directory = TempDir::new();
+        let mut hook = PartHook::new(Some("touch \"$SPLIMER_PART_PATH\"".to_string()), Some(0), false);
+        assert_eq!(indices(hook.start(part(&directory, 1)).ok().unwrap()), vec![1]);
+        assert!(directory.join("it's [1]").exists());
+    }
+
+    #[test]
+    fn failed_command_is_reported_with_its_part() {
+        let directory = TempDir::new();
+        let mut hook = PartHook::new(Some("sleep 0.1; exit {index}".to_string()), None, false);
+        hook.start(part(&directory, 3)).ok().unwrap();
+        let err = hook.finish().err().unwrap();
+        assert_eq!(err.part.index, 3);
+        assert_eq!(err.reason, "command exits with 3");
+    }
+}
@@ -0,0 +1,203 @@
+// Journal of a destructive run is written next to parts as `filename.ext_[journal].splm`,
+// so a run which was interrupted can be finished or undone. It starts with a header,
+// then one line is appended and synced for every finished step:
+//
+//     splimer-journal=1
+//     operation=consume-source
+//     target=game.zip
+//     part=3 2097152 904 ...
+//     undone=3
+//
+// `part=` is a part which is safely written and whose bytes may be dropped from the other side,
+// `undone=` is a part which was put back by `--undo`, `target=` is where a merge writes to.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{ManifestError, PartRecord};
+use crate::output::{self, ClobberPolicy};
+use crate::paths;
+
+const JOURNAL_VERSION: u32 = 1;
+
+pub struct Journal {
+    pub path: PathBuf,
+    pub operation: String,
+    pub target: Option<PathBuf>,
+    pub parts: Vec<PartRecord>,
+    pub undone: Vec<usize>,
+}
+
+impl Journal {
+    pub fn create(path: &Path, operation: &str, target: Option<&Path>) -> io::Result<Journal> {
+        let mut header = format!("splimer-journal={}\noperation={}\n", JOURNAL_VERSION, operation);
+        if let Some(target) = target {
+            header += &format!("target={}\n", paths::encode(target.as_os_str()));
+        }
+        output::write_file(path, header.as_bytes(), ClobberPolicy::Force)?;
+        File::open(path)?.sync_all()?;
+        output::sync_directory(path)?;
+        return Ok(Journal{
+            path: path.to_path_buf(),
+            operation: operation.to_string(),
+            target: target.map(Path::to_path_buf),
+            parts: Vec::new(),
+            undone: Vec::new(),
+        });
+    }
+
+    pub fn read(path: &Path) -> Result<Journal, ManifestError> {
+        let text = fs::read_to_string(path).map_err(ManifestError::Io)?;
+        let mut version = None;
+        let mut journal = Journal{
+            path: path.to_path_buf(),
+            operation: String::new(),
+            target: None,
+            parts: Vec::new(),
+            undone: Vec::new(),
+        };
+
+        // The last line may be cut by a crash, it was not synced then and means nothing
+        let complete_lines = &text[..text.rfind('\n').map(|end| end + 1).unwrap_or(0)];
+        for (number, line) in complete_lines.lines().enumerate() {
+            let bad_line = || ManifestError::BadLine(number + 1, line.to_string());
+            let (key, value) = line.split_once('=').ok_or_else(bad_line)?;
+            match key {
+                "splimer-journal" => version = Some(value.to_string()),
+                "operation" => journal.operation = value.to_string(),
+                "target" => journal.target = Some(PathBuf::from(paths::decode(value).ok_or_else(bad_line)?)),
+                "part" => {
+                    let record = PartRecord::parse(value).ok_or_else(bad_line)?;
+                    journal.undone.retain(|&index| index != record.index);
+                    journal.parts.push(record);
+                },
+                "undone" => journal.undone.push(value.parse().map_err(|_| bad_line())?),
+                _ => return Err(bad_line()),
+            }
+        }
+
+        match version {
+            Some(version) if version == JOURNAL_VERSION.to_string() => { },
+            Some(version) => return Err(ManifestError::UnsupportedVersion(version)),
+            None => return Err(ManifestError::MissingKey("splimer-journal")),
+        }
+        return Ok(journal);
+    }
+
+    // Parts which are done and not put back
+    pub fn done_part(&self, index: usize) -> Option<&PartRecord> {
+        if self.undone.contains(&index) {
+            return None;
+        }
+        return self.parts.iter().rev().find(|part| part.index == index);
+    }
+
+    // End of the last done part, when parts are done from the first one
+    pub fn done_size(&self) -> u64 {
+        return self.parts.iter()
+            .filter(|part| !self.undone.contains(&part.index))
+            .map(|part| part.offset + part.size)
+            .max()
+            .unwrap_or(0);
+    }
+
+    pub fn append_part(&mut self, record: &PartRecord) -> io::Result<()> {
+        self.append(&format!("part={}", record))?;
+        self.undone.retain(|&index| index != record.index);
+        self.parts.push(record.clone());
+        return Ok(());
+    }
+
+    pub fn append_undone(&mut self, index: usize) -> io::Result<()> {
+        self.append(&format!("undone={}", index))?;
+        self.undone.push(index);
+        return Ok(());
+    }
+
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)?;
+        return output::sync_directory(&self.path);
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        return file.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn record(index: usize, offset: u64, size: u64) -> PartRecord {
+        return PartRecord{ index, offset, size, sha256: Some(format!("{:064x}", index)) };
+    }
+
+    #[test]
+    fn parts_and_undone_parts() {
+        let directory = TempDir::new();
+        let path = directory.join("a.bin_[journal].splm");
+        let mut journal = Journal::create(&path, "consume-source", None).unwrap();
+        journal.append_part(&record(3, 200, 50)).unwrap();
+        journal.append_part(&record(2, 100, 100)).unwrap();
+        journal.append_undone(3).unwrap();
+
+        let read = Journal::read(&path).unwrap();
+        assert_eq!(read.operation, "consume-source");
+        assert_eq!(read.target, None);
+        assert_eq!(read.parts.len(), 2);
+        assert_eq!(read.undone, vec![3]);
+        assert!(read.done_part(3).is_none());
+        assert_eq!(read.done_part(2).map(|part| part.size), Some(100));
+        assert_eq!(read.done_size(), 200);
+
+        // A part which is done again after it was put back counts once more
+        journal.append_part(&record(3, 200, 50)).unwrap();
+        let read = Journal::read(&path).unwrap();
+        assert!(read.undone.is_empty());
+        assert_eq!(read.done_size(), 250);
+
+        read.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn merge_target() {
+        let directory = TempDir::new();
+        let path = directory.join("a.bin_[journal].splm");
+        let target = directory.join("out\nput.bin");
+        let mut journal = Journal::create(&path, "consume-parts", Some(&target)).unwrap();
+        journal.append_part(&record(1, 0, 10)).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().contains("out%0Aput.bin"));
+        let read = Journal::read(&path).unwrap();
+        assert_eq!(read.operation, "consume-parts");
+        assert_eq!(read.target, Some(target));
+        assert_eq!(read.done_size(), 10);
+    }
+
+    #[test]
+    fn cut_and_broken_journals() {
+        let directory = TempDir::new();
+        let path = directory.join("journal");
+        let hash = format!("{:064x}", 1);
+
+        // The last line was not synced when the run stopped
+        fs::write(&path, format!("splimer-journal=1\noperation=consume-source\npart=1 0 10 {}\npart=2 10 1", hash)).unwrap();
+        let journal = Journal::read(&path).unwrap();
+        assert_eq!(journal.parts.len(), 1);
+        assert_eq!(journal.done_size(), 10);
+
+        fs::write(&path, "splimer-journal=1\nsomething=else\n").unwrap();
+        assert!(matches!(Journal::read(&path), Err(ManifestError::BadLine(2, _))));
+        fs::write(&path, "splimer-journal=1\nundone=x\n").unwrap();
+        assert!(matches!(Journal::read(&path), Err(ManifestError::BadLine(2, _))));
+        fs::write(&path, "splimer-journal=7\n").unwrap();
+        assert!(matches!(Journal::read(&path), Err(ManifestError::UnsupportedVersion(_))));
+        fs::write(&path, "operation=consume-source\n").unwrap();
+        assert!(matches!(Journal::read(&path), Err(ManifestError::MissingKey("splimer-journal"))));
+    }
+}
@@ -0,0 +1,259 @@
+// Just enough JSON to print machine-readable output and to read answers of web APIs, without dependencies
+
+pub fn string(value: &str) -> String {
+    let mut text = String::with_capacity(value.len() + 2);
+    text.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => text += "\\\"",
+            '\\' => text += "\\\\",
+            '\n' => text += "\\n",
+            '\r' => text += "\\r",
+            '\t' => text += "\\t",
+            c if (c as u32) < 0x20 => text += &format!("\\u{:04x}", c as u32),
+            c => text.push(c),
+        }
+    }
+    text.push('"');
+    return text;
+}
+
+pub fn optional_string(value: Option<&str>) -> String {
+    return match value {
+        Some(value) => string(value),
+        None => "null".to_string(),
+    };
+}
+
+// Builds `{"key": value, ...}` from already encoded values
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields.iter()
+        .map(|(key, value)| format!("{}: {}", string(key), value))
+        .collect();
+    return format!("{{{}}}", fields.join(", "));
+}
+
+pub fn array(values: &[String]) -> String {
+    return format!("[{}]", values.join(", "));
+}
+
+// Parsed JSON, numbers are kept as text so big integers like Telegram chat ids stay exact
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let Value::Object(fields) = self else {
+            return None;
+        };
+        return fields.iter().find(|(name, _)| name == key).map(|(_, value)| value);
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        let Value::String(text) = self else {
+            return None;
+        };
+        return Some(text);
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        let Value::Number(text) = self else {
+            return None;
+        };
+        return text.parse().ok();
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        let Value::Bool(value) = self else {
+            return None;
+        };
+        return Some(*value);
+    }
+}
+
+pub fn parse(text: &str) -> Option<Value> {
+    let mut parser = Parser{ bytes: text.as_bytes(), position: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position != parser.bytes.len() {
+        return None;
+    }
+    return Some(value);
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(u8::is_ascii_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        return Some(byte);
+    }
+
+    fn expect(&mut self, text: &str) -> Option<()> {
+        if !self.bytes[self.position..].starts_with(text.as_bytes()) {
+            return None;
+        }
+        self.position += text.len();
+        return Some(());
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        return match *self.bytes.get(self.position)? {
+            b'n' => self.expect("null").map(|_| Value::Null),
+            b't' => self.expect("true").map(|_| Value::Bool(true)),
+            b'f' => self.expect("false").map(|_| Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'[' => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.position) == Some(&b']') {
+                    self.position += 1;
+                    return Some(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b']' => return Some(Value::Array(values)),
+                        _ => return None,
+                    }
+                }
+            },
+            b'{' => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.position) == Some(&b'}') {
+                    self.position += 1;
+                    return Some(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.next()? != b':' {
+                        return None;
+                    }
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b'}' => return Some(Value::Object(fields)),
+                        _ => return None,
+                    }
+                }
+            },
+            b'-' | b'0'..=b'9' => {
+                let start = self.position;
+                while self.bytes.get(self.position).is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.position += 1;
+                }
+                let number = std::str::from_utf8(&self.bytes[start..self.position]).ok()?;
+                number.parse::<f64>().ok()?;
+                Some(Value::Number(number.to_string()))
+            },
+            _ => None,
+        };
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.next()? != b'"' {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        loop {
+            match self.next()? {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let c = match self.next()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex_code()?;
+                            // Characters outside the basic plane come as a surrogate pair
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect("\\u")?;
+                                let low = self.hex_code()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + low.checked_sub(0xDC00)?;
+                            }
+                            char::from_u32(code)?
+                        },
+                        _ => return None,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                byte => bytes.push(byte),
+            }
+        }
+    }
+
+    fn hex_code(&mut self) -> Option<u32> {
+        let hex = std::str::from_utf8(self.bytes.get(self.position..self.position + 4)?).ok()?;
+        self.position += 4;
+        return u32::from_str_radix(hex, 16).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_api_answers() {
+        let answer = parse(r#" {"ok": true, "result": {"message_id": 42, "chat": {"id": -1001234567890123},
+            "document": {"file_id": "BQACA\/x", "file_name": "a \"b\"\n😀.splm"}, "date": 1.5e9, "tags": [null, false, []]}} "#).unwrap();
+        assert_eq!(answer.get("ok").and_then(Value::as_bool), Some(true));
+        let result = answer.get("result").unwrap();
+        assert_eq!(result.get("message_id").and_then(Value::as_i64), Some(42));
+        // Big ids stay exact
+        assert_eq!(result.get("chat").and_then(|chat| chat.get("id")), Some(&Value::Number("-1001234567890123".to_string())));
+        let document = result.get("document").unwrap();
+        assert_eq!(document.get("file_id").and_then(Value::as_str), Some("BQACA/x"));
+        assert_eq!(document.get("file_name").and_then(Value::as_str), Some("a \"b\"\n😀.splm"));
+        assert_eq!(result.get("tags"), Some(&Value::Array(vec![Value::Null, Value::Bool(false), Value::Array(Vec::new())])));
+        assert_eq!(result.get("date").and_then(Value::as_i64), None);
+        assert_eq!(parse("{}"), Some(Value::Object(Vec::new())));
+    }
+
+    #[test]
+    fn broken_json() {
+        for text in ["", "{", r#"{"a" 1}"#, r#"{"a": 1,}"#, "[1 2]", "tru", r#""\x""#, r#""\ud83d""#, "1-", "{} {}", "<html>"] {
+            assert_eq!(parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn written_json() {
+        assert_eq!(string("a\"b\\c\n\u{1}é"), r#""a\"b\\c\n\u0001é""#);
+        assert_eq!(object(&[("name", string("x")), ("size", 5.to_string()), ("hash", optional_string(None))]), r#"{"name": "x", "size": 5, "hash": null}"#);
+        assert_eq!(array(&[]), "[]");
+        let written = object(&[("message", string("it's \"quoted\"\t"))]);
+        assert_eq!(parse(&written).unwrap().get("message").and_then(Value::as_str), Some("it's \"quoted\"\t"));
+    }
+}
@@ -0,0 +1,34 @@
+#![allow(clippy::needless_return)]
+
+// The command line program is a thin layer over this library. A program which embeds splimer
+// builds `ProgramInput` with `ProgramInput::parse`, opens a store with `store::open`,
+// gives `Splimer::on_part_done` its own work for every part and runs the command
+
+mod boundary;
+pub mod cli;
+mod copy;
+pub mod hook;
+mod http;
+mod journal;
+mod json;
+mod manifest;
+mod notify;
+mod output;
+pub mod parser;
+mod paths;
+mod plan;
+pub mod report;
+mod s3;
+mod sftp;
+mod sha256;
+pub mod splimer;
+pub mod store;
+mod tar;
+mod telegram;
+#[cfg(test)]
+mod testing;
+mod uring;
+pub mod watch;
+mod webdav;
+mod xml;
+mod zip;
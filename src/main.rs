@@ -1,14 +1,19 @@
+#![allow(clippy::needless_return)]
+
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
+use std::process;
 
-mod parser;
-use parser::{ParseResult, ProgramInput};
-mod splimer;
-use splimer::Splimer;
+use splimer::cli;
+use splimer::parser::{Command, ParseResult, ProgramInput};
+use splimer::report::Reporter;
+use splimer::splimer::Splimer;
+use splimer::store::{self, StoreLocation};
+use splimer::watch;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<OsString> = env::args_os().collect();
 
     let program_input = ProgramInput::parse(&args);
 
@@ -18,14 +23,34 @@ fn main() {
             eprintln!("There is no input filename in arguments! \n\nUse `-h` flag to know about my arguments");
             return;
         },
-        ParseResult::MemoryValueCannotBeParsed(string) => {
-            eprintln!("Fragment size \"{}\" cannot be parsed as memory value \n\nUse `-h` flag to know about my arguments", string);
+        ParseResult::MemoryValueCannotBeParsed(string, err) => {
+            eprintln!("Fragment size \"{}\" cannot be parsed as memory value or size preset: {}", string, err);
+            if let Some(position) = err.position() {
+                eprintln!("    {}\n    {}^", string, " ".repeat(position));
+            }
+            eprintln!("\nUse `-h` flag to know about my arguments");
             return;
         },
         ParseResult::FragmentSizeIsToSmall(n) => {
             eprintln!("Fragment size should be at least {} bytes \n\nUse `-h` flag to know about my arguments", n);
             return;
         },
+        ParseResult::RestShouldBeLast(sizes) => {
+            eprintln!("In fragment sizes \"{}\" `rest` can only follow other sizes at the end \n\nUse `-h` flag to know about my arguments", sizes);
+            return;
+        },
+        ParseResult::CommaIsNotDecimal(sizes) => {
+            eprintln!("In fragment sizes \"{}\" a comma separates sizes, write fractions with a dot, like `1.5k` \n\nUse `-h` flag to know about my arguments", sizes);
+            return;
+        },
+        ParseResult::UnknownStrategy(strategy) => {
+            eprintln!("Split strategy \"{}\" is not supported, choose one of: fixed, balanced, min-last", strategy);
+            return;
+        },
+        ParseResult::UnknownBoundary(boundary) => {
+            eprintln!("Boundary \"{}\" is not supported, choose one of: line, csv, jsonl, delimiter:<bytes>, zip, tar", boundary);
+            return;
+        },
         ParseResult::NumberOfPartsCannotBeParsed(n) => {
             eprintln!("Number of parts \"{}\" cannot be parsed \n\nUse `-h` flag to know about my arguments", n);
             return;
@@ -42,65 +67,125 @@ fn main() {
             eprintln!("For argument `{}` value is empty \n\nUse `-h` flag to know about my arguments", string);
             return;
         },
-        ParseResult::Help => {
-            println!(
-                "
-Ok, this is how to use this piece of code:
-
-splimer
-    (input_filename)                Input file name
-
-    -S (memory-value)
-    --fragment-size=(memory-value)  Size of one output fragment; can be float number
-                                    with suffixes `b`, `m`, `k`, `g`,
-                                    ex. `1m` or `1mb` is 1048576 bytes, 
-                                    (by default is `1g`, 1073741824 bytes)
-
-    -n (number)
-    --parts=(number)                Number of output parts; should be more than 1.
-                                    Makes all output files equal size.
-                                    If `--fragment-size` is provided, `--parts` will be ignored
-
-    -N (number)
-    --part-number=(number)          Sequential number of part to make. 
-                                    It allows to make e.g. 4th part
-                                    skipping previous 3, which takes less storage
-                                    than making all at once
-
-    -s
-    --split                         Splits file `input_filename`
-                                    If file has `filename.ext` pattern there will be created
-                                    `filename_[N].splm` files in `output_directory`
-                                    (by default is true)
-
-    -m
-    --merge                         Merges files. For `input_filename` having `filename.ext` pattern
-                                    program will search `filename_[N].splm` files in directory
-                                    of `input_filename` and will try to merge them into `filename_[merged].ext`
-                                    (by default false, ignores -n and -S arguments)
-
-    -o (output_directory)
-    --output-directory=(output_directory)   Output directory
-                                            (by default it is a directory, where input file lies)
-
-    -h 
-    --help                                  Show help message"
-            );
+        ParseResult::OptionIsNotAllowed(option, command) => {
+            eprintln!("Argument `{}` cannot be used with `{}` command \n\nUse `splimer help {}` to know about its arguments", option, command.name(), command.name());
+            return;
+        },
+        ParseResult::UnknownShell(shell) => {
+            eprintln!("Shell \"{}\" is not supported, choose one of: {}", shell, cli::SHELLS.join(", "));
+            return;
+        },
+        ParseResult::UnknownOutputFormat(format) => {
+            eprintln!("Output format \"{}\" is not supported, choose one of: text, jsonl", format);
+            return;
+        },
+        ParseResult::UnknownIoBackend(io) => {
+            eprintln!("I/O backend \"{}\" is not supported, choose one of: sync, uring", io);
+            return;
+        },
+        ParseResult::UnknownFsyncPolicy(fsync) => {
+            eprintln!("Fsync policy \"{}\" is not supported, choose one of: never, per-part, end", fsync);
+            return;
+        },
+        ParseResult::BufferSizeCannotBeParsed(string, err) => {
+            eprintln!("Buffer size \"{}\" cannot be parsed as memory value: {}", string, err);
+            if let Some(position) = err.position() {
+                eprintln!("    {}\n    {}^", string, " ".repeat(position));
+            }
+            eprintln!("\nUse `-h` flag to know about my arguments");
+            return;
+        },
+        ParseResult::BufferSizeIsTooSmall(n) => {
+            eprintln!("Buffer size should be at least {} bytes \n\nUse `-h` flag to know about my arguments", n);
+            return;
+        },
+        ParseResult::UnknownStore(store) => {
+            eprintln!("Store \"{}\" is not supported, use a directory, s3://bucket/prefix, webdav://host/path, webdavs://host/path or sftp://user@host/path", store);
+            return;
+        },
+        ParseResult::MaxPendingCannotBeParsed(n) => {
+            eprintln!("Number of pending parts \"{}\" cannot be parsed \n\nUse `-h` flag to know about my arguments", n);
+            return;
+        },
+        ParseResult::MessageIdCannotBeParsed(id) => {
+            eprintln!("Message id \"{}\" cannot be parsed, it is the number which upload tells \n\nUse `-h` flag to know about my arguments", id);
+            return;
+        },
+        ParseResult::DurationCannotBeParsed(duration) => {
+            eprintln!("Duration \"{}\" cannot be parsed, use seconds or a number with `ms`, `s`, `m` or `h` like `30s` \n\nUse `-h` flag to know about my arguments", duration);
+            return;
+        },
+        ParseResult::UnknownCommand(command) => {
+            eprintln!("There is no command \"{}\" \n\nUse `-h` flag to know about my arguments", command);
+            return;
+        },
+        ParseResult::Help(command) => {
+            println!("{}", cli::help(command.as_deref()));
+            return;
+        },
+        ParseResult::Completions(shell) => {
+            print!("{}", cli::completions(&shell));
+            return;
+        },
+        ParseResult::Man => {
+            print!("{}", cli::man_page());
             return;
         },
         _ => { panic!("Unexpected parse result") }
     }
 
     let ParseResult::Success(program_input) = program_input else { panic!(); };
-    if let Some(dir) = &program_input.output_directory {
-        fs::create_dir_all(Path::new(dir)).unwrap();
+    // Watch makes a store for every file it splits
+    if program_input.command == Command::Watch {
+        if !watch::watch(program_input) {
+            process::exit(1);
+        }
+        return;
+    }
+    // Dry run doesn't touch the disk, not even to make the output directory
+    if let Some(dir) = program_input.output_directory.as_ref().filter(|_| !program_input.dry_run) {
+        fs::create_dir_all(dir).unwrap();
     }
 
-    let mut splimer = Splimer::new(program_input);
+    let location = Splimer::store_location(&program_input);
+    if let StoreLocation::Local(dir) = &location {
+        if program_input.store.is_some() && matches!(program_input.command, Command::Split | Command::Download) && !program_input.dry_run {
+            fs::create_dir_all(dir).unwrap();
+        }
+    }
+    let store = match store::open(&location, program_input.fsync, program_input.direct) {
+        Ok(store) => store,
+        Err(err) => {
+            Reporter::new(program_input.output_format).error(&format!("Store {} cannot be used: {}", location, err));
+            process::exit(1);
+        },
+    };
+    let mut splimer = Splimer::new(program_input, store);
 
-    if splimer.program_input.to_split {
-        splimer.split();
-    } else {
-        splimer.merge();
+    match splimer.program_input.command {
+        Command::Split => splimer.split(),
+        Command::Merge => splimer.merge(),
+        Command::Verify => {
+            if !splimer.verify() {
+                process::exit(1);
+            }
+        },
+        Command::Info => splimer.info(),
+        Command::List => splimer.list(),
+        Command::Upload => {
+            if !splimer.upload() {
+                process::exit(1);
+            }
+        },
+        Command::Download => {
+            if !splimer.download() {
+                process::exit(1);
+            }
+        },
+        Command::Watch => unreachable!(),
+    }
+    // Every reported error makes the exit status non-zero, whatever the command is
+    if splimer.has_failed() {
+        process::exit(1);
     }
 }
\ No newline at end of file
@@ -0,0 +1,364 @@
+// Manifest is written next to parts as `filename.ext_[manifest].splm`.
+// It is a plain text file of `key=value` lines, one `part=` line for every part:
+//
+//     splimer-manifest=1
+//     file_name=game.zip
+//     set_id=...
+//     total_size=3000000
+//     fragment_size=1048576
+//     part_sizes=...
+//     repeated_header_size=...
+//     header_sizes=...
+//     trailer_sizes=...
+//     compression=none
+//     encryption=none
+//     sha256=...
+//     following=true
+//     part=1 0 1048576 ...
+//
+// `part_sizes` lists sizes of all parts when they are not all of `fragment_size`, like with `-S 4g,1g`.
+// `repeated_header_size` bytes of the first part are repeated at the beginning of every other part,
+// so sizes and hashes of those parts are of their bytes after the header.
+// `header_sizes` and `trailer_sizes` list bytes added at the beginning and at the end of every part,
+// like `M` headers of `--boundary=tar` or the central directory of `--boundary=zip`.
+// `following=true` is written while `split --follow` still adds parts, so `total_size` and
+// the list of parts are of what is written so far.
+// Unknown keys are kept, so older versions can read newer manifests.
+// File name bytes which are not printable UTF-8 are written as `%XX`.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+
+use crate::output::ClobberPolicy;
+use crate::paths;
+use crate::store::PartStore;
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Debug)]
+pub struct PartRecord {
+    pub index: usize,
+    pub offset: u64,
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub file_name: OsString,
+    pub set_id: Option<String>,
+    pub total_size: u64,
+    pub fragment_size: u64,
+    // Empty when every part but the last one is of `fragment_size`
+    pub part_sizes: Vec<u64>,
+    pub repeated_header_size: u64,
+    // Empty when nothing is added at the beginning or at the end of parts
+    pub header_sizes: Vec<u64>,
+    pub trailer_sizes: Vec<u64>,
+    pub compression: String,
+    pub encryption: String,
+    pub sha256: Option<String>,
+    // Split goes on, more parts will come
+    pub following: bool,
+    pub parts: Vec<PartRecord>,
+    pub extra: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(io::Error),
+    UnsupportedVersion(String),
+    BadLine(usize, String),
+    MissingKey(&'static str),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Io(err) => write!(f, "{}", err),
+            ManifestError::UnsupportedVersion(version) => write!(f, "manifest version {} is not supported", version),
+            ManifestError::BadLine(number, line) => write!(f, "cannot parse line {}: \"{}\"", number, line),
+            ManifestError::MissingKey(key) => write!(f, "there is no `{}` key", key),
+        }
+    }
+}
+
+impl PartRecord {
+    // `index offset size sha256`, `-` for a hash which is not known
+    pub fn parse(value: &str) -> Option<PartRecord> {
+        let fields: Vec<&str> = value.split(' ').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        return Some(PartRecord{
+            index: fields[0].parse().ok()?,
+            offset: fields[1].parse().ok()?,
+            size: fields[2].parse().ok()?,
+            sha256: if fields[3] == "-" { None } else { Some(fields[3].to_string()) },
+        });
+    }
+}
+
+impl fmt::Display for PartRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} {} {} {}", self.index, self.offset, self.size, self.sha256.as_deref().unwrap_or("-"));
+    }
+}
+
+impl Manifest {
+    pub fn new(file_name: OsString, total_size: u64, fragment_size: u64) -> Manifest {
+        return Manifest{
+            file_name,
+            set_id: None,
+            total_size,
+            fragment_size,
+            part_sizes: Vec::new(),
+            repeated_header_size: 0,
+            header_sizes: Vec::new(),
+            trailer_sizes: Vec::new(),
+            compression: "none".to_string(),
+            encryption: "none".to_string(),
+            sha256: None,
+            following: false,
+            parts: Vec::new(),
+            extra: Vec::new(),
+        };
+    }
+
+    pub fn part_count(&self) -> usize {
+        if !self.part_sizes.is_empty() {
+            return self.part_sizes.len();
+        }
+        if self.fragment_size == 0 {
+            return self.parts.iter().map(|part| part.index).max().unwrap_or(0);
+        }
+        return self.total_size.div_ceil(self.fragment_size) as usize;
+    }
+
+    // Sizes of all parts as they were planned, also of those which are not written yet
+    pub fn planned_sizes(&self) -> Vec<u64> {
+        if !self.part_sizes.is_empty() {
+            return self.part_sizes.clone();
+        }
+        if self.fragment_size == 0 {
+            return self.parts.iter().map(|part| part.size).collect();
+        }
+        return (0..self.part_count() as u64)
+            .map(|i| self.fragment_size.min(self.total_size - i * self.fragment_size))
+            .collect();
+    }
+
+    pub fn set_planned_sizes(&mut self, sizes: &[u64]) {
+        self.fragment_size = sizes.first().copied().unwrap_or(0);
+        self.part_sizes = Vec::new();
+        if self.planned_sizes() != sizes {
+            self.part_sizes = sizes.to_vec();
+        }
+    }
+
+    // Bytes at the beginning of a part file which are not a piece of the original
+    pub fn header_size(&self, index: usize) -> u64 {
+        let header_size = index.checked_sub(1).and_then(|i| self.header_sizes.get(i)).copied().unwrap_or(0);
+        return header_size + if index > 1 { self.repeated_header_size } else { 0 };
+    }
+
+    // All bytes of a part file which are not a piece of the original, at its beginning and at its end
+    pub fn added_size(&self, index: usize) -> u64 {
+        let trailer_size = index.checked_sub(1).and_then(|i| self.trailer_sizes.get(i)).copied().unwrap_or(0);
+        return self.header_size(index) + trailer_size;
+    }
+
+    pub fn part(&self, index: usize) -> Option<&PartRecord> {
+        return self.parts.iter().find(|part| part.index == index);
+    }
+
+    pub fn set_part(&mut self, record: PartRecord) {
+        match self.parts.iter_mut().find(|part| part.index == record.index) {
+            Some(part) => *part = record,
+            None => {
+                self.parts.push(record);
+                self.parts.sort_by_key(|part| part.index);
+            }
+        }
+    }
+
+    pub fn read(store: &dyn PartStore, name: &OsStr) -> Result<Manifest, ManifestError> {
+        let text = store.read_to_string(name).map_err(ManifestError::Io)?;
+        return Self::parse(&text);
+    }
+
+    pub fn write(&self, store: &dyn PartStore, name: &OsStr, policy: ClobberPolicy) -> io::Result<()> {
+        return store.write(name, self.to_string().as_bytes(), policy);
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, ManifestError> {
+        let mut version = None;
+        let mut file_name = None;
+        let mut total_size = None;
+        let mut fragment_size = None;
+        let mut manifest = Manifest::new(OsString::new(), 0, 0);
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || ManifestError::BadLine(number + 1, line.to_string());
+            let (key, value) = line.split_once('=').ok_or_else(bad_line)?;
+
+            match key {
+                "splimer-manifest" => version = Some(value.to_string()),
+                "file_name" => file_name = Some(paths::decode(value).ok_or_else(bad_line)?),
+                "total_size" => total_size = Some(value.parse::<u64>().map_err(|_| bad_line())?),
+                "fragment_size" => fragment_size = Some(value.parse::<u64>().map_err(|_| bad_line())?),
+                "repeated_header_size" => manifest.repeated_header_size = value.parse::<u64>().map_err(|_| bad_line())?,
+                "part_sizes" => manifest.part_sizes = value.split(',')
+                    .map(|size| size.parse::<u64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad_line())?,
+                "header_sizes" => manifest.header_sizes = value.split(',')
+                    .map(|size| size.parse::<u64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad_line())?,
+                "trailer_sizes" => manifest.trailer_sizes = value.split(',')
+                    .map(|size| size.parse::<u64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad_line())?,
+                "set_id" => manifest.set_id = Some(value.to_string()),
+                "compression" => manifest.compression = value.to_string(),
+                "encryption" => manifest.encryption = value.to_string(),
+                "sha256" => manifest.sha256 = Some(value.to_string()),
+                "following" => manifest.following = value == "true",
+                "part" => manifest.set_part(PartRecord::parse(value).ok_or_else(bad_line)?),
+                _ => manifest.extra.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        match version {
+            Some(version) if version == MANIFEST_VERSION.to_string() => { },
+            Some(version) => return Err(ManifestError::UnsupportedVersion(version)),
+            None => return Err(ManifestError::MissingKey("splimer-manifest")),
+        }
+        manifest.file_name = file_name.ok_or(ManifestError::MissingKey("file_name"))?;
+        manifest.total_size = total_size.ok_or(ManifestError::MissingKey("total_size"))?;
+        manifest.fragment_size = fragment_size.ok_or(ManifestError::MissingKey("fragment_size"))?;
+
+        return Ok(manifest);
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "splimer-manifest={}", MANIFEST_VERSION)?;
+        writeln!(f, "file_name={}", paths::encode(&self.file_name))?;
+        if let Some(set_id) = &self.set_id {
+            writeln!(f, "set_id={}", set_id)?;
+        }
+        writeln!(f, "total_size={}", self.total_size)?;
+        writeln!(f, "fragment_size={}", self.fragment_size)?;
+        if !self.part_sizes.is_empty() {
+            writeln!(f, "part_sizes={}", self.part_sizes.iter().map(u64::to_string).collect::<Vec<_>>().join(","))?;
+        }
+        if self.repeated_header_size > 0 {
+            writeln!(f, "repeated_header_size={}", self.repeated_header_size)?;
+        }
+        if !self.header_sizes.is_empty() {
+            writeln!(f, "header_sizes={}", self.header_sizes.iter().map(u64::to_string).collect::<Vec<_>>().join(","))?;
+        }
+        if !self.trailer_sizes.is_empty() {
+            writeln!(f, "trailer_sizes={}", self.trailer_sizes.iter().map(u64::to_string).collect::<Vec<_>>().join(","))?;
+        }
+        writeln!(f, "compression={}", self.compression)?;
+        writeln!(f, "encryption={}", self.encryption)?;
+        if let Some(sha256) = &self.sha256 {
+            writeln!(f, "sha256={}", sha256)?;
+        }
+        if self.following {
+            writeln!(f, "following=true")?;
+        }
+        for (key, value) in &self.extra {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        for part in &self.parts {
+            writeln!(f, "part={}", part)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new(OsString::from("game 100%.zip"), 2500, 1000);
+        manifest.set_id = Some("0123abcd".to_string());
+        manifest.sha256 = Some("ff".repeat(32));
+        manifest.set_part(PartRecord{ index: 2, offset: 1000, size: 1000, sha256: None });
+        manifest.set_part(PartRecord{ index: 1, offset: 0, size: 1000, sha256: Some("aa".repeat(32)) });
+        return manifest;
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut original = manifest();
+        original.header_sizes = vec![0, 512, 512];
+        original.trailer_sizes = vec![1024, 0, 0];
+        original.repeated_header_size = 8;
+        original.following = true;
+        original.extra.push(("future_key".to_string(), "a=b".to_string()));
+
+        let text = original.to_string();
+        assert!(text.starts_with("splimer-manifest=1\nfile_name=game 100%25.zip\n"));
+        assert!(text.ends_with(&format!("part=1 0 1000 {}\npart=2 1000 1000 -\n", "aa".repeat(32))));
+
+        let parsed = Manifest::parse(&text).unwrap();
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(parsed.file_name, original.file_name);
+        assert_eq!(parsed.header_sizes, vec![0, 512, 512]);
+        assert_eq!(parsed.trailer_sizes, vec![1024, 0, 0]);
+        assert!(parsed.following);
+        assert_eq!(parsed.extra, vec![("future_key".to_string(), "a=b".to_string())]);
+        assert_eq!(parsed.part(2).map(|part| part.sha256.clone()), Some(None));
+        assert!(parsed.part(3).is_none());
+        assert_eq!(parsed.added_size(1), 1024);
+        assert_eq!(parsed.added_size(2), 520);
+    }
+
+    #[test]
+    fn planned_sizes() {
+        let mut manifest = manifest();
+        assert_eq!(manifest.part_count(), 3);
+        assert_eq!(manifest.planned_sizes(), vec![1000, 1000, 500]);
+
+        manifest.set_planned_sizes(&[1000, 1000, 500]);
+        assert!(manifest.part_sizes.is_empty());
+        assert!(!manifest.to_string().contains("part_sizes="));
+
+        manifest.set_planned_sizes(&[1000, 400, 400, 700]);
+        assert_eq!(manifest.fragment_size, 1000);
+        assert_eq!(manifest.part_count(), 4);
+        let parsed = Manifest::parse(&manifest.to_string()).unwrap();
+        assert_eq!(parsed.planned_sizes(), vec![1000, 400, 400, 700]);
+    }
+
+    #[test]
+    fn older_and_broken_manifests() {
+        let text = "# written by hand\r\nsplimer-manifest=1\r\nfile_name=a.bin\r\n\r\ntotal_size=10\r\nfragment_size=4\r\npart=3 8 2 -\r\n";
+        let manifest = Manifest::parse(text).unwrap();
+        assert_eq!(manifest.file_name, OsString::from("a.bin"));
+        assert_eq!(manifest.compression, "none");
+        assert!(!manifest.following);
+        assert_eq!(manifest.part_count(), 3);
+        assert_eq!(manifest.part(3).map(|part| (part.offset, part.size)), Some((8, 2)));
+
+        assert!(matches!(Manifest::parse("splimer-manifest=2\nfile_name=a\ntotal_size=1\nfragment_size=1\n"), Err(ManifestError::UnsupportedVersion(version)) if version == "2"));
+        assert!(matches!(Manifest::parse("file_name=a\ntotal_size=1\nfragment_size=1\n"), Err(ManifestError::MissingKey("splimer-manifest"))));
+        assert!(matches!(Manifest::parse("splimer-manifest=1\nfile_name=a\nfragment_size=1\n"), Err(ManifestError::MissingKey("total_size"))));
+        assert!(matches!(Manifest::parse("splimer-manifest=1\nfile_name=a\ntotal_size=x\n"), Err(ManifestError::BadLine(3, _))));
+        assert!(matches!(Manifest::parse("splimer-manifest=1\njust a line\n"), Err(ManifestError::BadLine(2, _))));
+        assert!(matches!(Manifest::parse("splimer-manifest=1\nfile_name=a%zz\n"), Err(ManifestError::BadLine(2, _))));
+        assert!(matches!(Manifest::parse("splimer-manifest=1\npart=1 0 10\n"), Err(ManifestError::BadLine(2, _))));
+    }
+}
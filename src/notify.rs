@@ -0,0 +1,107 @@
+// Changes of files in a directory, which `watch` and `--follow` wait for instead of polling.
+// On Linux they come from inotify, elsewhere the caller is told to look at the files again every second.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+pub struct Change {
+    pub name: OsString,
+    // The file is closed by a program which wrote it
+    pub closed: bool,
+}
+
+#[cfg(target_os = "linux")]
+pub struct Events {
+    fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Events {
+    pub fn new(directory: &Path) -> io::Result<Events> {
+        use std::os::fd::FromRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        // SAFETY: plain system calls, the descriptor is owned from here on
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+        let path = std::ffi::CString::new(directory.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_MODIFY | libc::IN_ATTRIB;
+        // SAFETY: `path` is a NUL-terminated string which lives through the call
+        if unsafe { libc::inotify_add_watch(std::os::fd::AsRawFd::as_raw_fd(&fd), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(Events{ fd });
+    }
+
+    // Files which changed, `None` when too many did and the directory should be looked at again
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Option<Vec<Change>>> {
+        use std::ffi::OsStr;
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut poll = libc::pollfd{ fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout = timeout.map(|timeout| timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int).unwrap_or(-1);
+        // SAFETY: one `pollfd` is passed
+        if unsafe { libc::poll(&mut poll, 1, timeout) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(Some(Vec::new()));
+            }
+            return Err(err);
+        }
+
+        let mut changes = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            // SAFETY: the buffer is valid for its length
+            let size = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if size < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(Some(changes));
+                }
+                return Err(err);
+            }
+            // Every event is `wd`, `mask`, `cookie` and `len` of 4 bytes each, and a name of `len` bytes padded with NULs
+            let mut offset = 0;
+            while offset + 16 <= size as usize {
+                let field = |index: usize| u32::from_ne_bytes(buffer[offset + index * 4..offset + index * 4 + 4].try_into().unwrap());
+                let (mask, length) = (field(1), field(3) as usize);
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    return Ok(None);
+                }
+                let name = &buffer[offset + 16..offset + 16 + length];
+                let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(name.len())];
+                if mask & libc::IN_ISDIR == 0 && !name.is_empty() {
+                    changes.push(Change{
+                        name: OsStr::from_bytes(name).to_os_string(),
+                        closed: mask & libc::IN_CLOSE_WRITE != 0,
+                    });
+                }
+                offset += 16 + length;
+            }
+        }
+    }
+}
+
+// Without inotify files are looked at every second
+#[cfg(not(target_os = "linux"))]
+pub struct Events;
+
+#[cfg(not(target_os = "linux"))]
+impl Events {
+    pub fn new(_directory: &Path) -> io::Result<Events> {
+        return Ok(Events);
+    }
+
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Option<Vec<Change>>> {
+        std::thread::sleep(timeout.unwrap_or(Duration::MAX).min(Duration::from_secs(1)));
+        return Ok(None);
+    }
+}
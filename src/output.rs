@@ -0,0 +1,92 @@
+// Every output is written under a temporary name next to its target
+// and renamed into place only when it is complete, so a failed or cancelled run
+// never leaves a half-written file which looks finished.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClobberPolicy {
+    // Split replaces its own parts but not parts of another file, merge replaces nothing
+    Default,
+    // Nothing that already exists is replaced
+    NoClobber,
+    // Everything is replaced
+    Force,
+    // Everything is replaced, old files are kept as `name~`
+    Backup,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FsyncPolicy {
+    // The kernel writes files out when it wants to
+    Never,
+    // Every part is on disk before the next one is started
+    PerPart,
+    // All files are synced once, when everything is written
+    End,
+}
+
+impl FsyncPolicy {
+    pub fn from_name(name: &str) -> Option<FsyncPolicy> {
+        return match name {
+            "never" => Some(FsyncPolicy::Never),
+            "per-part" => Some(FsyncPolicy::PerPart),
+            "end" => Some(FsyncPolicy::End),
+            _ => None,
+        };
+    }
+}
+
+// `dir/game.zip_[1].splm` -> `dir/.game.zip_[1].splm.splimer-tmp`
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    return path.with_file_name(paths::concat(&[OsStr::new("."), name, OsStr::new(".splimer-tmp")]));
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    return path.with_file_name(paths::concat(&[name, OsStr::new("~")]));
+}
+
+// Moves a finished temporary file to `target`, keeping the old one aside with `Backup` policy
+pub fn commit(temporary: &Path, target: &Path, policy: ClobberPolicy) -> io::Result<()> {
+    if policy == ClobberPolicy::Backup && fs::symlink_metadata(target).is_ok() {
+        fs::rename(target, backup_path(target))?;
+    }
+    return fs::rename(temporary, target);
+}
+
+pub fn write_file(target: &Path, contents: &[u8], policy: ClobberPolicy) -> io::Result<()> {
+    let temporary = temporary_path(target);
+    fs::write(&temporary, contents)?;
+    return commit(&temporary, target, policy);
+}
+
+// Puts a file which is not needed anymore aside with `Backup` policy, or removes it
+pub fn discard(path: &Path, policy: ClobberPolicy) -> io::Result<()> {
+    if policy == ClobberPolicy::Backup {
+        return fs::rename(path, backup_path(path));
+    }
+    return fs::remove_file(path);
+}
+
+pub fn sync_file(path: &Path) -> io::Result<()> {
+    return fs::File::open(path)?.sync_all();
+}
+
+// A rename or a removal is durable only when the directory itself is synced
+#[cfg(unix)]
+pub fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    return fs::File::open(directory)?.sync_all();
+}
+
+#[cfg(not(unix))]
+pub fn sync_directory(_path: &Path) -> io::Result<()> {
+    return Ok(());
+}
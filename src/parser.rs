@@ -1,105 +1,431 @@
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::boundary::Boundary;
+use crate::cli;
+use crate::copy::IoBackend;
+use crate::output::{ClobberPolicy, FsyncPolicy};
+use crate::store::StoreLocation;
+use crate::plan::Strategy;
+use crate::report::OutputFormat;
+
 const DEFAULT_FRAGMENT_SIZE: usize = 1024 * 1024 * 1024usize;
 const MINIMUM_FRAGMENT_SIZE: usize = 1024;
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+const MINIMUM_BUFFER_SIZE: usize = 4096;
+const DEFAULT_SETTLE_TIME: Duration = Duration::from_secs(5);
 
-pub fn parse_memory_value(string: &String) -> Result<usize, ()> {
-    let mut value = 0usize;
-    let mut exp10 = -1;
-    let mut exp2 = -1;
+// Named upload targets for `--fragment-size`, in bytes.
+// Parts are written as raw slices of the input, so splimer itself adds no
+// header, compression or encryption overhead; the values below only leave
+// room for what the target adds on its own side.
+pub const SIZE_PRESETS: &[(&str, u64, &str)] = &[
+    ("telegram",         2000 * 1024 * 1024,        "Telegram, 2000 MiB"),
+    ("telegram-premium", 4000 * 1024 * 1024,        "Telegram Premium, 4000 MiB"),
+    ("discord",          10 * 1000 * 1000,          "Discord without Nitro, 10 MB"),
+    ("email-25m",        18 * 1000 * 1000,          "25 MB mail limit, minus base64 and MIME headers"),
+    ("fat32",            4 * 1024 * 1024 * 1024 - 1, "FAT32 file size limit, 4 GiB minus one byte"),
+    ("cd700",            700 * 1000 * 1000,         "700 MB CD-R, minus filesystem"),
+    ("dvd",              4_600_000_000,             "4.7 GB DVD-R, minus filesystem"),
+];
+
+pub fn parse_size_preset(string: &str) -> Option<Result<usize, MemoryValueError>> {
+    let name = string.trim().to_lowercase();
+    SIZE_PRESETS.iter()
+        .find(|(preset, _, _)| *preset == name)
+        .map(|(_, value, _)| usize::try_from(*value).map_err(|_| MemoryValueError::Overflow))
+}
 
+pub fn parse_fragment_size(string: &str) -> Result<usize, MemoryValueError> {
+    if let Some(result) = parse_size_preset(string) {
+        return result;
+    }
+    return parse_memory_value(string);
+}
 
-    for i in string.chars().into_iter() {
-        match i {
-            '0'..='9' => {
-                value *= 10;
-                value += i.to_digit(10u32).unwrap_or(0u32) as usize;
+// Units in any case, they keep their old binary meaning: `1m` and `1mb` are still 1048576 bytes
+const MEMORY_UNITS: &[(&str, u128)] = &[
+    ("b", 1),
+    ("k", 1 << 10),
+    ("m", 1 << 20),
+    ("g", 1 << 30),
+    ("t", 1 << 40),
+    ("kb", 1 << 10),
+    ("mb", 1 << 20),
+    ("gb", 1 << 30),
+    ("tb", 1 << 40),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+];
 
-                if exp10 != -1 { exp10 += 1; }
-                if exp2 != -1 {
-                    return Err(());
-                }
-            },
-            '.' | ',' => {
-                if exp10 == -1 {
-                    exp10 = 0;
+// Decimal units are told by the capital `B`, whatever case the letter before it is,
+// so `1MB` and `1mB` are 1000000 bytes. These are their prefixes
+const DECIMAL_MEMORY_UNITS: &[(&str, u128)] = &[
+    ("k", 1_000),
+    ("m", 1_000_000),
+    ("g", 1_000_000_000),
+    ("t", 1_000_000_000_000),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum MemoryValueError {
+    Empty,
+    UnexpectedCharacter(usize, char),
+    UnknownUnit(usize, String),
+    Overflow,
+}
+
+impl MemoryValueError {
+    // Position of the offending character, counted in chars from 0
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            MemoryValueError::UnexpectedCharacter(position, _) => Some(*position),
+            MemoryValueError::UnknownUnit(position, _) => Some(*position),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for MemoryValueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryValueError::Empty => write!(f, "value is empty"),
+            MemoryValueError::UnexpectedCharacter(position, c) =>
+                write!(f, "unexpected character '{}' at position {}", c, position + 1),
+            MemoryValueError::UnknownUnit(position, unit) =>
+                write!(f, "unknown unit \"{}\" at position {}", unit, position + 1),
+            MemoryValueError::Overflow => write!(f, "value is too big"),
+        }
+    }
+}
+
+// Grammar: digits ['.' digits] [unit]
+// A comma is not a decimal separator, it separates sizes of a `-S` list.
+// Digits may be grouped with `_` or spaces, e.g. `1_000_000` or `1 000 000`,
+// and spaces are allowed between the number and the unit.
+// Everything is counted in integers, fractional bytes are rounded down.
+pub fn parse_memory_value(string: &str) -> Result<usize, MemoryValueError> {
+    let chars: Vec<char> = string.chars().collect();
+    let is_digit_at = |i: usize| i < chars.len() && chars[i].is_ascii_digit();
+
+    let mut i = 0;
+    while i < chars.len() && chars[i] == ' ' {
+        i += 1;
+    }
+    if i == chars.len() {
+        return Err(MemoryValueError::Empty);
+    }
+    if !is_digit_at(i) {
+        return Err(MemoryValueError::UnexpectedCharacter(i, chars[i]));
+    }
+
+    let mut integer = 0u128;
+    let mut fraction_digits: Vec<u128> = Vec::new();
+    let mut in_fraction = false;
+
+    while i < chars.len() {
+        match chars[i] {
+            c @ '0'..='9' => {
+                let digit = c.to_digit(10).unwrap() as u128;
+                if in_fraction {
+                    fraction_digits.push(digit);
                 } else {
-                    return Err(());
-                }
-                if exp2 != -1 {
-                    return Err(());
+                    integer = integer.checked_mul(10)
+                        .and_then(|v| v.checked_add(digit))
+                        .ok_or(MemoryValueError::Overflow)?;
                 }
             },
-            'g' | 'G' | 'm' | 'M' | 'k' | 'K' | 'b' | 'B' => {
-                if exp2 == -1 {
-                    exp2 = 0;
-                } else {
-                    return Err(());
-                }
-                exp2 += match i {
-                    'g' | 'G' => 30,
-                    'm' | 'M' => 20,
-                    'k' | 'K' => 10,
-                    'b' | 'B' => 0,
-                    _ => panic!()
-                };
+            '_' | ' ' if is_digit_at(i - 1) && is_digit_at(i + 1) => { },
+            '.' if !in_fraction && is_digit_at(i - 1) && is_digit_at(i + 1) => {
+                in_fraction = true;
             },
-            _ => return Err(())
+            _ => break
         }
+        i += 1;
     }
 
-    value *= 2usize.pow(if exp2 >= 0 { exp2 as u32 } else { 0 });
+    while i < chars.len() && chars[i] == ' ' {
+        i += 1;
+    }
 
-    let value = value as f64 / 10f64.powf(if exp10 >= 0 { exp10 as f64 } else { 0.0 });
+    let unit_start = i;
+    while i < chars.len() && chars[i].is_ascii_alphabetic() {
+        i += 1;
+    }
+    let unit: String = chars[unit_start..i].iter().collect();
+
+    while i < chars.len() && chars[i] == ' ' {
+        i += 1;
+    }
+    if i < chars.len() {
+        return Err(MemoryValueError::UnexpectedCharacter(i, chars[i]));
+    }
+
+    let multiplier = if unit.is_empty() {
+        1
+    } else {
+        let decimal = unit.strip_suffix('B').and_then(|prefix| DECIMAL_MEMORY_UNITS.iter().find(|(name, _)| *name == prefix.to_lowercase()));
+        match decimal.or_else(|| MEMORY_UNITS.iter().find(|(name, _)| *name == unit.to_lowercase())) {
+            Some((_, multiplier)) => *multiplier,
+            None => return Err(MemoryValueError::UnknownUnit(unit_start, unit)),
+        }
+    };
+
+    // floor(0.d1d2...dn * multiplier), folding digits from the right keeps it exact
+    let mut fraction = 0u128;
+    for digit in fraction_digits.iter().rev() {
+        fraction = (digit * multiplier + fraction) / 10;
+    }
+
+    let value = integer.checked_mul(multiplier)
+        .and_then(|v| v.checked_add(fraction))
+        .ok_or(MemoryValueError::Overflow)?;
+
+    return usize::try_from(value).map_err(|_| MemoryValueError::Overflow);
+}
+
+// `90`, `1.5s`, `500ms`, `10m` or `2h`, seconds when there is no unit
+pub fn parse_duration(string: &str) -> Option<Duration> {
+    let string = string.trim();
+    let split = string.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(string.len());
+    let (number, unit) = string.split_at(split);
+    let number: f64 = number.trim().parse().ok().filter(|number: &f64| number.is_finite() && *number >= 0.0)?;
+    let multiplier = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    return Duration::try_from_secs_f64(number * multiplier).ok();
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command {
+    Split,
+    Merge,
+    Verify,
+    Info,
+    List,
+    Upload,
+    Download,
+    Watch,
+}
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "split" => Some(Command::Split),
+            "merge" => Some(Command::Merge),
+            "verify" => Some(Command::Verify),
+            "info" => Some(Command::Info),
+            "list" => Some(Command::List),
+            "upload" => Some(Command::Upload),
+            "download" => Some(Command::Download),
+            "watch" => Some(Command::Watch),
+            _ => None
+        }
+    }
 
-    return Ok(value as usize);
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Split => "split",
+            Command::Merge => "merge",
+            Command::Verify => "verify",
+            Command::Info => "info",
+            Command::List => "list",
+            Command::Upload => "upload",
+            Command::Download => "download",
+            Command::Watch => "watch",
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct ProgramInput {
-    pub to_split: bool,
-    pub input_filename: String,
-    pub fragment_size: usize,
-    pub output_directory: Option<String>,
+    pub command: Command,
+    pub input_filename: PathBuf,
+    // Sizes given by `-S`, the last one is repeated, empty when only `--parts` is given
+    pub fragment_sizes: Vec<usize>,
+    // `-S` list ends with `rest`
+    pub rest_part: bool,
+    pub strategy: Strategy,
+    pub boundary: Boundary,
+    pub csv_header: bool,
+    pub output_directory: Option<PathBuf>,
+    pub output_file: Option<PathBuf>,
     pub parts: Option<usize>,
-    pub part_number: Option<usize>
+    pub part_number: Option<usize>,
+    pub json: bool,
+    pub dry_run: bool,
+    pub consume_source: bool,
+    pub consume_parts: bool,
+    pub zero_copy: bool,
+    pub io: IoBackend,
+    pub fsync: FsyncPolicy,
+    pub direct: bool,
+    pub buffer_size: usize,
+    // Where parts are kept, `None` for the directory of the set
+    pub store: Option<StoreLocation>,
+    // Upload and download go through a Telegram bot
+    pub telegram: bool,
+    // Chat which parts are sent to, its id or `@channel`
+    pub chat: Option<String>,
+    // Base URL of the Bot API, `None` for api.telegram.org
+    pub telegram_api: Option<String>,
+    // Message with the manifest which download fetches, `None` for the pinned one
+    pub manifest_message: Option<i64>,
+    // Shell command which is run for every written part
+    pub on_part_done: Option<String>,
+    // The next part is written only when the command of the previous one succeeds
+    pub wait_for_hook: bool,
+    pub delete_after_hook: bool,
+    // How many parts may wait for their commands while the next one is written
+    pub max_pending: Option<usize>,
+    // Where `watch` puts parts, `outbox` in the watched directory by default
+    pub outbox: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub status_file: Option<PathBuf>,
+    // How long a file should stay the same before `watch` splits it
+    pub settle: Duration,
+    // Split a file which is still written, or merge parts which are still coming
+    pub follow: bool,
+    // How long `--follow` waits for anything new, `None` for the default of the command
+    pub follow_timeout: Option<Duration>,
+    pub undo: bool,
+    pub output_format: OutputFormat,
+    pub clobber: ClobberPolicy
 }
 
 struct ProgramInputBuilder {
-    pub to_split: bool,
-    pub input_filename: Option<String>,
-    pub fragment_size: usize,
-    pub output_directory: Option<String>,
+    pub command: Command,
+    // None for old flat syntax, where every option is accepted
+    pub subcommand: Option<Command>,
+    pub input_filename: Option<PathBuf>,
+    pub fragment_sizes: Vec<usize>,
+    pub rest_part: bool,
+    pub strategy: Strategy,
+    pub boundary: Boundary,
+    pub csv_header: bool,
+    pub output_directory: Option<PathBuf>,
+    pub output_file: Option<PathBuf>,
     pub parts: Option<usize>,
-    pub part_number: Option<usize>
+    pub part_number: Option<usize>,
+    pub json: bool,
+    pub dry_run: bool,
+    pub consume_source: bool,
+    pub consume_parts: bool,
+    pub zero_copy: bool,
+    pub io: IoBackend,
+    pub fsync: FsyncPolicy,
+    pub direct: bool,
+    pub buffer_size: usize,
+    pub store: Option<StoreLocation>,
+    pub telegram: bool,
+    pub chat: Option<String>,
+    pub telegram_api: Option<String>,
+    pub manifest_message: Option<i64>,
+    pub on_part_done: Option<String>,
+    pub wait_for_hook: bool,
+    pub delete_after_hook: bool,
+    pub max_pending: Option<usize>,
+    pub outbox: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub status_file: Option<PathBuf>,
+    pub settle: Duration,
+    pub follow: bool,
+    pub follow_timeout: Option<Duration>,
+    pub undo: bool,
+    pub output_format: OutputFormat,
+    pub clobber: ClobberPolicy
 }
 
 impl ProgramInputBuilder {
     fn new() -> ProgramInputBuilder {
         return Self{
-            to_split: true,
+            command: Command::Split,
+            subcommand: None,
             input_filename: None,
-            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            fragment_sizes: Vec::new(),
+            rest_part: false,
+            strategy: Strategy::Fixed,
+            boundary: Boundary::Byte,
+            csv_header: false,
             output_directory: None,
+            output_file: None,
             parts: None,
-            part_number: None
+            part_number: None,
+            json: false,
+            dry_run: false,
+            consume_source: false,
+            consume_parts: false,
+            zero_copy: true,
+            io: IoBackend::Sync,
+            fsync: FsyncPolicy::PerPart,
+            direct: false,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            store: None,
+            telegram: false,
+            chat: None,
+            telegram_api: None,
+            manifest_message: None,
+            on_part_done: None,
+            wait_for_hook: false,
+            delete_after_hook: false,
+            max_pending: None,
+            outbox: None,
+            state_file: None,
+            status_file: None,
+            settle: DEFAULT_SETTLE_TIME,
+            follow: false,
+            follow_timeout: None,
+            undo: false,
+            output_format: OutputFormat::Text,
+            clobber: ClobberPolicy::Default
         }
     }
 }
 
+// Parsed only once, so the size of `Success` doesn't matter
+#[allow(clippy::large_enum_variant)]
 pub enum ParseResult {
     Success(ProgramInput),
     ThereIsNoInputFilename,
-    MemoryValueCannotBeParsed(String),
+    MemoryValueCannotBeParsed(String, MemoryValueError),
     NumberOfPartsCannotBeParsed(String),
     NumberOfPartsShouldBeMoreThanOne(usize),
     PartNumberShouldBePositive(usize),
     FragmentSizeIsToSmall(usize),
+    RestShouldBeLast(String),
+    // `-S 1,5k` was a fraction before sizes could be listed
+    CommaIsNotDecimal(String),
+    UnknownStrategy(String),
+    UnknownBoundary(String),
     ThereIsNoValue(String),
+    OptionIsNotAllowed(String, Command),
+    UnknownShell(String),
+    UnknownOutputFormat(String),
+    UnknownIoBackend(String),
+    UnknownFsyncPolicy(String),
+    BufferSizeCannotBeParsed(String, MemoryValueError),
+    BufferSizeIsTooSmall(usize),
+    UnknownStore(String),
+    MaxPendingCannotBeParsed(String),
+    MessageIdCannotBeParsed(String),
+    DurationCannotBeParsed(String),
+    UnknownCommand(String),
     SuccessfulHandledArgument,
     SuccessfulHandledFlag,
-    Help
+    Help(Option<String>),
+    Completions(String),
+    Man
 }
 
 impl ProgramInput {
-    pub fn parse(arguments: &Vec<String>) -> ParseResult {
+    pub fn parse(arguments: &[OsString]) -> ParseResult {
         let _exe_name = &arguments[0];
 
         if arguments.len() <= 1 {
@@ -107,17 +433,48 @@ impl ProgramInput {
         }
 
         let mut builder = ProgramInputBuilder::new();
+        let argument_str = |i: usize| arguments.get(i).map(|argument| argument.to_string_lossy().to_string());
 
         let mut i = 1usize;
+        match arguments[1].to_str().unwrap_or("") {
+            "completions" => {
+                return match argument_str(2) {
+                    Some(shell) if cli::SHELLS.contains(&shell.as_str()) => ParseResult::Completions(shell),
+                    Some(shell) => ParseResult::UnknownShell(shell),
+                    None => ParseResult::ThereIsNoValue("completions".to_string()),
+                };
+            },
+            "man" => return ParseResult::Man,
+            "help" => {
+                return match argument_str(2) {
+                    Some(name) if cli::COMMANDS.iter().any(|spec| spec.name == name) => ParseResult::Help(Some(name)),
+                    Some(name) => ParseResult::UnknownCommand(name),
+                    None => ParseResult::Help(None),
+                };
+            },
+            name => {
+                if let Some(command) = Command::from_name(name) {
+                    builder.command = command;
+                    builder.subcommand = Some(command);
+                    i += 1;
+                }
+            }
+        }
+
         while i < arguments.len() {
             let string = &arguments[i];
+            let bytes = string.as_encoded_bytes();
 
             let key;
             let value;
             let is_next_argument_a_value;
-            if let Some(equal_sign_index) = string.find('=') {
-                value = string[equal_sign_index + 1..].to_string();
-                key = string[..equal_sign_index].to_string();
+            // Only options are split at `=`, file names may contain it too
+            if let Some(equal_sign_index) = bytes.iter().position(|&byte| byte == b'=').filter(|_| bytes.starts_with(b"-")) {
+                // SAFETY: `=` is ASCII, so the string can be split around it
+                unsafe {
+                    key = OsStr::from_encoded_bytes_unchecked(&bytes[..equal_sign_index]).to_os_string();
+                    value = OsStr::from_encoded_bytes_unchecked(&bytes[equal_sign_index + 1..]).to_os_string();
+                }
                 is_next_argument_a_value = false;
             } else {
                 key = string.clone();
@@ -126,7 +483,7 @@ impl ProgramInput {
                     arguments[i + 1].clone()
                 } else {
                     is_next_argument_a_value = false;
-                    String::new()
+                    OsString::new()
                 }
             }
 
@@ -141,34 +498,121 @@ impl ProgramInput {
             i += 1;
         }
 
-        if builder.input_filename == None {
+        if builder.input_filename.is_none() {
             return ParseResult::ThereIsNoInputFilename;
         }
+        if builder.fragment_sizes.is_empty() && builder.parts.is_none() {
+            builder.fragment_sizes.push(DEFAULT_FRAGMENT_SIZE);
+        }
 
         return ParseResult::Success(
             ProgramInput{
-                to_split: builder.to_split,
+                command: builder.command,
                 input_filename: builder.input_filename.unwrap(), 
-                fragment_size: builder.fragment_size,
+                fragment_sizes: builder.fragment_sizes.clone(),
+                rest_part: builder.rest_part,
+                strategy: builder.strategy,
+                boundary: builder.boundary.clone(),
+                csv_header: builder.csv_header,
                 output_directory: builder.output_directory.clone(),
+                output_file: builder.output_file.clone(),
                 parts: builder.parts,
-                part_number: builder.part_number
+                part_number: builder.part_number,
+                json: builder.json,
+                dry_run: builder.dry_run,
+                consume_source: builder.consume_source,
+                consume_parts: builder.consume_parts,
+                zero_copy: builder.zero_copy,
+                io: builder.io,
+                fsync: builder.fsync,
+                direct: builder.direct,
+                buffer_size: builder.buffer_size,
+                store: builder.store.clone(),
+                telegram: builder.telegram,
+                chat: builder.chat.clone(),
+                telegram_api: builder.telegram_api.clone(),
+                manifest_message: builder.manifest_message,
+                on_part_done: builder.on_part_done.clone(),
+                wait_for_hook: builder.wait_for_hook,
+                delete_after_hook: builder.delete_after_hook,
+                max_pending: builder.max_pending,
+                outbox: builder.outbox.clone(),
+                state_file: builder.state_file.clone(),
+                status_file: builder.status_file.clone(),
+                settle: builder.settle,
+                follow: builder.follow,
+                follow_timeout: builder.follow_timeout,
+                undo: builder.undo,
+                output_format: builder.output_format,
+                clobber: builder.clobber
             }
         );
     }
 
-    fn handle_argument(key: &String, value: &String, builder: &mut ProgramInputBuilder) -> ParseResult {
-        match key.as_str() {
+    fn handle_argument(key_os: &OsStr, value_os: &OsStr, builder: &mut ProgramInputBuilder) -> ParseResult {
+        let key = &key_os.to_string_lossy().to_string();
+        let value = &value_os.to_string_lossy().to_string();
+
+        if let (Some(command), Some(option)) = (builder.subcommand, cli::find_option(key)) {
+            if !option.commands.contains(&command.name()) {
+                return ParseResult::OptionIsNotAllowed(key.clone(), command);
+            }
+        }
+
+        match key_os.to_str().unwrap_or("") {
             "-S" | "--fragment-size" => {
                 if value.is_empty() {
                     return ParseResult::ThereIsNoValue(key.clone());
                 }
-                builder.fragment_size = match parse_memory_value(&value) {
-                    Ok(v) => v,
-                    Err(_) => return ParseResult::MemoryValueCannotBeParsed(value.clone()),
+                // `4g,4g,1g,rest` gives every part its own size
+                let entries: Vec<&str> = value.split(',').collect();
+                builder.fragment_sizes.clear();
+                builder.rest_part = false;
+                for (i, entry) in entries.iter().enumerate() {
+                    if entry.trim() == "rest" {
+                        if i == 0 || i + 1 < entries.len() {
+                            return ParseResult::RestShouldBeLast(value.to_string());
+                        }
+                        builder.rest_part = true;
+                        continue;
+                    }
+                    let fragment_size = match parse_fragment_size(entry) {
+                        Ok(v) => v,
+                        Err(err) => return ParseResult::MemoryValueCannotBeParsed(entry.to_string(), err),
+                    };
+                    if fragment_size < MINIMUM_FRAGMENT_SIZE {
+                        let chars: Vec<char> = value.chars().collect();
+                        let is_decimal_comma = chars.windows(3).any(|c| c[0].is_ascii_digit() && c[1] == ',' && c[2].is_ascii_digit());
+                        if entries.len() > 1 && is_decimal_comma {
+                            return ParseResult::CommaIsNotDecimal(value.to_string());
+                        }
+                        return ParseResult::FragmentSizeIsToSmall(MINIMUM_FRAGMENT_SIZE);
+                    };
+                    builder.fragment_sizes.push(fragment_size);
+                }
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--boundary" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.boundary = match Boundary::from_name(value) {
+                    Some(boundary) => boundary,
+                    None => return ParseResult::UnknownBoundary(value.to_string()),
                 };
-                if builder.fragment_size < MINIMUM_FRAGMENT_SIZE {
-                    return ParseResult::FragmentSizeIsToSmall(MINIMUM_FRAGMENT_SIZE);
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--csv-header" => {
+                builder.csv_header = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--strategy" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.strategy = match Strategy::from_name(value) {
+                    Some(strategy) => strategy,
+                    None => return ParseResult::UnknownStrategy(value.to_string()),
                 };
                 return ParseResult::SuccessfulHandledArgument;
             },
@@ -183,7 +627,7 @@ impl ProgramInput {
                     builder.parts = Some(number);
                     return ParseResult::SuccessfulHandledArgument;
                 } else {
-                    return ParseResult::NumberOfPartsCannotBeParsed(value.clone());
+                    return ParseResult::NumberOfPartsCannotBeParsed(value.to_string());
                 }
             },
             "-N" | "--part-number" => {
@@ -197,30 +641,228 @@ impl ProgramInput {
                     builder.part_number = Some(number);
                     return ParseResult::SuccessfulHandledArgument;
                 } else {
-                    return ParseResult::NumberOfPartsCannotBeParsed(value.clone());
+                    return ParseResult::NumberOfPartsCannotBeParsed(value.to_string());
                 }
             },
             "-o" | "--output-directory" => {
                 if value.is_empty() {
                     return ParseResult::ThereIsNoValue(key.clone());
                 }
-                builder.output_directory = Some(value.clone());
+                builder.output_directory = Some(PathBuf::from(value_os));
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--force" => {
+                builder.clobber = ClobberPolicy::Force;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--no-clobber" => {
+                builder.clobber = ClobberPolicy::NoClobber;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--backup" => {
+                builder.clobber = ClobberPolicy::Backup;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--json" => {
+                builder.json = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--output-format" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.output_format = match OutputFormat::from_name(value) {
+                    Some(format) => format,
+                    None => return ParseResult::UnknownOutputFormat(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--consume-source" => {
+                builder.consume_source = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--consume-parts" => {
+                builder.consume_parts = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--io" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.io = match IoBackend::from_name(value) {
+                    Some(io) => io,
+                    None => return ParseResult::UnknownIoBackend(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--fsync" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.fsync = match FsyncPolicy::from_name(value) {
+                    Some(fsync) => fsync,
+                    None => return ParseResult::UnknownFsyncPolicy(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--direct" => {
+                builder.direct = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--buffer-size" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.buffer_size = match parse_memory_value(value) {
+                    Ok(v) => v,
+                    Err(err) => return ParseResult::BufferSizeCannotBeParsed(value.to_string(), err),
+                };
+                if builder.buffer_size < MINIMUM_BUFFER_SIZE {
+                    return ParseResult::BufferSizeIsTooSmall(MINIMUM_BUFFER_SIZE);
+                }
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--store" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.store = match StoreLocation::parse(value) {
+                    Some(store) => Some(store),
+                    None => return ParseResult::UnknownStore(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--telegram" => {
+                builder.telegram = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--chat" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.chat = Some(value.to_string());
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--telegram-api" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.telegram_api = Some(value.trim_end_matches('/').to_string());
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--manifest-message" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.manifest_message = match value.parse::<i64>() {
+                    Ok(id) if id > 0 => Some(id),
+                    _ => return ParseResult::MessageIdCannotBeParsed(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--on-part-done" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.on_part_done = Some(value.to_string());
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--wait-for-hook" => {
+                builder.wait_for_hook = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--delete-after-hook" => {
+                builder.delete_after_hook = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--max-pending" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.max_pending = match value.parse::<usize>() {
+                    Ok(number) => Some(number),
+                    Err(_) => return ParseResult::MaxPendingCannotBeParsed(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--outbox" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.outbox = Some(PathBuf::from(value_os));
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--state-file" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.state_file = Some(PathBuf::from(value_os));
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--status-file" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.status_file = Some(PathBuf::from(value_os));
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--settle" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.settle = match parse_duration(value) {
+                    Some(duration) => duration,
+                    None => return ParseResult::DurationCannotBeParsed(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--no-zero-copy" => {
+                builder.zero_copy = false;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--undo" => {
+                builder.undo = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--follow" | "--wait" => {
+                builder.follow = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--follow-timeout" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.follow_timeout = match parse_duration(value) {
+                    Some(duration) => Some(duration),
+                    None => return ParseResult::DurationCannotBeParsed(value.to_string()),
+                };
+                return ParseResult::SuccessfulHandledArgument;
+            },
+            "--dry-run" => {
+                builder.dry_run = true;
+                return ParseResult::SuccessfulHandledFlag;
+            },
+            "--output-file" => {
+                if value.is_empty() {
+                    return ParseResult::ThereIsNoValue(key.clone());
+                }
+                builder.output_file = Some(PathBuf::from(value_os));
                 return ParseResult::SuccessfulHandledArgument;
             },
             "-m" | "--merge" => {
-                builder.to_split = false;
+                builder.command = Command::Merge;
                 return ParseResult::SuccessfulHandledFlag;
             },
             "-s" | "--split" => {
-                builder.to_split = true;
+                builder.command = Command::Split;
                 return ParseResult::SuccessfulHandledFlag;
             },
             "-h" | "--help" => {
-                return ParseResult::Help;
+                return ParseResult::Help(builder.subcommand.map(|command| command.name().to_string()));
             },
             _ => {
-                if builder.input_filename == None {
-                    builder.input_filename = Some(key.clone());
+                if builder.input_filename.is_none() {
+                    builder.input_filename = Some(PathBuf::from(key_os));
                     return ParseResult::SuccessfulHandledFlag;
                 }
                 println!("Warning: unknown argument - {}", key);
@@ -229,3 +871,143 @@ impl ProgramInput {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> ParseResult {
+        let arguments: Vec<OsString> = std::iter::once("splimer").chain(arguments.iter().copied()).map(OsString::from).collect();
+        return ProgramInput::parse(&arguments);
+    }
+
+    #[test]
+    fn binary_units() {
+        assert_eq!(parse_memory_value("1024"), Ok(1024));
+        assert_eq!(parse_memory_value("1b"), Ok(1));
+        assert_eq!(parse_memory_value("1k"), Ok(1 << 10));
+        assert_eq!(parse_memory_value("1m"), Ok(1 << 20));
+        assert_eq!(parse_memory_value("2g"), Ok(2 << 30));
+        assert_eq!(parse_memory_value("1T"), Ok(1 << 40));
+        assert_eq!(parse_memory_value("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_memory_value("1gib"), Ok(1 << 30));
+    }
+
+    #[test]
+    fn lowercase_b_keeps_units_binary() {
+        assert_eq!(parse_memory_value("1kb"), Ok(1 << 10));
+        assert_eq!(parse_memory_value("1mb"), Ok(1 << 20));
+        assert_eq!(parse_memory_value("1gb"), Ok(1 << 30));
+        assert_eq!(parse_memory_value("1tb"), Ok(1 << 40));
+        assert_eq!(parse_memory_value("1Mb"), Ok(1 << 20));
+    }
+
+    #[test]
+    fn capital_b_makes_units_decimal() {
+        assert_eq!(parse_memory_value("1kB"), Ok(1_000));
+        assert_eq!(parse_memory_value("1KB"), Ok(1_000));
+        assert_eq!(parse_memory_value("1MB"), Ok(1_000_000));
+        assert_eq!(parse_memory_value("20MB"), Ok(20_000_000));
+        assert_eq!(parse_memory_value("1GB"), Ok(1_000_000_000));
+        assert_eq!(parse_memory_value("1TB"), Ok(1_000_000_000_000));
+        // Only the `B` counts, not the case of the letter before it
+        assert_eq!(parse_memory_value("1mB"), Ok(1_000_000));
+        assert_eq!(parse_memory_value("1gB"), Ok(1_000_000_000));
+        assert_eq!(parse_memory_value("1Mb"), Ok(1 << 20));
+        assert_eq!(parse_memory_value("1Gb"), Ok(1 << 30));
+        assert_eq!(parse_memory_value("1B"), Ok(1));
+        assert_eq!(parse_memory_value("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_memory_value("1miB"), Ok(1 << 20));
+    }
+
+    #[test]
+    fn fractions_and_grouping() {
+        assert_eq!(parse_memory_value("1.5k"), Ok(1536));
+        assert_eq!(parse_memory_value("0.5"), Ok(0));
+        assert_eq!(parse_memory_value("1.9999"), Ok(1));
+        assert_eq!(parse_memory_value("0.1MB"), Ok(100_000));
+        assert_eq!(parse_memory_value("1_000_000"), Ok(1_000_000));
+        assert_eq!(parse_memory_value("1 000 000"), Ok(1_000_000));
+        assert_eq!(parse_memory_value("  10 m  "), Ok(10 << 20));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_memory_value(""), Err(MemoryValueError::Empty));
+        assert_eq!(parse_memory_value("   "), Err(MemoryValueError::Empty));
+        assert_eq!(parse_memory_value("m"), Err(MemoryValueError::UnexpectedCharacter(0, 'm')));
+        assert_eq!(parse_memory_value("10m5"), Err(MemoryValueError::UnexpectedCharacter(3, '5')));
+        assert_eq!(parse_memory_value("1.2.3"), Err(MemoryValueError::UnexpectedCharacter(3, '.')));
+        assert_eq!(parse_memory_value("1,5k"), Err(MemoryValueError::UnexpectedCharacter(1, ',')));
+        assert_eq!(parse_memory_value("1__0"), Err(MemoryValueError::UnexpectedCharacter(1, '_')));
+        assert_eq!(parse_memory_value("10 zb"), Err(MemoryValueError::UnknownUnit(3, "zb".to_string())));
+        assert_eq!(parse_memory_value("99999999999999999999999999999999999999999"), Err(MemoryValueError::Overflow));
+        assert_eq!(parse_memory_value("99999999999999999999t"), Err(MemoryValueError::Overflow));
+        assert_eq!(MemoryValueError::UnknownUnit(3, "zb".to_string()).position(), Some(3));
+        assert_eq!(MemoryValueError::Overflow.position(), None);
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(parse_fragment_size("telegram"), Ok(2000 * 1024 * 1024));
+        assert_eq!(parse_fragment_size(" Discord "), Ok(10_000_000));
+        assert_eq!(parse_fragment_size("fat32"), Ok(4 * 1024 * 1024 * 1024 - 1));
+        assert_eq!(parse_fragment_size("100m"), Ok(100 << 20));
+        assert_eq!(parse_size_preset("100m"), None);
+        for (name, value, _) in SIZE_PRESETS {
+            assert_eq!(parse_fragment_size(name), Ok(*value as usize));
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_duration(" 2h "), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("1d"), None);
+        assert_eq!(parse_duration("inf"), None);
+    }
+
+    #[test]
+    fn size_lists() {
+        match parse(&["split", "file.bin", "-S", "1MB,1m,rest"]) {
+            ParseResult::Success(input) => {
+                assert_eq!(input.fragment_sizes, vec![1_000_000, 1 << 20]);
+                assert!(input.rest_part);
+            },
+            _ => panic!("size list is not parsed"),
+        }
+        assert!(matches!(parse(&["split", "file.bin", "-S", "rest,1m"]), ParseResult::RestShouldBeLast(_)));
+        match parse(&["split", "file.bin", "-S", "1000000,2000000"]) {
+            ParseResult::Success(input) => assert_eq!(input.fragment_sizes, vec![1_000_000, 2_000_000]),
+            _ => panic!("size list is not parsed"),
+        }
+        // A fraction with a comma is told apart from a list with a size which is too small
+        assert!(matches!(parse(&["split", "file.bin", "-S", "1,5k"]), ParseResult::CommaIsNotDecimal(value) if value == "1,5k"));
+        assert!(matches!(parse(&["split", "file.bin", "-S", "1.5k"]), ParseResult::Success(input) if input.fragment_sizes == vec![1536]));
+        assert!(matches!(parse(&["split", "file.bin", "-S", "1m,10"]), ParseResult::FragmentSizeIsToSmall(MINIMUM_FRAGMENT_SIZE)));
+        assert!(matches!(parse(&["split", "file.bin", "-S", "1 zb"]), ParseResult::MemoryValueCannotBeParsed(_, MemoryValueError::UnknownUnit(2, _))));
+        assert!(matches!(parse(&["split", "file.bin", "-S", "10"]), ParseResult::FragmentSizeIsToSmall(MINIMUM_FRAGMENT_SIZE)));
+    }
+
+    #[test]
+    fn io_options() {
+        match parse(&["split", "file.bin", "--fsync", "end", "--direct", "--buffer-size", "4k"]) {
+            ParseResult::Success(input) => {
+                assert_eq!(input.fsync, FsyncPolicy::End);
+                assert!(input.direct);
+                assert_eq!(input.buffer_size, 4096);
+            },
+            _ => panic!("io options are not parsed"),
+        }
+        assert!(matches!(parse(&["merge", "file.bin", "--fsync", "never"]), ParseResult::Success(input) if input.fsync == FsyncPolicy::Never && !input.direct));
+        assert!(matches!(parse(&["split", "file.bin", "--fsync", "always"]), ParseResult::UnknownFsyncPolicy(value) if value == "always"));
+        assert!(matches!(parse(&["split", "file.bin", "--buffer-size", "4095"]), ParseResult::BufferSizeIsTooSmall(MINIMUM_BUFFER_SIZE)));
+        assert!(matches!(parse(&["split", "file.bin", "--buffer-size", "4 zb"]), ParseResult::BufferSizeCannotBeParsed(_, MemoryValueError::UnknownUnit(2, _))));
+    }
+}
@@ -0,0 +1,162 @@
+// File names are kept as `OsStr` all the way, so names which are not UTF-8 survive split and merge
+
+use std::ffi::{OsStr, OsString};
+
+// Second extension which is kept together with the last one, `game.tar.gz` -> (`game`, `.tar.gz`)
+const COMPOUND_EXTENSIONS: &[&str] = &["tar"];
+
+// Splits an encoded name at an ASCII character, so both halves are valid `OsStr`
+fn split_at_ascii(name: &OsStr, index: usize) -> (OsString, OsString) {
+    let bytes = name.as_encoded_bytes();
+    // SAFETY: `index` is a position of an ASCII byte or the end, and `OsStr` can be split there
+    unsafe {
+        return (
+            OsStr::from_encoded_bytes_unchecked(&bytes[..index]).to_os_string(),
+            OsStr::from_encoded_bytes_unchecked(&bytes[index..]).to_os_string(),
+        );
+    }
+}
+
+// `game.zip` -> (`game`, `.zip`), `game.tar.gz` -> (`game`, `.tar.gz`),
+// `README` -> (`README`, ``), `.bashrc` -> (`.bashrc`, ``)
+pub fn split_extension(name: &OsStr) -> (OsString, OsString) {
+    let bytes = name.as_encoded_bytes();
+    let Some(dot) = bytes.iter().rposition(|&byte| byte == b'.').filter(|&dot| dot > 0) else {
+        return (name.to_os_string(), OsString::new());
+    };
+
+    let mut start = dot;
+    if let Some(previous) = bytes[..dot].iter().rposition(|&byte| byte == b'.').filter(|&previous| previous > 0) {
+        let extension = &bytes[previous + 1..dot];
+        if COMPOUND_EXTENSIONS.iter().any(|compound| compound.as_bytes().eq_ignore_ascii_case(extension)) {
+            start = previous;
+        }
+    }
+    return split_at_ascii(name, start);
+}
+
+// `name_[tag].splm` -> (`name`, `tag`)
+pub fn split_tagged_name(name: &OsStr) -> Option<(OsString, String)> {
+    let bytes = name.as_encoded_bytes();
+    let rest = bytes.strip_suffix(b"].splm")?;
+    let open = rest.windows(2).rposition(|pair| pair == b"_[")?;
+    let tag = std::str::from_utf8(&rest[open + 2..]).ok()?.to_string();
+    let (stem, _) = split_at_ascii(name, open);
+    return Some((stem, tag));
+}
+
+pub fn concat(parts: &[&OsStr]) -> OsString {
+    let mut result = OsString::new();
+    for part in parts {
+        result.push(part);
+    }
+    return result;
+}
+
+// Bytes of names which are not printable UTF-8 are written as `%XX`, so they fit into a text line
+pub fn encode(name: &OsStr) -> String {
+    let mut text = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '%' || c.is_control() {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    text += &format!("%{:02X}", byte);
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            text += &format!("%{:02X}", byte);
+        }
+    }
+    return text;
+}
+
+pub fn decode(text: &str) -> Option<OsString> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    return os_string_from_bytes(bytes);
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    return Some(OsString::from_vec(bytes));
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    return String::from_utf8(bytes).ok().map(OsString::from);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(name: &str) -> (String, String) {
+        let (stem, extension) = split_extension(OsStr::new(name));
+        return (stem.into_string().unwrap(), extension.into_string().unwrap());
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(split("game.zip"), ("game".to_string(), ".zip".to_string()));
+        assert_eq!(split("backup.tar.gz"), ("backup".to_string(), ".tar.gz".to_string()));
+        assert_eq!(split("backup.TAR.xz"), ("backup".to_string(), ".TAR.xz".to_string()));
+        assert_eq!(split("archive.v2.zip"), ("archive.v2".to_string(), ".zip".to_string()));
+        assert_eq!(split("README"), ("README".to_string(), String::new()));
+        assert_eq!(split(".bashrc"), (".bashrc".to_string(), String::new()));
+        assert_eq!(split(".config.json"), (".config".to_string(), ".json".to_string()));
+    }
+
+    #[test]
+    fn tagged_names() {
+        assert_eq!(split_tagged_name(OsStr::new("game.zip_[2].splm")), Some((OsString::from("game.zip"), "2".to_string())));
+        assert_eq!(split_tagged_name(OsStr::new("a_[b]_[manifest].splm")), Some((OsString::from("a_[b]"), "manifest".to_string())));
+        assert_eq!(split_tagged_name(OsStr::new("_[1].splm")), Some((OsString::new(), "1".to_string())));
+        assert_eq!(split_tagged_name(OsStr::new("game.zip_[2].spl")), None);
+        assert_eq!(split_tagged_name(OsStr::new("game.zip[2].splm")), None);
+        assert_eq!(split_tagged_name(OsStr::new("game.zip")), None);
+    }
+
+    #[test]
+    fn encoded_names() {
+        assert_eq!(encode(OsStr::new("plain name.txt")), "plain name.txt");
+        assert_eq!(encode(OsStr::new("100%.txt")), "100%25.txt");
+        assert_eq!(encode(OsStr::new("new\nline")), "new%0Aline");
+        assert_eq!(encode(OsStr::new("файл.txt")), "файл.txt");
+        for name in ["plain", "100%", "new\nline", "tab\there", "файл"] {
+            assert_eq!(decode(&encode(OsStr::new(name))), Some(OsString::from(name)));
+        }
+        assert_eq!(decode("bad%2"), None);
+        assert_eq!(decode("bad%zz"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_which_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        assert_eq!(encode(name), "caf%E9.txt");
+        assert_eq!(decode("caf%E9.txt").as_deref(), Some(name));
+
+        let tagged = OsStr::from_bytes(b"caf\xe9_[3].splm");
+        assert_eq!(split_tagged_name(tagged), Some((OsStr::from_bytes(b"caf\xe9").to_os_string(), "3".to_string())));
+        let (stem, extension) = split_extension(OsStr::from_bytes(b"\xff\xfe.bin"));
+        assert_eq!(stem.as_bytes(), b"\xff\xfe");
+        assert_eq!(extension.as_bytes(), b".bin");
+    }
+}
@@ -0,0 +1,382 @@
+// Split is planned before anything is written: which parts are made, where each one starts
+// and how large it is. `--dry-run` prints the plan, a real split just follows it.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::json;
+
+pub struct PlannedPart {
+    pub index: usize,
+    pub filename: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Strategy {
+    // Every part is as large as it may be, the last one takes what is left
+    Fixed,
+    // Parts of the same size limit differ by one byte at most
+    Balanced,
+    // A small last part is put into the one before it, if that one still fits its limit
+    MinLast,
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        return match name {
+            "fixed" => Some(Strategy::Fixed),
+            "balanced" => Some(Strategy::Balanced),
+            "min-last" => Some(Strategy::MinLast),
+            _ => None,
+        };
+    }
+}
+
+// How large parts may be: `-S 4g,4g,1g,rest` gives every part its own limit
+// and `--parts` gives the number of parts, together they are both kept
+pub struct SizeSpec {
+    // Limit of every part in turn, the last one is repeated. Empty when there is no limit
+    pub limits: Vec<u64>,
+    // Part after the listed ones takes everything that is left
+    pub rest: bool,
+    pub parts: Option<usize>,
+    pub strategy: Strategy,
+}
+
+impl SizeSpec {
+    // Limit of the part at `position`, counted from 0
+    fn limit(&self, position: usize) -> u64 {
+        return match self.limits.get(position) {
+            Some(&limit) => limit,
+            None if self.rest => u64::MAX,
+            None => self.limits.last().copied().unwrap_or(u64::MAX),
+        };
+    }
+
+    // Size of the part at `position` when it is full, `--parts` may make it less than the limit
+    pub fn capacity(&self, position: usize, file_size: u64) -> u64 {
+        let part_size = self.parts.map(|parts| file_size.div_ceil(parts as u64)).unwrap_or(u64::MAX);
+        return self.limit(position).min(part_size).max(1);
+    }
+
+    pub fn part_sizes(&self, file_size: u64) -> Vec<u64> {
+        let capacity = |position: usize| self.capacity(position, file_size);
+        let mut sizes = Vec::new();
+        let mut left = file_size;
+        while left > 0 {
+            let size = capacity(sizes.len()).min(left);
+            sizes.push(size);
+            left -= size;
+        }
+        if sizes.len() < 2 {
+            return sizes;
+        }
+
+        let last = sizes.len() - 1;
+        match self.strategy {
+            Strategy::Fixed => { },
+            Strategy::Balanced => {
+                // Bytes are spread over the trailing parts which have the same limit
+                let mut first = last;
+                while first > 0 && capacity(first - 1) == capacity(last) {
+                    first -= 1;
+                }
+                let mut count = sizes.len() - first;
+                if first == 0 {
+                    count = count.max(self.parts.unwrap_or(0));
+                }
+                let total: u64 = sizes[first..].iter().sum();
+                sizes.truncate(first);
+                sizes.extend((0..count as u64).map(|i| total / count as u64 + u64::from(i < total % count as u64)));
+            },
+            Strategy::MinLast => {
+                // The last part is too small when it is less than half of a full one
+                if sizes[last] >= capacity(last) / 2 {
+                    return sizes;
+                }
+                let total = sizes[last - 1] + sizes[last];
+                if total <= self.limit(last - 1) {
+                    sizes.pop();
+                    sizes[last - 1] = total;
+                    return sizes;
+                }
+                // Otherwise the last two parts share their bytes, as evenly as the previous part's limit allows
+                let tail = (total / 2).min(capacity(last)).max(total - capacity(last - 1));
+                sizes[last - 1] = total - tail;
+                sizes[last] = tail;
+            },
+        }
+        return sizes;
+    }
+}
+
+pub struct SplitPlan {
+    pub file_size: u64,
+    // Largest of the parts
+    pub fragment_size: u64,
+    pub part_count: usize,
+    // Sizes of all parts of the file, also of those which are not made by this run
+    pub part_sizes: Vec<u64>,
+    // Parts made by this run, all of them or only one with `--part-number`
+    pub parts: Vec<PlannedPart>,
+    pub manifest_filename: PathBuf,
+    pub manifest_size: u64,
+    // Bytes which split adds to every part, like archive headers and trailers or a repeated CSV header
+    pub added_sizes: Vec<u64>,
+    // With `--consume-source` the file shrinks as parts are made
+    pub in_place: bool,
+}
+
+pub enum PlanError {
+    FileIsAlreadySmall(u64),
+    NoSuchPart(usize, usize),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::FileIsAlreadySmall(fragment_size) => write!(f, "it is already less than {} kB", fragment_size / 1024),
+            PlanError::NoSuchPart(part_number, part_count) => write!(f, "Cannot generate {}{} part because there will be {} part{} in total",
+                part_number,
+                match part_number {
+                    1 => "st",
+                    2 => "nd",
+                    3 => "rd",
+                    _ => "th"
+                },
+                part_count,
+                if *part_count == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+impl SplitPlan {
+    pub fn new(
+        file_size: u64,
+        spec: &SizeSpec,
+        part_number: Option<usize>,
+        part_filename: impl Fn(usize) -> PathBuf,
+        manifest_filename: PathBuf
+    ) -> Result<SplitPlan, PlanError> {
+        let first_size = spec.capacity(0, file_size);
+        if file_size < first_size {
+            return Err(PlanError::FileIsAlreadySmall(first_size));
+        }
+        return Self::from_sizes(file_size, spec.part_sizes(file_size), part_number, part_filename, manifest_filename);
+    }
+
+    // Parts of the given sizes one after another, like they are recorded in a manifest
+    pub fn from_sizes(
+        file_size: u64,
+        part_sizes: Vec<u64>,
+        part_number: Option<usize>,
+        part_filename: impl Fn(usize) -> PathBuf,
+        manifest_filename: PathBuf
+    ) -> Result<SplitPlan, PlanError> {
+        let part_count = part_sizes.len();
+        let indexes = match part_number {
+            Some(part_number) if part_number > part_count => return Err(PlanError::NoSuchPart(part_number, part_count)),
+            Some(part_number) => part_number..=part_number,
+            None => 1..=part_count,
+        };
+
+        let parts = indexes
+            .map(|index| {
+                PlannedPart{
+                    index,
+                    filename: part_filename(index),
+                    offset: part_sizes[..index - 1].iter().sum(),
+                    size: part_sizes[index - 1],
+                }
+            })
+            .collect();
+
+        return Ok(SplitPlan{
+            file_size,
+            fragment_size: part_sizes.iter().copied().max().unwrap_or(0),
+            part_count,
+            part_sizes,
+            parts,
+            manifest_filename,
+            manifest_size: 0,
+            added_sizes: Vec::new(),
+            in_place: false,
+        });
+    }
+
+    // Size of the part file, the piece of the original with what split adds to it
+    pub fn file_size_of(&self, part: &PlannedPart) -> u64 {
+        return part.size + self.added_sizes.get(part.index - 1).copied().unwrap_or(0);
+    }
+
+    // Every part is written next to its final name and then renamed, so this much is taken at most,
+    // or only the largest part when the file gives its space back after every part
+    pub fn required_space(&self) -> u64 {
+        let sizes = self.parts.iter().map(|part| self.file_size_of(part));
+        if self.in_place {
+            return sizes.max().unwrap_or(0) + self.manifest_size;
+        }
+        return sizes.sum::<u64>() + self.manifest_size;
+    }
+
+    pub fn print(&self, file_name: &OsStr, available_space: Option<u64>, as_json: bool) {
+        if as_json {
+            let parts: Vec<String> = self.parts.iter()
+                .map(|part| json::object(&[
+                    ("index", part.index.to_string()),
+                    ("path", json::string(&part.filename.to_string_lossy())),
+                    ("offset", part.offset.to_string()),
+                    ("size", self.file_size_of(part).to_string()),
+                ]))
+                .collect();
+            println!("{}", json::object(&[
+                ("file_name", json::string(&file_name.to_string_lossy())),
+                ("file_size", self.file_size.to_string()),
+                ("fragment_size", self.fragment_size.to_string()),
+                ("part_count", self.part_count.to_string()),
+                ("manifest", json::string(&self.manifest_filename.to_string_lossy())),
+                ("in_place", self.in_place.to_string()),
+                ("required_space", self.required_space().to_string()),
+                ("available_space", available_space.map(|space| space.to_string()).unwrap_or("null".to_string())),
+                ("parts", json::array(&parts)),
+            ]));
+            return;
+        }
+
+        println!("File name:       {}", file_name.to_string_lossy());
+        println!("File size:       {} bytes", self.file_size);
+        println!("Fragment size:   {} bytes", self.fragment_size);
+        println!("Parts:           {}{}",
+            self.part_count,
+            if self.parts.len() == self.part_count { String::new() } else { format!(", {} of them is written", self.parts.len()) }
+        );
+        println!("Manifest:        {}", self.manifest_filename.display());
+        println!("Space needed:    {} bytes{}{}",
+            self.required_space(),
+            if self.in_place { ", the file is consumed" } else { "" },
+            available_space.map(|space| format!(", {} bytes available", space)).unwrap_or_default()
+        );
+        println!();
+
+        let path_width = self.parts.iter().map(|part| part.filename.display().to_string().chars().count()).max().unwrap_or(0);
+        println!("{:>5}  {:<path_width$}  {:>14}  {:>14}", "Part", "Path", "Offset", "Size", path_width = path_width);
+        for part in &self.parts {
+            println!("{:>5}  {:<path_width$}  {:>14}  {:>14}",
+                part.index,
+                part.filename.display(),
+                part.offset,
+                self.file_size_of(part),
+                path_width = path_width
+            );
+        }
+    }
+}
+
+// Space left for an ordinary user on the file system where `directory` lies
+#[cfg(unix)]
+pub fn available_space(directory: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // The directory may not be made yet, then the nearest existing one above it is asked
+    let directory = directory.ancestors()
+        .find(|directory| !directory.as_os_str().is_empty() && directory.exists())
+        .unwrap_or(Path::new("."));
+    let path = CString::new(directory.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid C string and `stat` is a valid place to write the result
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(stat.f_bavail as u64 * stat.f_frsize as u64);
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_directory: &Path) -> io::Result<u64> {
+    return Err(io::Error::new(io::ErrorKind::Unsupported, "free space is not known on this platform"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(limits: &[u64], rest: bool, parts: Option<usize>, strategy: Strategy) -> SizeSpec {
+        return SizeSpec{ limits: limits.to_vec(), rest, parts, strategy };
+    }
+
+    #[test]
+    fn fixed() {
+        assert_eq!(spec(&[100], false, None, Strategy::Fixed).part_sizes(250), vec![100, 100, 50]);
+        assert_eq!(spec(&[100], false, None, Strategy::Fixed).part_sizes(300), vec![100, 100, 100]);
+        assert_eq!(spec(&[100], false, None, Strategy::Fixed).part_sizes(50), vec![50]);
+        assert_eq!(spec(&[], false, Some(4), Strategy::Fixed).part_sizes(10), vec![3, 3, 3, 1]);
+        assert_eq!(spec(&[], false, None, Strategy::Fixed).part_sizes(0), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn balanced() {
+        assert_eq!(spec(&[100], false, None, Strategy::Balanced).part_sizes(250), vec![84, 83, 83]);
+        assert_eq!(spec(&[100], false, None, Strategy::Balanced).part_sizes(201), vec![67, 67, 67]);
+        assert_eq!(spec(&[], false, Some(4), Strategy::Balanced).part_sizes(10), vec![3, 3, 2, 2]);
+        // Only the parts of the last limit are evened out
+        assert_eq!(spec(&[100, 50], false, None, Strategy::Balanced).part_sizes(230), vec![100, 44, 43, 43]);
+    }
+
+    #[test]
+    fn min_last() {
+        // A tail of half a part or more is kept
+        assert_eq!(spec(&[100], false, None, Strategy::MinLast).part_sizes(280), vec![100, 100, 80]);
+        // A full previous part cannot take the tail, so the two share their bytes
+        assert_eq!(spec(&[100], false, None, Strategy::MinLast).part_sizes(210), vec![100, 55, 55]);
+        assert_eq!(spec(&[100], false, None, Strategy::MinLast).part_sizes(101), vec![51, 50]);
+        // With `--parts` the previous part is below its limit and takes the tail
+        assert_eq!(spec(&[], false, Some(5), Strategy::MinLast).part_sizes(21), vec![5, 5, 5, 6]);
+        // Sharing never makes the previous part larger than its own limit
+        assert_eq!(spec(&[100, 30], false, None, Strategy::MinLast).part_sizes(161), vec![100, 30, 16, 15]);
+    }
+
+    #[test]
+    fn size_lists() {
+        let spec = spec(&[400, 400, 100], true, None, Strategy::Fixed);
+        assert_eq!(spec.part_sizes(1500), vec![400, 400, 100, 600]);
+        assert_eq!(spec.part_sizes(850), vec![400, 400, 50]);
+        assert_eq!(spec.capacity(3, 1500), u64::MAX);
+
+        let repeated = SizeSpec{ limits: vec![400, 100], rest: false, parts: None, strategy: Strategy::Fixed };
+        assert_eq!(repeated.part_sizes(650), vec![400, 100, 100, 50]);
+        // The rest part is never evened out with the listed ones
+        let balanced = SizeSpec{ limits: vec![400, 400], rest: true, parts: None, strategy: Strategy::Balanced };
+        assert_eq!(balanced.part_sizes(1000), vec![400, 400, 200]);
+    }
+
+    #[test]
+    fn plans() {
+        let name = |index: usize| PathBuf::from(format!("file_[{}].splm", index));
+        let plan = SplitPlan::new(250, &spec(&[100], false, None, Strategy::Fixed), None, name, PathBuf::from("m")).ok().unwrap();
+        assert_eq!(plan.part_count, 3);
+        assert_eq!(plan.fragment_size, 100);
+        assert_eq!(plan.parts.iter().map(|part| (part.index, part.offset, part.size)).collect::<Vec<_>>(), vec![(1, 0, 100), (2, 100, 100), (3, 200, 50)]);
+        assert_eq!(plan.parts[2].filename, PathBuf::from("file_[3].splm"));
+        assert_eq!(plan.required_space(), 250);
+
+        // Headers and trailers of archive parts take space too
+        let mut plan = SplitPlan::new(250, &spec(&[100], false, None, Strategy::Fixed), None, name, PathBuf::from("m")).ok().unwrap();
+        plan.added_sizes = vec![10, 20, 30];
+        assert_eq!(plan.file_size_of(&plan.parts[1]), 120);
+        assert_eq!(plan.required_space(), 310);
+        plan.in_place = true;
+        assert_eq!(plan.required_space(), 120);
+
+        let plan = SplitPlan::new(250, &spec(&[100], false, None, Strategy::Fixed), Some(2), name, PathBuf::from("m")).ok().unwrap();
+        assert_eq!(plan.parts.len(), 1);
+        assert_eq!((plan.parts[0].offset, plan.parts[0].size), (100, 100));
+
+        assert!(matches!(SplitPlan::new(50, &spec(&[100], false, None, Strategy::Fixed), None, name, PathBuf::from("m")), Err(PlanError::FileIsAlreadySmall(100))));
+        assert!(matches!(SplitPlan::new(250, &spec(&[100], false, None, Strategy::Fixed), Some(4), name, PathBuf::from("m")), Err(PlanError::NoSuchPart(4, 3))));
+    }
+}
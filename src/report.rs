@@ -0,0 +1,114 @@
+// Messages for people and events for programs.
+// With `--output-format=jsonl` every event is one JSON object on its own line in stdout,
+// like `{"event": "part_finished", "index": 1, ...}`, and messages for people go to stderr,
+// so scripts don't depend on their wording.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use crate::json;
+
+// `progress` events are not sent more often than this
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Text,
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        return match name {
+            "text" => Some(OutputFormat::Text),
+            "jsonl" => Some(OutputFormat::Jsonl),
+            _ => None,
+        };
+    }
+}
+
+pub struct Reporter {
+    format: OutputFormat,
+    last_progress: Cell<Option<Instant>>,
+    errors: Cell<usize>,
+}
+
+impl Reporter {
+    pub fn new(format: OutputFormat) -> Reporter {
+        if format == OutputFormat::Jsonl {
+            install_panic_hook();
+        }
+        return Reporter{
+            format,
+            last_progress: Cell::new(None),
+            errors: Cell::new(0),
+        };
+    }
+
+    pub fn message(&self, text: &str) {
+        match self.format {
+            OutputFormat::Text => println!("{}", text),
+            OutputFormat::Jsonl => eprintln!("{}", text),
+        }
+    }
+
+    pub fn event(&self, name: &str, fields: &[(&str, String)]) {
+        if self.format == OutputFormat::Jsonl {
+            println!("{}", event_line(name, fields));
+        }
+    }
+
+    pub fn progress(&self, fields: &[(&str, String)]) {
+        let now = Instant::now();
+        if self.last_progress.get().is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last_progress.set(Some(now));
+        self.event("progress", fields);
+    }
+
+    pub fn warning(&self, text: &str) {
+        self.message(&format!("Warning: {}", text));
+        self.event("warning", &[("message", json::string(text))]);
+    }
+
+    pub fn error(&self, text: &str) {
+        self.errors.set(self.errors.get() + 1);
+        self.message(&format!("Error: {}", text));
+        self.event("error", &[("message", json::string(text))]);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        return self.errors.get() > 0;
+    }
+}
+
+fn event_line(name: &str, fields: &[(&str, String)]) -> String {
+    let mut all_fields = vec![("event", json::string(name))];
+    all_fields.extend(fields.iter().cloned());
+    return json::object(&all_fields);
+}
+
+// What a panic says, like the error of `check_file_access`
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    return payload.downcast_ref::<String>().map(String::as_str)
+        .or(payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unexpected failure");
+}
+
+// Failures which stop the program are panics, a script still gets an `error` event for them.
+// Watch makes a reporter for every file, the hook is installed once
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let text = panic_message(info.payload());
+            println!("{}", event_line("error", &[("message", json::string(text))]));
+            default_hook(info);
+        }));
+    });
+}
@@ -0,0 +1,508 @@
+// SFTP version 3 spoken through `ssh -s host sftp`, so keys, agents and `~/.ssh/config` work as usual.
+// `SPLIMER_SSH` replaces the `ssh` command, like `ssh -i key -o BatchMode=yes`.
+// `sftp://host/path` is an absolute path, `sftp://host/~/path` is inside the home directory.
+// Writes are sent several at once and their answers are read later, so latency doesn't stall uploads.
+
+use std::cell::{RefCell, RefMut};
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+
+use crate::http::Url;
+use crate::output::ClobberPolicy;
+use crate::store::{self, PartStore, PartWriter, StoredFile};
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_RENAME: u8 = 18;
+const SSH_FXP_EXTENDED: u8 = 200;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+const SSH_FXF_READ: u32 = 0x01;
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x02;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x08;
+const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x8000_0000;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+const POSIX_RENAME: &str = "posix-rename@openssh.com";
+// Servers take at least 32 kB in one request
+const CHUNK_SIZE: usize = 32 * 1024;
+// Writes which are sent before the answer to the first of them is awaited
+const MAX_PENDING_WRITES: usize = 32;
+
+struct Session {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    next_id: u32,
+    // Answers which came while another one was awaited
+    early_answers: Vec<(u32, u8, Vec<u8>)>,
+    has_posix_rename: bool,
+}
+
+// Fields of a packet are read one after another
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "SFTP packet is too short"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        return Ok(taken);
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        return Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        return Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn string(&mut self) -> io::Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        return self.take(length);
+    }
+
+    // Size and whether it is a directory, other attributes are skipped
+    fn attributes(&mut self) -> io::Result<(Option<u64>, bool)> {
+        let flags = self.u32()?;
+        let size = if flags & SSH_FILEXFER_ATTR_SIZE != 0 { Some(self.u64()?) } else { None };
+        if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        let mut is_directory = false;
+        if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+            is_directory = self.u32()? & S_IFMT == S_IFDIR;
+        }
+        if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+            self.take(8)?;
+        }
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+        return Ok((size, is_directory));
+    }
+}
+
+fn put_u32(packet: &mut Vec<u8>, value: u32) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(packet: &mut Vec<u8>, value: u64) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(packet: &mut Vec<u8>, value: &[u8]) {
+    put_u32(packet, value.len() as u32);
+    packet.extend_from_slice(value);
+}
+
+// Status answer as a result, `what` tells which request it was
+fn status(kind: u8, body: &[u8], what: &str) -> io::Result<u32> {
+    if kind != SSH_FXP_STATUS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is answered with packet {}", what, kind)));
+    }
+    let mut fields = Fields{ bytes: body };
+    let code = fields.u32()?;
+    if code == SSH_FX_OK || code == SSH_FX_EOF {
+        return Ok(code);
+    }
+    let message = fields.string().map(|message| String::from_utf8_lossy(message).to_string()).unwrap_or_default();
+    let kind = match code {
+        SSH_FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+        SSH_FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    return Err(io::Error::new(kind, format!("{} failed: {}", what, message)));
+}
+
+impl Session {
+    fn start(url: &Url) -> io::Result<Session> {
+        let command = env::var("SPLIMER_SSH").unwrap_or("ssh".to_string());
+        let mut words = command.split_whitespace();
+        let mut ssh = Command::new(words.next().unwrap_or("ssh"));
+        ssh.args(words);
+        if url.port != 22 {
+            ssh.arg("-p").arg(url.port.to_string());
+        }
+        if let Some(user) = &url.user {
+            ssh.arg("-l").arg(user);
+        }
+        ssh.arg("-s").arg(&url.host).arg("sftp");
+        let mut child = ssh.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+
+        let mut session = Session{
+            input: child.stdin.take().unwrap(),
+            output: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            next_id: 0,
+            early_answers: Vec::new(),
+            has_posix_rename: false,
+        };
+        let mut version = Vec::new();
+        put_u32(&mut version, 3);
+        session.send(SSH_FXP_INIT, &version)?;
+        let (kind, body) = session.receive()?;
+        if kind != SSH_FXP_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("SFTP server of {} doesn't answer with its version", url.host)));
+        }
+        let mut fields = Fields{ bytes: &body };
+        fields.u32()?;
+        while !fields.bytes.is_empty() {
+            let name = fields.string()?;
+            fields.string()?;
+            session.has_posix_rename |= name == POSIX_RENAME.as_bytes();
+        }
+        return Ok(session);
+    }
+
+    fn send(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(payload.len() + 5);
+        put_u32(&mut packet, payload.len() as u32 + 1);
+        packet.push(kind);
+        packet.extend_from_slice(payload);
+        return self.input.write_all(&packet);
+    }
+
+    fn receive(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut length = [0; 4];
+        self.output.read_exact(&mut length)?;
+        let mut packet = vec![0; u32::from_be_bytes(length) as usize];
+        self.output.read_exact(&mut packet)?;
+        if packet.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "SFTP packet is empty"));
+        }
+        let body = packet.split_off(1);
+        return Ok((packet[0], body));
+    }
+
+    // Sends a request with a new id, its answer is awaited with `answer`
+    fn request(&mut self, kind: u8, fields: &[u8]) -> io::Result<u32> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut payload = Vec::with_capacity(fields.len() + 4);
+        put_u32(&mut payload, id);
+        payload.extend_from_slice(fields);
+        self.send(kind, &payload)?;
+        return Ok(id);
+    }
+
+    fn answer(&mut self, id: u32) -> io::Result<(u8, Vec<u8>)> {
+        if let Some(position) = self.early_answers.iter().position(|(early_id, _, _)| *early_id == id) {
+            let (_, kind, body) = self.early_answers.remove(position);
+            return Ok((kind, body));
+        }
+        loop {
+            let (kind, body) = self.receive()?;
+            let mut fields = Fields{ bytes: &body };
+            let answer_id = fields.u32()?;
+            let rest = fields.bytes.to_vec();
+            if answer_id == id {
+                return Ok((kind, rest));
+            }
+            self.early_answers.push((answer_id, kind, rest));
+        }
+    }
+
+    fn call(&mut self, kind: u8, fields: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let id = self.request(kind, fields)?;
+        return self.answer(id);
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> io::Result<Vec<u8>> {
+        let mut fields = Vec::new();
+        put_string(&mut fields, path.as_bytes());
+        put_u32(&mut fields, flags);
+        put_u32(&mut fields, 0);
+        let (kind, body) = self.call(SSH_FXP_OPEN, &fields)?;
+        return Self::handle(kind, &body, &format!("Opening of {}", path));
+    }
+
+    fn handle(kind: u8, body: &[u8], what: &str) -> io::Result<Vec<u8>> {
+        if kind != SSH_FXP_HANDLE {
+            status(kind, body, what)?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is answered without a handle", what)));
+        }
+        return Ok(Fields{ bytes: body }.string()?.to_vec());
+    }
+
+    fn close(&mut self, handle: &[u8]) -> io::Result<()> {
+        let mut fields = Vec::new();
+        put_string(&mut fields, handle);
+        let (kind, body) = self.call(SSH_FXP_CLOSE, &fields)?;
+        status(kind, &body, "Closing of a file")?;
+        return Ok(());
+    }
+
+    fn path_call(&mut self, kind: u8, path: &str, what: &str) -> io::Result<(u8, Vec<u8>)> {
+        let mut fields = Vec::new();
+        put_string(&mut fields, path.as_bytes());
+        let (kind, body) = self.call(kind, &fields)?;
+        if kind == SSH_FXP_STATUS {
+            status(kind, &body, &format!("{} of {}", what, path))?;
+        }
+        return Ok((kind, body));
+    }
+
+    // Moves a file into place, replacing what was there
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        let mut fields = Vec::new();
+        if self.has_posix_rename {
+            put_string(&mut fields, POSIX_RENAME.as_bytes());
+            put_string(&mut fields, from.as_bytes());
+            put_string(&mut fields, to.as_bytes());
+            let (kind, body) = self.call(SSH_FXP_EXTENDED, &fields)?;
+            status(kind, &body, &format!("Move of {}", from))?;
+            return Ok(());
+        }
+        // Plain SFTP rename doesn't replace files
+        match self.path_call(SSH_FXP_REMOVE, to, "Removal") {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => { },
+        }
+        put_string(&mut fields, from.as_bytes());
+        put_string(&mut fields, to.as_bytes());
+        let (kind, body) = self.call(SSH_FXP_RENAME, &fields)?;
+        status(kind, &body, &format!("Move of {}", from))?;
+        return Ok(());
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Every request is answered by now, so ssh has nothing left to do
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct SftpStore {
+    url: Url,
+    directory: String,
+    session: Rc<RefCell<Option<Session>>>,
+}
+
+struct SftpWriter {
+    session: Rc<RefCell<Option<Session>>>,
+    handle: Vec<u8>,
+    offset: u64,
+    pending_writes: VecDeque<u32>,
+    temporary_path: String,
+    path: String,
+}
+
+struct SftpReader {
+    session: Rc<RefCell<Option<Session>>>,
+    handle: Vec<u8>,
+    offset: u64,
+    is_done: bool,
+}
+
+impl SftpStore {
+    pub fn new(location: &str) -> io::Result<SftpStore> {
+        let url = Url::parse(location)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not an SFTP URL", location)))?;
+        let directory = match url.path.strip_prefix("/~") {
+            Some(relative) => format!(".{}", relative),
+            None if url.path.is_empty() => ".".to_string(),
+            None => url.path.clone(),
+        };
+        return Ok(SftpStore{ url, directory, session: Rc::new(RefCell::new(None)) });
+    }
+
+    // ssh is started with the first request
+    fn session(&self) -> io::Result<RefMut<'_, Session>> {
+        let mut session = self.session.borrow_mut();
+        if session.is_none() {
+            *session = Some(Session::start(&self.url)?);
+        }
+        return Ok(RefMut::map(session, |session| session.as_mut().unwrap()));
+    }
+
+    fn path(&self, name: &OsStr) -> io::Result<String> {
+        return Ok(format!("{}/{}", self.directory, store::remote_name(name)?));
+    }
+}
+
+impl PartStore for SftpStore {
+    fn create(&self, name: &OsStr, _size: u64, _policy: ClobberPolicy) -> io::Result<Box<dyn PartWriter>> {
+        let path = self.path(name)?;
+        let temporary_path = format!("{}/{}", self.directory, store::temporary_name(store::remote_name(name)?));
+        let handle = self.session()?.open(&temporary_path, SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC)?;
+        return Ok(Box::new(SftpWriter{
+            session: Rc::clone(&self.session),
+            handle,
+            offset: 0,
+            pending_writes: VecDeque::new(),
+            temporary_path,
+            path,
+        }));
+    }
+
+    fn read(&self, name: &OsStr, offset: u64) -> io::Result<Box<dyn Read>> {
+        let handle = self.session()?.open(&self.path(name)?, SSH_FXF_READ)?;
+        return Ok(Box::new(SftpReader{ session: Rc::clone(&self.session), handle, offset, is_done: false }));
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        let mut session = self.session()?;
+        let (kind, body) = match session.path_call(SSH_FXP_OPENDIR, &self.directory, "Listing") {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let handle = Session::handle(kind, &body, &format!("Listing of {}", self.directory))?;
+
+        let mut files = Vec::new();
+        loop {
+            let mut fields = Vec::new();
+            put_string(&mut fields, &handle);
+            let (kind, body) = session.call(SSH_FXP_READDIR, &fields)?;
+            if kind != SSH_FXP_NAME {
+                if status(kind, &body, &format!("Listing of {}", self.directory))? == SSH_FX_EOF {
+                    break;
+                }
+                continue;
+            }
+            let mut fields = Fields{ bytes: &body };
+            for _ in 0..fields.u32()? {
+                let name = String::from_utf8_lossy(fields.string()?).to_string();
+                fields.string()?;
+                let (size, is_directory) = fields.attributes()?;
+                if !is_directory && name != "." && name != ".." {
+                    files.push(StoredFile{ name: name.into(), size: size.unwrap_or(0) });
+                }
+            }
+        }
+        session.close(&handle)?;
+        return Ok(files);
+    }
+
+    fn stat(&self, name: &OsStr) -> io::Result<Option<u64>> {
+        let path = self.path(name)?;
+        let (kind, body) = match self.session()?.path_call(SSH_FXP_STAT, &path, "Lookup") {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            result => result?,
+        };
+        if kind != SSH_FXP_ATTRS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Lookup of {} is answered without attributes", path)));
+        }
+        return Ok(Some(Fields{ bytes: &body }.attributes()?.0.unwrap_or(0)));
+    }
+
+    fn delete(&self, name: &OsStr, _policy: ClobberPolicy) -> io::Result<()> {
+        self.session()?.path_call(SSH_FXP_REMOVE, &self.path(name)?, "Removal")?;
+        return Ok(());
+    }
+}
+
+impl SftpWriter {
+    fn wait_for_write(&mut self, session: &mut Session) -> io::Result<()> {
+        if let Some(id) = self.pending_writes.pop_front() {
+            let (kind, body) = session.answer(id)?;
+            status(kind, &body, &format!("Upload of {}", self.path))?;
+        }
+        return Ok(());
+    }
+}
+
+impl Write for SftpWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let session = Rc::clone(&self.session);
+        let mut session = RefMut::map(session.borrow_mut(), |session| session.as_mut().unwrap());
+        let length = buffer.len().min(CHUNK_SIZE);
+        let mut fields = Vec::with_capacity(length + self.handle.len() + 16);
+        put_string(&mut fields, &self.handle);
+        put_u64(&mut fields, self.offset);
+        put_string(&mut fields, &buffer[..length]);
+        let id = session.request(SSH_FXP_WRITE, &fields)?;
+        self.pending_writes.push_back(id);
+        self.offset += length as u64;
+        if self.pending_writes.len() >= MAX_PENDING_WRITES {
+            self.wait_for_write(&mut session)?;
+        }
+        return Ok(length);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl PartWriter for SftpWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let session = Rc::clone(&self.session);
+        let mut session = RefMut::map(session.borrow_mut(), |session| session.as_mut().unwrap());
+        while !self.pending_writes.is_empty() {
+            self.wait_for_write(&mut session)?;
+        }
+        session.close(&self.handle)?;
+        return session.rename(&self.temporary_path, &self.path);
+    }
+}
+
+impl Read for SftpReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.is_done || buffer.is_empty() {
+            return Ok(0);
+        }
+        let mut session = RefMut::map(self.session.borrow_mut(), |session| session.as_mut().unwrap());
+        let mut fields = Vec::new();
+        put_string(&mut fields, &self.handle);
+        put_u64(&mut fields, self.offset);
+        put_u32(&mut fields, buffer.len().min(CHUNK_SIZE) as u32);
+        let (kind, body) = session.call(SSH_FXP_READ, &fields)?;
+        if kind != SSH_FXP_DATA {
+            status(kind, &body, "Download")?;
+            self.is_done = true;
+            return Ok(0);
+        }
+        let data = Fields{ bytes: &body }.string()?;
+        let length = data.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&data[..length]);
+        self.offset += length as u64;
+        return Ok(length);
+    }
+}
+
+impl Drop for SftpReader {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.borrow_mut().as_mut() {
+            let _ = session.close(&self.handle);
+        }
+    }
+}
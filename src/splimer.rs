@@ -1,246 +1,2975 @@
 use std::cmp::min;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write, Seek, SeekFrom};
-use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::parser::ProgramInput;
+use crate::boundary::{self, Boundary, BoundaryError};
+use crate::copy::{self, Buffer, CopyMethod, CopyOptions, IoBackend};
+use crate::hook::{FinishedPart, HookError, PartCallback, PartHook};
+use crate::journal::Journal;
+use crate::json;
+use crate::manifest::{Manifest, PartRecord};
+use crate::notify::Events;
+use crate::output::{self, ClobberPolicy, FsyncPolicy};
+use crate::parser::{Command, ProgramInput};
+use crate::plan::{self, PlanError, PlannedPart, SizeSpec, SplitPlan, Strategy};
+use crate::report::{OutputFormat, Reporter};
+use crate::paths;
+use crate::sha256::Sha256;
+use crate::store::{PartStore, PartWriter, StoreLocation};
+use crate::tar::{TarLayout, TarPart};
+use crate::telegram::{self, TelegramBot};
+use crate::uring;
+use crate::zip::ZipLayout;
 
-const MAX_BUFFER_SIZE: usize = 1024 * 1024usize; // in bytes
+// Operations which are written into the journal of `--consume-source` split and `--consume-parts` merge
+const CONSUME_SOURCE: &str = "consume-source";
+const CONSUME_PARTS: &str = "consume-parts";
+// How long `split --follow` waits for the file to grow before it takes the file as finished
+const FOLLOW_TIMEOUT: Duration = Duration::from_secs(60);
+// Files are looked at this often even when no change is noticed, like writes through a mapping
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+// Remote stores are asked less often
+const REMOTE_FOLLOW_INTERVAL: Duration = Duration::from_secs(10);
+// How long `merge --follow` waits when no part comes or grows
+const MERGE_FOLLOW_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// Parts whose hashes don't match are read again this often, even when they don't look changed
+const MISMATCH_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Splimer {
     pub program_input: ProgramInput,
+    // Where parts and the manifest are kept
+    store: Box<dyn PartStore>,
+    set_stem: OsString,
+    // Part which is being written into the store
+    current_part: Option<Box<dyn PartWriter>>,
     current_file_to_write: Option<File>,
+    // Temporary file which is being written and where it goes when it is done
+    current_file_paths: Option<(PathBuf, PathBuf)>,
+    current_file_hash: Sha256,
+    // How the last part was copied, to tell in the `done` event
+    copy_method: Option<CopyMethod>,
+    // First CSV row, which is put at the beginning of every part but the first with `--csv-header`
+    repeated_header: Vec<u8>,
+    // Bytes written before and after every part, like `M` headers of `--boundary=tar`
+    // or the central directory of `--boundary=zip`
+    part_headers: Vec<Vec<u8>>,
+    part_trailers: Vec<Vec<u8>>,
+    // Files which are synced at the very end with `--fsync=end`
+    unsynced_files: Vec<PathBuf>,
+    // What is done with every part after split writes it
+    hook: PartHook,
+    report: Reporter,
+}
+
+pub struct FoundPart {
+    pub index: usize,
+    pub filename: PathBuf,
+    pub size: u64,
 }
 
 impl Splimer {
-    pub fn new(program_input: ProgramInput) -> Splimer {
-        return Splimer{
-            program_input, 
-            current_file_to_write: None
+    pub fn new(program_input: ProgramInput, store: Box<dyn PartStore>) -> Splimer {
+        let splimer = Splimer{
+            set_stem: Self::find_set_stem(&program_input, store.as_ref()),
+            store,
+            current_part: None,
+            current_file_to_write: None,
+            current_file_paths: None,
+            current_file_hash: Sha256::new(),
+            copy_method: None,
+            repeated_header: Vec::new(),
+            part_headers: Vec::new(),
+            part_trailers: Vec::new(),
+            unsynced_files: Vec::new(),
+            hook: PartHook::new(
+                program_input.on_part_done.clone(),
+                if program_input.wait_for_hook { Some(0) } else { program_input.max_pending },
+                program_input.output_format == OutputFormat::Jsonl
+            ),
+            report: Reporter::new(program_input.output_format),
+            program_input,
+        };
+        if splimer.program_input.io == IoBackend::Uring && !uring::is_available() {
+            splimer.report.warning("io_uring is not available, files are read and written one buffer at a time");
+        }
+        return splimer;
+    }
+
+    // Programs which embed splimer get `--on-part-done` as a closure, it is called before the command
+    pub fn on_part_done(&mut self, callback: PartCallback) {
+        self.hook.set_callback(callback);
+    }
+
+    // Some error is reported, which stops the command
+    pub fn has_failed(&self) -> bool {
+        return self.report.has_errors();
+    }
+
+    // `--store`, or the directory where parts are made or looked for
+    pub fn store_location(program_input: &ProgramInput) -> StoreLocation {
+        return match &program_input.store {
+            Some(store) => store.clone(),
+            None => StoreLocation::Local(Self::set_directory_of(program_input)),
         };
     }
 
     pub fn split(&mut self) {
-        let file = OpenOptions::new()
-            .read(true)
-            .open(&self.program_input.input_filename);
+        if self.program_input.store.is_some() && self.program_input.output_directory.is_some() {
+            self.report.error("`--output-directory` and `--store` both tell where parts go, use one of them");
+            return;
+        }
+        if !self.is_store_local() && self.program_input.consume_source {
+            self.report.error("`--consume-source` cuts the source as parts are made, they must be on the local disk, it cannot be used with a remote `--store`");
+            return;
+        }
+        if !self.is_store_local() && self.program_input.clobber == ClobberPolicy::Backup {
+            self.report.error("`--backup` keeps old parts next to new ones, it cannot be used with a remote `--store`");
+            return;
+        }
+        if self.program_input.consume_source && self.program_input.part_number.is_some() {
+            self.report.error("`--consume-source` makes all parts at once, it cannot be used with `--part-number`");
+            return;
+        }
+        if self.program_input.csv_header && self.program_input.boundary != Boundary::Csv {
+            self.report.error("`--csv-header` can only be used with `--boundary=csv`");
+            return;
+        }
+        if self.program_input.csv_header && self.program_input.consume_source {
+            self.report.error("`--csv-header` adds bytes to parts, so they cannot be put back with `--undo`, it cannot be used with `--consume-source`");
+            return;
+        }
+        if self.program_input.boundary == Boundary::Tar && self.program_input.consume_source {
+            self.report.error("`--boundary=tar` adds bytes to parts, so they cannot be put back with `--undo`, it cannot be used with `--consume-source`");
+            return;
+        }
+        if self.program_input.boundary == Boundary::Zip && self.program_input.consume_source {
+            self.report.error("`--boundary=zip` needs the central directory, which `--consume-source` cuts off first, they cannot be used together");
+            return;
+        }
+        if self.hook.is_set() && self.program_input.consume_source {
+            self.report.error("`--consume-source` needs all parts to put them back with `--undo`, it cannot be used with `--on-part-done`");
+            return;
+        }
+        if !self.hook.is_set() && (self.program_input.delete_after_hook || self.program_input.wait_for_hook || self.program_input.max_pending.is_some()) {
+            self.report.error("`--delete-after-hook`, `--wait-for-hook` and `--max-pending` are about the command of `--on-part-done`, which is not given");
+            return;
+        }
+        if self.program_input.follow && (self.program_input.consume_source || self.program_input.part_number.is_some() || self.program_input.dry_run) {
+            self.report.error("`--follow` makes parts while the file grows, it cannot be used with `--consume-source`, `--part-number` or `--dry-run`");
+            return;
+        }
+        if self.program_input.follow && (self.program_input.parts.is_some() || self.program_input.strategy != Strategy::Fixed) {
+            self.report.error("`--parts` and `--strategy` need the size of the whole file, which is not known with `--follow`");
+            return;
+        }
+        if self.program_input.follow && self.program_input.boundary != Boundary::Byte {
+            self.report.error("`--boundary` looks for records in the whole file, it cannot be used with `--follow`");
+            return;
+        }
+        let journal_filename = self.make_set_filename("journal");
+        if fs::symlink_metadata(&journal_filename).is_ok() {
+            self.continue_consuming_source(&journal_filename);
+            return;
+        }
+        if self.program_input.undo {
+            self.report.error(&format!("There is no interrupted `--consume-source` split of {} to undo", 
+                self.program_input.input_filename.display()
+            ));
+            return;
+        }
+        if self.program_input.follow {
+            self.split_following();
+            return;
+        }
+
+        let file = copy::open(
+            OpenOptions::new().read(true),
+            &self.program_input.input_filename,
+            self.program_input.direct
+        );
         let mut file = Self::check_file_access(file);
 
         let metadata = Self::check_file_access(file.metadata());
 
         let file_size = metadata.len() as usize;
+
+        let plan = SplitPlan::new(
+            file_size as u64,
+            &self.size_spec(),
+            self.program_input.part_number,
+            |index| self.make_output_filename(index),
+            self.make_manifest_filename()
+        );
+        let mut plan = match plan {
+            Ok(plan) => plan,
+            Err(PlanError::FileIsAlreadySmall(fragment_size)) => {
+                self.report.message(&format!("File {} is already less than {} kB, no work is done!", 
+                    self.program_input.input_filename.display(), 
+                    fragment_size / 1024
+                ));
+                return;
+            },
+            Err(err) => {
+                self.report.error(&err.to_string());
+                return;
+            },
+        };
         
-        if let Some(parts) = self.program_input.parts {
-            self.program_input.fragment_size = (file_size + parts - 1) / parts;
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+
+        // Samples are small and unaligned, they are read without `O_DIRECT`
+        let mut sample_file = Self::check_file_access(File::open(&self.program_input.input_filename));
+        let set_id = Self::check_file_access(Self::make_set_id(&mut sample_file, file_size as u64));
+
+        // Parts are moved to record boundaries, which are looked for in the file itself
+        if self.program_input.csv_header {
+            let header_size = Self::check_file_access(boundary::csv_header_size(&mut sample_file, file_size as u64)).unwrap_or(0);
+            self.repeated_header = vec![0; header_size as usize];
+            Self::check_file_access(sample_file.seek(SeekFrom::Start(0)));
+            Self::check_file_access(sample_file.read_exact(&mut self.repeated_header));
+        }
+        if self.program_input.boundary != Boundary::Byte {
+            let spec = self.size_spec();
+            let limit = |position| spec.capacity(position, file_size as u64);
+            let sizes = match &self.program_input.boundary {
+                Boundary::Zip => ZipLayout::read(&mut sample_file, file_size as u64)
+                    .map(|layout| {
+                        let parts = layout.plan_parts(file_size as u64, limit);
+                        self.part_trailers = parts.iter().map(|part| layout.trailer(part)).collect();
+                        parts.iter().map(|part| part.size).collect()
+                    })
+                    .map_err(BoundaryError::Io),
+                Boundary::Tar => TarLayout::read(&mut sample_file, file_size as u64)
+                    .and_then(|layout| layout.plan_parts(file_size as u64, limit))
+                    .map(|parts| {
+                        self.part_trailers = parts.iter().map(TarPart::trailer).collect();
+                        parts.into_iter().map(|part| {
+                            self.part_headers.push(part.header);
+                            part.size
+                        }).collect()
+                    })
+                    .map_err(BoundaryError::Io),
+                boundary => boundary.part_sizes(
+                    &mut sample_file,
+                    file_size as u64,
+                    &plan.part_sizes,
+                    limit,
+                    self.repeated_header.len() as u64
+                ),
+            };
+            let sizes = match sizes {
+                Ok(sizes) => sizes,
+                Err(err) => {
+                    self.report.error(&format!("File {} cannot be split on record boundaries, {}", 
+                        self.program_input.input_filename.display(),
+                        err
+                    ));
+                    return;
+                },
+            };
+            let in_place = plan.in_place;
+            plan = match SplitPlan::from_sizes(
+                file_size as u64,
+                sizes,
+                self.program_input.part_number,
+                |index| self.make_output_filename(index),
+                self.make_manifest_filename()
+            ) {
+                Ok(plan) => plan,
+                Err(err) => {
+                    self.report.error(&err.to_string());
+                    return;
+                },
+            };
+            plan.in_place = in_place;
         }
 
-        if file_size < self.program_input.fragment_size {
-            println!("File {} is already less than {} kB, no work is done!", 
-                self.program_input.input_filename, 
-                self.program_input.fragment_size / 1024
-            );
+        match self.program_input.clobber {
+            ClobberPolicy::Default => {
+                if let Err(reason) = self.check_existing_set(&set_id, file_size as u64) {
+                    self.report.error(&format!("{}, use `--force` or `--backup` to overwrite them", reason));
+                    return;
+                }
+            },
+            ClobberPolicy::NoClobber => {
+                if let Some(filename) = self.existing_split_outputs(&plan).first() {
+                    self.report.error(&format!("File {} already exists and `--no-clobber` is given", filename.display()));
+                    return;
+                }
+            },
+            ClobberPolicy::Force | ClobberPolicy::Backup => { },
+        }
+
+        let mut manifest = self.manifest_for_split(file_size as u64, &plan.part_sizes, &set_id);
+        manifest.repeated_header_size = self.repeated_header.len() as u64;
+        if self.part_headers.iter().any(|header| !header.is_empty()) {
+            manifest.header_sizes = self.part_headers.iter().map(|header| header.len() as u64).collect();
+        }
+        if self.part_trailers.iter().any(|trailer| !trailer.is_empty()) {
+            manifest.trailer_sizes = self.part_trailers.iter().map(|trailer| trailer.len() as u64).collect();
+        }
+        plan.added_sizes = (1..=plan.part_count).map(|index| manifest.added_size(index)).collect();
+        plan.manifest_size = Self::estimate_manifest_size(&manifest, &plan);
+        plan.in_place = self.program_input.consume_source;
+
+        if self.program_input.dry_run {
+            // Free space of remote stores is not known
+            let available_space = match self.is_store_local() {
+                true => plan::available_space(Self::readable_directory(&self.set_directory())).ok(),
+                false => None,
+            };
+            let as_json = self.program_input.json || self.program_input.output_format == OutputFormat::Jsonl;
+            plan.print(&self.input_file_name(), available_space, as_json);
             return;
         }
-        let part_number = self.program_input.part_number;
-        if part_number.is_some() && 
-            ((file_size as f32) / self.program_input.fragment_size as f32).ceil() < part_number.unwrap() as f32 {
-            println!("Error: Cannot generate {}{} part because there will be {} part{} in total", 
-                part_number.unwrap(),
-                match part_number.unwrap() {
-                    1 => "st",
-                    2 => "nd",
-                    3 => "rd",
-                    _ => "th"
-                },
-                ((file_size as f32) / self.program_input.fragment_size as f32).ceil() as usize,
-                if ((file_size as f32) / self.program_input.fragment_size as f32).ceil() == 1f32 { "" } else { "s" }
-            );
+
+        let mut buffer = self.make_buffer(plan.fragment_size);
+
+        // Parts are cut from the end, so the hash of the whole file can only be taken beforehand
+        if self.program_input.consume_source {
+            let mut file_hash = Sha256::new();
+            loop {
+                let size = match file.read(&mut buffer) {
+                    // The unaligned end of the file is read without `O_DIRECT`
+                    Err(err) if copy::is_misaligned(&err) => {
+                        Self::check_file_access(copy::clear_direct(&file));
+                        continue;
+                    },
+                    result => Self::check_file_access(result),
+                };
+                if size == 0 {
+                    break;
+                }
+                file_hash.update(&buffer[..size]);
+            }
+            manifest.sha256 = Some(file_hash.finish_hex());
+        }
+
+        // Manifest goes first, so an interrupted split is still known to be ours
+        let is_own_manifest = self.read_manifest().is_some_and(|manifest| manifest.set_id.as_deref() == Some(set_id.as_str()));
+        Self::check_file_access(manifest.write(
+            self.store.as_ref(),
+            &self.make_set_name("manifest"),
+            if is_own_manifest { ClobberPolicy::Force } else { self.program_input.clobber }
+        ));
+
+        if self.program_input.consume_source {
+            self.remove_stale_parts(plan.part_count);
+            let journal = Self::check_file_access(Journal::create(&journal_filename, CONSUME_SOURCE, None));
+            self.consume_source(&plan, manifest, journal, start);
             return;
         }
-        
+
+        let mut file_hash = Sha256::new();
+        let mut total_bytes_written = 0;
+
+        for part in &plan.parts {
+            self.copy_part(&mut file, part, &mut buffer, Some(&mut file_hash), total_bytes_written, file_size as u64);
+            self.finish_part();
+            self.record_part(&mut manifest, part);
+            total_bytes_written += part.size;
+            self.report_part_finished(manifest.part(part.index).unwrap(), &part.filename);
+            self.report.message(&format!("File {} is written, total written - {:0fill$} kB  /  {} kB", 
+                part.filename.display(),
+                total_bytes_written / 1024,
+                file_size / 1024,
+                fill = (file_size / 1024).to_string().len()
+            ));
+            if self.hook.is_set() {
+                let done = self.hook.start(FinishedPart{
+                    path: part.filename.clone(),
+                    name: self.make_set_name(&part.index.to_string()),
+                    index: part.index,
+                    total: plan.part_count,
+                });
+                if !self.part_hooks_done(done) {
+                    return;
+                }
+            }
+        }
+
+        if self.program_input.part_number.is_none() {
+            manifest.sha256 = Some(file_hash.finish_hex());
+            self.remove_stale_parts(plan.part_count);
+        }
+        Self::check_file_access(manifest.write(self.store.as_ref(), &self.make_set_name("manifest"), ClobberPolicy::Force));
+        self.sync_at_end();
+        let done = self.hook.finish();
+        if !self.part_hooks_done(done) {
+            return;
+        }
+
+        self.report_done(start, &[
+            ("file_name", json::string(&self.program_input.input_filename.to_string_lossy())),
+            ("manifest", json::string(&self.make_manifest_filename().to_string_lossy())),
+            ("parts", plan.parts.len().to_string()),
+            ("part_count", plan.part_count.to_string()),
+            ("bytes", total_bytes_written.to_string()),
+            ("sha256", json::optional_string(manifest.sha256.as_deref())),
+        ]);
+
+    }
+
+    // `--follow`: every part is written as soon as the file has grown past it. The last one is written
+    // when the writer closes the file or when it doesn't grow for `--follow-timeout`
+    fn split_following(&mut self) {
+        let input_filename = self.program_input.input_filename.clone();
+        let file = copy::open(OpenOptions::new().read(true), &input_filename, self.program_input.direct);
+        let mut file = Self::check_file_access(file);
+        let watched_name = input_filename.file_name().map(OsStr::to_os_string).unwrap_or_default();
+        let mut events = match Events::new(Self::readable_directory(input_filename.parent().unwrap_or(Path::new("")))) {
+            Ok(events) => Some(events),
+            Err(err) => {
+                self.report.warning(&format!("closing of {} cannot be noticed, {}, split finishes when it stops growing", input_filename.display(), err));
+                None
+            },
+        };
+        let timeout = self.program_input.follow_timeout.unwrap_or(FOLLOW_TIMEOUT);
+
+        // The source is not known until it is written, so any parts which are there are in the way
+        let has_manifest = self.store.stat(&self.make_set_name("manifest")).is_ok_and(|size| size.is_some());
+        if has_manifest || !self.find_parts().is_empty() {
+            match self.program_input.clobber {
+                ClobberPolicy::Default => {
+                    self.report.error(&format!("Parts of {} are already in {}, use `--force` or `--backup` to overwrite them",
+                        input_filename.display(),
+                        self.readable_set_directory().display()
+                    ));
+                    return;
+                },
+                ClobberPolicy::NoClobber => {
+                    self.report.error(&format!("Parts of {} are already in {} and `--no-clobber` is given",
+                        input_filename.display(),
+                        self.readable_set_directory().display()
+                    ));
+                    return;
+                },
+                ClobberPolicy::Force | ClobberPolicy::Backup => { },
+            }
+        }
+
         let start = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
+        let spec = self.size_spec();
+        let mut manifest = Manifest::new(self.input_file_name(), 0, 0);
+        manifest.following = true;
+        let mut buffer = self.make_buffer(spec.capacity(0, u64::MAX));
+        let mut file_hash = Sha256::new();
+        let mut part_sizes = Vec::new();
+        let mut offset = 0;
+        let mut file_size = 0;
+        let mut last_growth = Instant::now();
+        let mut is_closed = false;
+        loop {
+            let size = Self::check_file_access(file.metadata()).len();
+            if size < file_size {
+                self.report.error(&format!("File {} is cut from {} to {} bytes while it is followed", input_filename.display(), file_size, size));
+                return;
+            }
+            if size > file_size {
+                file_size = size;
+                last_growth = Instant::now();
+            }
+            let capacity = spec.capacity(part_sizes.len(), u64::MAX);
+            if file_size - offset >= capacity {
+                if !self.write_followed_part(&mut file, &mut manifest, &mut part_sizes, offset, capacity, &mut buffer, &mut file_hash, file_size) {
+                    return;
+                }
+                offset += capacity;
+                continue;
+            }
+            if is_closed || last_growth.elapsed() >= timeout {
+                break;
+            }
 
-        let mut buffer = vec![0; min(MAX_BUFFER_SIZE, self.program_input.fragment_size)];
+            let wait = timeout.saturating_sub(last_growth.elapsed()).min(FOLLOW_INTERVAL);
+            match events.as_mut() {
+                Some(events) => {
+                    if let Some(changes) = Self::check_file_access(events.wait(Some(wait))) {
+                        is_closed |= changes.iter().any(|change| change.closed && change.name == watched_name);
+                    }
+                },
+                None => thread::sleep(wait),
+            }
+        }
 
-        let mut fragment_number = self.program_input.part_number.unwrap_or(1);
-        let mut bytes_written = 0;
-        let mut total_bytes_written = 0;
+        if part_sizes.is_empty() {
+            self.report.message(&format!("File {} is already less than {} kB, no work is done!", 
+                input_filename.display(), 
+                spec.capacity(0, u64::MAX) / 1024
+            ));
+            return;
+        }
+        if file_size > offset {
+            if !self.write_followed_part(&mut file, &mut manifest, &mut part_sizes, offset, file_size - offset, &mut buffer, &mut file_hash, file_size) {
+                return;
+            }
+            offset = file_size;
+        }
 
-        self.open_file_for_write(&self.make_output_filename(fragment_number, &self.program_input.input_filename));
+        // Samples are small and unaligned, they are read without `O_DIRECT`
+        let mut sample_file = Self::check_file_access(File::open(&input_filename));
+        manifest.set_id = Some(Self::check_file_access(Self::make_set_id(&mut sample_file, file_size)));
+        manifest.sha256 = Some(file_hash.finish_hex());
+        manifest.following = false;
+        self.remove_stale_parts(part_sizes.len());
+        Self::check_file_access(manifest.write(self.store.as_ref(), &self.make_set_name("manifest"), ClobberPolicy::Force));
+        self.sync_at_end();
+        let done = self.hook.finish();
+        if !self.part_hooks_done(done) {
+            return;
+        }
 
-        if self.program_input.part_number.is_some() {
-            Self::check_file_access(
-                file.seek(SeekFrom::Start(((fragment_number - 1) * self.program_input.fragment_size) as u64))
-            );
+        self.report_done(start, &[
+            ("file_name", json::string(&input_filename.to_string_lossy())),
+            ("manifest", json::string(&self.make_manifest_filename().to_string_lossy())),
+            ("parts", part_sizes.len().to_string()),
+            ("part_count", part_sizes.len().to_string()),
+            ("bytes", offset.to_string()),
+            ("sha256", json::optional_string(manifest.sha256.as_deref())),
+        ]);
+    }
+
+    // Writes the next part of a followed file and the manifest with it, false when its hook fails
+    #[allow(clippy::too_many_arguments)]
+    fn write_followed_part(
+        &mut self,
+        file: &mut File,
+        manifest: &mut Manifest,
+        part_sizes: &mut Vec<u64>,
+        offset: u64,
+        size: u64,
+        buffer: &mut [u8],
+        file_hash: &mut Sha256,
+        file_size: u64
+    ) -> bool {
+        let index = part_sizes.len() + 1;
+        let part = PlannedPart{ index, filename: self.make_output_filename(index), offset, size };
+        self.copy_part(file, &part, buffer, Some(file_hash), offset, file_size);
+        self.finish_part();
+        self.record_part(manifest, &part);
+        part_sizes.push(size);
+        manifest.total_size = offset + size;
+        manifest.set_planned_sizes(part_sizes);
+        self.report_part_finished(manifest.part(index).unwrap(), &part.filename);
+        self.report.message(&format!("File {} is written, total written - {} kB so far", 
+            part.filename.display(),
+            manifest.total_size / 1024
+        ));
+        // Readers like `merge --follow` see every part in the manifest as soon as it is written
+        Self::check_file_access(manifest.write(
+            self.store.as_ref(),
+            &self.make_set_name("manifest"),
+            if index == 1 { self.program_input.clobber } else { ClobberPolicy::Force }
+        ));
+
+        if self.hook.is_set() {
+            // The number of parts is not known until the file is finished
+            let done = self.hook.start(FinishedPart{
+                path: part.filename.clone(),
+                name: self.make_set_name(&index.to_string()),
+                index,
+                total: 0,
+            });
+            return self.part_hooks_done(done);
         }
+        return true;
+    }
 
-        while let Ok(size) = file.read(&mut buffer) {
-            if size == 0 {
-                break;
-            }
+    // Picks up an interrupted `--consume-source` split, to finish it or to undo it with `--undo`
+    fn continue_consuming_source(&mut self, journal_filename: &Path) {
+        let journal = match Journal::read(journal_filename) {
+            Ok(journal) => journal,
+            Err(err) => {
+                self.report.error(&format!("Journal {} cannot be read, {}", journal_filename.display(), err));
+                return;
+            },
+        };
+        if journal.operation != CONSUME_SOURCE {
+            self.report.error(&format!("Journal {} is left by an interrupted `{}`, finish it first", 
+                journal_filename.display(),
+                journal.operation
+            ));
+            return;
+        }
+        if self.program_input.dry_run {
+            self.report.message(&format!("Split of {} was interrupted, there is nothing to plan until it is finished or undone", 
+                self.program_input.input_filename.display()
+            ));
+            return;
+        }
+        if !self.program_input.consume_source && !self.program_input.undo {
+            self.report.error(&format!("Split of {} was interrupted and the file is cut, \
+                run it again with `--consume-source` to finish it or with `--undo` to put parts back", 
+                self.program_input.input_filename.display()
+            ));
+            return;
+        }
+        let Some(manifest) = self.read_manifest() else {
+            self.report.error(&format!("There is no manifest of interrupted split {}", self.make_manifest_filename().display()));
+            return;
+        };
 
-            let how_many = min(size, self.program_input.fragment_size - bytes_written);
-            self.write_bytes(buffer[..how_many].as_ref());
+        let plan = SplitPlan::from_sizes(
+            manifest.total_size,
+            manifest.planned_sizes(),
+            None,
+            |index| self.make_output_filename(index),
+            self.make_manifest_filename()
+        );
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(err) => {
+                self.report.error(&format!("Manifest {} doesn't describe a split, {}", self.make_manifest_filename().display(), err));
+                return;
+            },
+        };
 
-            bytes_written += how_many;
-            if bytes_written == self.program_input.fragment_size {
-                if file_size == bytes_written * fragment_number as usize ||
-                self.program_input.part_number.is_some() {
-                    break;
-                }
-                self.flush();
-                total_bytes_written += bytes_written;
-                println!("File {} is written, total written - {:0fill$} kB  /  {} kB", 
-                    self.make_output_filename(fragment_number, &self.program_input.input_filename),
-                    total_bytes_written / 1024,
-                    file_size / 1024,
-                    fill = (file_size / 1024).to_string().len()
-                );
-                fragment_number += 1;
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        if self.program_input.undo {
+            self.undo_consuming_source(&plan, journal);
+        } else {
+            self.report.message(&format!("Split of {} was interrupted, it is continued", self.program_input.input_filename.display()));
+            self.consume_source(&plan, manifest, journal, start);
+        }
+    }
 
-                self.open_file_for_write(&self.make_output_filename(fragment_number, &self.program_input.input_filename));
-                bytes_written = size - how_many;
+    // From the last part to the first, every part is synced and recorded in the journal
+    // before the source is cut, so the source never loses bytes which are not safely in a part
+    fn consume_source(&mut self, plan: &SplitPlan, mut manifest: Manifest, mut journal: Journal, start: u128) {
+        let source_filename = self.program_input.input_filename.clone();
+        let mut source = copy::open(
+            OpenOptions::new().read(true).write(true),
+            &source_filename,
+            self.program_input.direct
+        ).ok();
+        let mut buffer = self.make_buffer(plan.fragment_size);
+        let mut total_bytes_written = 0;
 
-                if how_many == size {
-                    continue;
+        for part in plan.parts.iter().rev() {
+            if let Some(record) = journal.done_part(part.index).cloned() {
+                // Interrupted after the part was recorded, but maybe before the source was cut
+                if let Some(file) = &source {
+                    if Self::check_file_access(file.metadata()).len() > part.offset {
+                        if fs::metadata(&part.filename).map(|metadata| metadata.len()).ok() != Some(record.size) {
+                            self.report.error(&format!("Part {} is recorded in the journal, but it is missing or has another size", 
+                                part.filename.display()
+                            ));
+                            return;
+                        }
+                        Self::check_file_access(file.set_len(part.offset));
+                        Self::check_file_access(file.sync_all());
+                    }
                 }
-                self.write_bytes(buffer[how_many..].as_ref());
+                total_bytes_written += record.size;
+                manifest.set_part(record);
+                continue;
+            }
+
+            let Some(file) = &mut source else {
+                self.report.error(&format!("File {} is not found, but part {} is not made yet", 
+                    source_filename.display(),
+                    part.filename.display()
+                ));
+                return;
+            };
+            let source_size = Self::check_file_access(file.metadata()).len();
+            if source_size != part.offset + part.size {
+                self.report.error(&format!("File {} has {} bytes, but {} are expected, it was changed after split had started", 
+                    source_filename.display(),
+                    source_size,
+                    part.offset + part.size
+                ));
+                return;
             }
+
+            self.copy_part(file, part, &mut buffer, None, total_bytes_written, plan.file_size);
+            self.sync_part();
+            self.finish_part();
+            Self::check_file_access(output::sync_directory(&part.filename));
+            self.record_part(&mut manifest, part);
+            let record = manifest.part(part.index).unwrap().clone();
+            Self::check_file_access(journal.append_part(&record));
+
+            Self::check_file_access(file.set_len(part.offset));
+            Self::check_file_access(file.sync_all());
+            total_bytes_written += part.size;
+            self.report_part_finished(&record, &part.filename);
+            self.report.message(&format!("File {} is written, {} kB are left in {}", 
+                part.filename.display(),
+                part.offset / 1024,
+                source_filename.display()
+            ));
         }
-        self.flush();
-        total_bytes_written += bytes_written;
-        println!("File {} is written, total written - {:0fill$} kB  /  {} kB", 
-            self.make_output_filename(fragment_number, &self.program_input.input_filename),
-            total_bytes_written / 1024,
-            file_size / 1024,
-            fill = (file_size / 1024).to_string().len()
-        );
+        drop(source);
 
-        println!(
-            "The job is done! Total passed {:?} s", 
-            (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - start) as f64 / 1000f64
-        );
+        Self::check_file_access(manifest.write(self.store.as_ref(), &self.make_set_name("manifest"), ClobberPolicy::Force));
+        Self::check_file_access(output::sync_file(&self.make_manifest_filename()));
+        if fs::symlink_metadata(&source_filename).is_ok() {
+            Self::check_file_access(fs::remove_file(&source_filename));
+            Self::check_file_access(output::sync_directory(&source_filename));
+        }
+        Self::check_file_access(journal.remove());
+        self.report.message(&format!("File {} is consumed", source_filename.display()));
+
+        self.report_done(start, &[
+            ("file_name", json::string(&source_filename.to_string_lossy())),
+            ("manifest", json::string(&self.make_manifest_filename().to_string_lossy())),
+            ("parts", plan.parts.len().to_string()),
+            ("part_count", plan.part_count.to_string()),
+            ("bytes", total_bytes_written.to_string()),
+            ("sha256", json::optional_string(manifest.sha256.as_deref())),
+        ]);
+    }
+
+    // From the first consumed part to the last, every part is appended back to the source,
+    // checked by its hash and synced before it is removed
+    fn undo_consuming_source(&mut self, plan: &SplitPlan, mut journal: Journal) {
+        let source_filename = self.program_input.input_filename.clone();
+        let mut source = Self::check_file_access(copy::open(
+            OpenOptions::new().read(true).write(true).create(true).truncate(false),
+            &source_filename,
+            self.program_input.direct
+        ));
+        let mut buffer = self.make_buffer(plan.fragment_size);
+
+        for part in &plan.parts {
+            let Some(record) = journal.done_part(part.index).cloned() else {
+                // Not consumed, or put back already and maybe not removed after that
+                if journal.undone.contains(&part.index) && fs::symlink_metadata(&part.filename).is_ok() {
+                    Self::check_file_access(fs::remove_file(&part.filename));
+                }
+                continue;
+            };
+
+            let source_size = Self::check_file_access(source.metadata()).len();
+            if source_size < record.offset {
+                self.report.error(&format!("File {} has {} bytes, but part {} starts at {}", 
+                    source_filename.display(),
+                    source_size,
+                    part.filename.display(),
+                    record.offset
+                ));
+                return;
+            }
+            // Piece of the part may be left at the end by an interrupted undo, or the source wasn't cut yet
+            Self::check_file_access(source.set_len(record.offset));
+            Self::check_file_access(source.seek(SeekFrom::Start(record.offset)));
+
+            let mut file = Self::check_file_access(copy::open(OpenOptions::new().read(true), &part.filename, self.program_input.direct));
+            let mut part_hash = Sha256::new();
+            Self::check_file_access(copy::copy_range(
+                &mut file, 0, &mut source, record.offset, record.size,
+                self.copy_options(), &mut buffer, &mut [&mut part_hash], |_| { }
+            ));
+            if record.sha256.as_deref().is_some_and(|sha256| sha256 != part_hash.finish_hex()) {
+                Self::check_file_access(source.set_len(record.offset));
+                self.report.error(&format!("Part {} is damaged, its SHA-256 doesn't match the journal", part.filename.display()));
+                return;
+            }
+            Self::check_file_access(source.sync_all());
 
+            Self::check_file_access(journal.append_undone(part.index));
+            Self::check_file_access(fs::remove_file(&part.filename));
+            Self::check_file_access(output::sync_directory(&part.filename));
+            self.report.message(&format!("File {} is put back into {}", part.filename.display(), source_filename.display()));
+        }
+
+        Self::check_file_access(fs::remove_file(self.make_manifest_filename()));
+        Self::check_file_access(journal.remove());
+        self.report.message(&format!("Split of {} is undone", source_filename.display()));
     }
 
     pub fn merge(&mut self) {
-        self.current_file_to_write = Some(
-            Self::check_file_access(
-                OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(Self::make_filename_with_suffix(&"_[merged]".to_string(), &self.program_input.input_filename))
-            )
-        );
+        if !self.is_store_local() && self.program_input.consume_parts {
+            self.report.error("`--consume-parts` moves parts into the merged file, they must be on the local disk, it cannot be used with a remote `--store`");
+            return;
+        }
+        if self.program_input.follow && self.program_input.consume_parts {
+            self.report.error("`--consume-parts` needs the whole set before it removes anything, it cannot be used with `--follow`");
+            return;
+        }
+        if self.program_input.follow {
+            self.merge_following();
+            return;
+        }
+        let manifest = self.read_manifest();
+        let mut merged_filename = self.make_merged_filename(manifest.as_ref());
+
+        let journal_filename = self.make_set_filename("journal");
+        let journal = if fs::symlink_metadata(&journal_filename).is_ok() {
+            let journal = match Journal::read(&journal_filename) {
+                Ok(journal) => journal,
+                Err(err) => {
+                    self.report.error(&format!("Journal {} cannot be read, {}", journal_filename.display(), err));
+                    return;
+                },
+            };
+            if journal.operation != CONSUME_PARTS {
+                self.report.error(&format!("Journal {} is left by an interrupted `{}`, finish it first", 
+                    journal_filename.display(),
+                    journal.operation
+                ));
+                return;
+            }
+            if !self.program_input.consume_parts {
+                self.report.error(&format!("Merge of {} was interrupted and some parts are removed, \
+                    run it again with `--consume-parts` to finish it", 
+                    self.program_input.input_filename.display()
+                ));
+                return;
+            }
+            // The merge goes on where it was started
+            if let Some(target) = &journal.target {
+                merged_filename = target.clone();
+            }
+            Some(journal)
+        } else {
+            None
+        };
+        let may_replace = matches!(self.program_input.clobber, ClobberPolicy::Force | ClobberPolicy::Backup);
+        if fs::symlink_metadata(&merged_filename).is_ok() && !may_replace {
+            self.report.error(&format!("File {} already exists, choose another place with `--output-file` or `--output-directory`, \
+                or replace it with `--force` or `--backup`", 
+                merged_filename.display()
+            ));
+            return;
+        }
+
+        if let Some(manifest) = &manifest {
+            for part in self.find_parts() {
+                let added_size = manifest.added_size(part.index);
+                if let Some(record) = manifest.part(part.index).filter(|record| record.size + added_size != part.size) {
+                    self.report.error(&format!("File {} has {} bytes, but {} were written, it is from another split", 
+                        part.filename.display(),
+                        part.size,
+                        record.size + added_size
+                    ));
+                    return;
+                }
+            }
+        }
 
         let start = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
 
-        let mut buffer = vec![0; MAX_BUFFER_SIZE];
+        if self.program_input.consume_parts {
+            let Some(manifest) = manifest else {
+                self.report.error("There is no manifest, so it cannot be checked that all parts are here before they are removed");
+                return;
+            };
+            self.merge_consuming_parts(&manifest, &merged_filename, journal, &journal_filename, start);
+            return;
+        }
+
+        // Without a manifest parts are merged up to the first missing one, like before manifests
+        if let Some(manifest) = &manifest {
+            let missing = self.missing_parts(manifest);
+            if !missing.is_empty() {
+                self.report.error(&format!("Parts {} are missing, nothing is merged", missing.join(", ")));
+                return;
+            }
+        }
+
+        self.open_file_for_write(&merged_filename);
+
+        let mut buffer = self.make_buffer(u64::MAX);
 
         let mut fragment_number = 1;
         let mut bytes_written = 0usize;
+        let total_size = manifest.as_ref().map(|manifest| manifest.total_size)
+            .unwrap_or_else(|| self.find_parts().iter().map(|part| part.size).sum());
 
-        while let Ok(_) = fs::metadata(self.make_output_filename(fragment_number, &self.program_input.input_filename)) {
-            let mut file = Self::check_file_access(
-                OpenOptions::new()
-                    .read(true)
-                    .open(self.make_output_filename(fragment_number, &self.program_input.input_filename)
-                )
-            );
+        while let Some(stored_size) = Self::check_file_access(self.store.stat(&self.make_set_name(&fragment_number.to_string()))) {
+            let part_name = self.make_set_name(&fragment_number.to_string());
+            let part_filename = self.make_output_filename(fragment_number);
+            // A repeated header is left out, it is already in the first part, and so is a trailer
+            let header_size = manifest.as_ref().map(|manifest| manifest.header_size(fragment_number)).unwrap_or(0);
+            let added_size = manifest.as_ref().map(|manifest| manifest.added_size(fragment_number)).unwrap_or(0);
+            let part_size = stored_size.saturating_sub(added_size);
+            let part_offset = bytes_written as u64;
+            let mut part_hash = Sha256::new();
+            self.report_part_started(fragment_number, &part_filename, part_offset, part_size);
 
-            while let Ok(size) = file.read(&mut buffer) {
-                if size == 0 {
-                    break;
-                }
-    
-                self.write_bytes(buffer[..size].as_ref());
-                bytes_written += size;
-            }
+            let mut merged = self.current_file_to_write.take().unwrap();
+            let mut merged_hash = std::mem::replace(&mut self.current_file_hash, Sha256::new());
+            let progress = |done| self.report_progress(fragment_number, done, part_offset + done, total_size);
+            let copied = match self.store.local_path(&part_name) {
+                Some(local_filename) => {
+                    let mut file = Self::check_file_access(copy::open(OpenOptions::new().read(true), &local_filename, self.program_input.direct));
+                    copy::copy_range(
+                        &mut file, header_size, &mut merged, part_offset, part_size,
+                        self.copy_options(), &mut buffer, &mut [&mut part_hash, &mut merged_hash], progress
+                    )
+                },
+                // Remote parts are downloaded straight into the merged file
+                None => {
+                    let mut stream = Self::check_file_access(self.store.read(&part_name, header_size));
+                    copy::copy_stream_to_range(
+                        &mut stream, &mut merged, part_offset, part_size,
+                        &mut buffer, &mut [&mut part_hash, &mut merged_hash], progress
+                    ).map(|_| CopyMethod::Buffered)
+                },
+            };
+            self.current_file_to_write = Some(merged);
+            self.current_file_hash = merged_hash;
+            self.copy_method = Some(Self::check_file_access(copied));
+            bytes_written += part_size as usize;
 
             self.flush();
-            println!("File {} is read, total kilobytes written - {}", 
-                self.make_output_filename(fragment_number, &self.program_input.input_filename),
-                bytes_written / 1024
+            self.report_part_finished(
+                &PartRecord{
+                    index: fragment_number,
+                    offset: part_offset,
+                    size: bytes_written as u64 - part_offset,
+                    sha256: Some(part_hash.finish_hex()),
+                },
+                &part_filename
             );
+            self.report.message(&format!("File {} is read, total kilobytes written - {}", 
+                part_filename.display(),
+                bytes_written / 1024
+            ));
 
             fragment_number += 1;
 
 
         }
-        println!("File {} was merged", &self.program_input.input_filename);
-
-        println!(
-            "The job is done! Total passed {:?} s", 
-            (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - start) as f64 / 1000f64
-        );
+        let merged_hash = std::mem::replace(&mut self.current_file_hash, Sha256::new()).finish_hex();
+        if let Some(manifest) = &manifest {
+            let reason = if bytes_written as u64 != manifest.total_size {
+                Some(format!("it has {} bytes, but the original has {}", bytes_written, manifest.total_size))
+            } else if manifest.sha256.as_deref().is_some_and(|sha256| sha256 != merged_hash) {
+                Some("its SHA-256 doesn't match the manifest".to_string())
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                self.discard_file();
+                self.report.error(&format!("File {} is not merged, {}", merged_filename.display(), reason));
+                return;
+            }
+        }
+        self.finish_file();
+        self.sync_at_end();
+        self.report.message(&format!("File {} was merged into {}", self.program_input.input_filename.display(), merged_filename.display()));
 
+        self.report_done(start, &[
+            ("file_name", json::string(&merged_filename.to_string_lossy())),
+            ("parts", (fragment_number - 1).to_string()),
+            ("bytes", bytes_written.to_string()),
+            ("sha256", json::string(&merged_hash)),
+        ]);
     }
 
-    fn write_bytes(&mut self, buffer: &[u8]) {
-        if let Some(f) = &mut self.current_file_to_write {
-            Self::check_file_access(f.write(buffer));
-        }
-    }
+    // `--follow`: every part is put at its offset in the merged file as soon as it has the size and the hash
+    // which the manifest tells, in any order. Merge finishes when all parts of a finished split are there,
+    // or fails when nothing new comes for `--follow-timeout`
+    fn merge_following(&mut self) {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let directory = self.readable_set_directory();
+        let mut events = match self.is_store_local() {
+            true => match Events::new(&directory) {
+                Ok(events) => Some(events),
+                Err(err) => {
+                    self.report.warning(&format!("changes in {} cannot be noticed, {}, it is looked at every second", directory.display(), err));
+                    None
+                },
+            },
+            false => None,
+        };
 
-    fn open_file_for_write(&mut self, filename: &String) {
-        self.current_file_to_write = Some(
-            Self::check_file_access(
-                OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(filename)
-            )
-        );
-    }
+        let mut buffer = self.make_buffer(u64::MAX);
+        let mut manifest: Option<Manifest> = None;
+        let mut merged_filename = PathBuf::new();
+        let timeout = self.program_input.follow_timeout.unwrap_or(MERGE_FOLLOW_TIMEOUT);
+        // Indexes of merged parts, sizes of parts which are not merged yet, and modification times
+        // of parts whose hashes don't match yet, with the time they were read
+        let mut merged = BTreeSet::new();
+        let mut sizes: HashMap<usize, Option<u64>> = HashMap::new();
+        let mut mismatched: HashMap<usize, (Option<SystemTime>, Instant)> = HashMap::new();
+        let mut last_change = Instant::now();
+        loop {
+            // The manifest grows while `split --follow` goes on, one which is still downloaded doesn't parse yet
+            let manifest_name = self.make_set_name("manifest");
+            if self.store.stat(&manifest_name).is_ok_and(|size| size.is_some()) {
+                if let Ok(read) = Manifest::read(self.store.as_ref(), &manifest_name) {
+                    if manifest.is_none() {
+                        merged_filename = self.make_merged_filename(Some(&read));
+                        let may_replace = matches!(self.program_input.clobber, ClobberPolicy::Force | ClobberPolicy::Backup);
+                        if fs::symlink_metadata(&merged_filename).is_ok() && !may_replace {
+                            self.report.error(&format!("File {} already exists, choose another place with `--output-file` or `--output-directory`, \
+                                or replace it with `--force` or `--backup`", 
+                                merged_filename.display()
+                            ));
+                            return;
+                        }
+                        self.open_file_for_write(&merged_filename);
+                    }
+                    if manifest.as_ref().is_none_or(|manifest| manifest.parts.len() != read.parts.len()) {
+                        last_change = Instant::now();
+                    }
+                    manifest = Some(read);
+                }
+            }
 
-    fn flush(&mut self) {
-        if let Some(f) = &mut self.current_file_to_write {
-            Self::check_file_access(f.flush());
-        }
-    }
+            if let Some(manifest) = &manifest {
+                for record in &manifest.parts {
+                    if merged.contains(&record.index) {
+                        continue;
+                    }
+                    let part_name = self.make_set_name(&record.index.to_string());
+                    let expected_size = record.size + manifest.added_size(record.index);
+                    let size = Self::check_file_access(self.store.stat(&part_name));
+                    // A growing part is something new too
+                    if sizes.insert(record.index, size) != Some(size) {
+                        last_change = Instant::now();
+                    }
+                    if size != Some(expected_size) {
+                        continue;
+                    }
+                    // A part which doesn't match is looked at again when it is changed, or after a while,
+                    // as remote stores don't tell when their files change
+                    let modified = self.store.local_path(&part_name).and_then(|path| fs::metadata(path).ok()?.modified().ok());
+                    if mismatched.get(&record.index).is_some_and(|(seen, checked)| *seen == modified && checked.elapsed() < MISMATCH_RECHECK_INTERVAL) {
+                        continue;
+                    }
+                    if self.merge_followed_part(manifest, record, &mut buffer) {
+                        merged.insert(record.index);
+                        mismatched.remove(&record.index);
+                        last_change = Instant::now();
+                        continue;
+                    }
+                    if !mismatched.contains_key(&record.index) {
+                        self.report.warning(&format!("part {} has its size but not its SHA-256 yet, it is looked at again when it changes", 
+                            self.make_output_filename(record.index).display()
+                        ));
+                    }
+                    mismatched.insert(record.index, (modified, Instant::now()));
+                }
+                if !manifest.following && (1..=manifest.part_count()).all(|index| merged.contains(&index)) {
+                    break;
+                }
+            }
 
-    fn make_output_filename(&self, fragment_number: usize, pattern: &String) -> String {
-        let filename = Path::new(pattern).file_stem().unwrap().to_str().unwrap();
+            if last_change.elapsed() >= timeout {
+                let missing = match &manifest {
+                    Some(manifest) => {
+                        let missing: Vec<String> = (1..=manifest.part_count())
+                            .filter(|index| !merged.contains(index))
+                            .map(|index| index.to_string())
+                            .collect();
+                        match manifest.following {
+                            true => format!("the split is not finished and parts not merged yet are: {}", missing.join(", ")),
+                            false => format!("parts not merged yet are: {}", missing.join(", ")),
+                        }
+                    },
+                    None => format!("manifest {} is not there", self.make_manifest_filename().display()),
+                };
+                self.report.error(&format!("Nothing new comes for {:?}, {}", timeout, missing));
+                // The merged file has holes, it is of no use
+                self.discard_file();
+                return;
+            }
+            self.wait_for_changes(events.as_mut());
+        }
 
-        let filename = filename.to_string() + 
-            "_[" + &fragment_number.to_string().to_owned() + "].splm";
+        let manifest = manifest.unwrap();
+        let merged_file = self.current_file_to_write.take().unwrap();
+        Self::check_file_access(merged_file.set_len(manifest.total_size));
+        self.current_file_to_write = Some(merged_file);
+        self.flush();
+        self.finish_file();
+        self.sync_at_end();
+        self.report.message(&format!("File {} was merged into {}", self.program_input.input_filename.display(), merged_filename.display()));
 
-        if let Some(dir) = &self.program_input.output_directory {
-            Path::new(&dir)
-                .join(filename)
-                .to_str().unwrap().to_string()
-        } else {
-            return Path::new(pattern).parent().unwrap()
-                .join(Path::new(&filename))
-                .to_str().unwrap().to_string()
-        }        
+        self.report_done(start, &[
+            ("file_name", json::string(&merged_filename.to_string_lossy())),
+            ("parts", manifest.part_count().to_string()),
+            ("bytes", manifest.total_size.to_string()),
+            ("sha256", json::optional_string(manifest.sha256.as_deref())),
+        ]);
     }
 
-    fn make_filename_with_suffix(suffix: &String, pattern: &String) -> String {        
-        return Path::new(pattern).file_stem().unwrap().to_str().unwrap().to_string() + 
-            suffix + 
-            "." + 
-            Path::new(pattern).extension().unwrap().to_str().unwrap();
+    // Copies a part into the merged file at its offset, false when its hash doesn't match the manifest
+    fn merge_followed_part(&mut self, manifest: &Manifest, record: &PartRecord, buffer: &mut [u8]) -> bool {
+        let part_name = self.make_set_name(&record.index.to_string());
+        let part_filename = self.make_output_filename(record.index);
+        let header_size = manifest.header_size(record.index);
+        let mut part_hash = Sha256::new();
+        self.report_part_started(record.index, &part_filename, record.offset, record.size);
+
+        let mut merged = self.current_file_to_write.take().unwrap();
+        let progress = |done| self.report_progress(record.index, done, record.offset + done, manifest.total_size);
+        let copied = match self.store.local_path(&part_name) {
+            Some(local_filename) => {
+                let mut file = Self::check_file_access(copy::open(OpenOptions::new().read(true), &local_filename, self.program_input.direct));
+                copy::copy_range(
+                    &mut file, header_size, &mut merged, record.offset, record.size,
+                    self.copy_options(), buffer, &mut [&mut part_hash], progress
+                )
+            },
+            None => {
+                let mut stream = Self::check_file_access(self.store.read(&part_name, header_size));
+                copy::copy_stream_to_range(
+                    &mut stream, &mut merged, record.offset, record.size,
+                    buffer, &mut [&mut part_hash], progress
+                ).map(|_| CopyMethod::Buffered)
+            },
+        };
+        self.current_file_to_write = Some(merged);
+        self.copy_method = Some(Self::check_file_access(copied));
+        if record.sha256.as_deref().is_some_and(|sha256| sha256 != part_hash.finish_hex()) {
+            return false;
+        }
+
+        self.report_part_finished(record, &part_filename);
+        match manifest.following {
+            true => self.report.message(&format!("File {} is read, part {} is merged", part_filename.display(), record.index)),
+            false => self.report.message(&format!("File {} is read, part {} of {} is merged", 
+                part_filename.display(),
+                record.index,
+                manifest.part_count()
+            )),
+        }
+        return true;
     }
 
-    fn check_file_access<T, Error: std::fmt::Debug>(result: Result<T, Error>) -> T {
-        match result {
-            Ok(t) => t,
-            Err(err) => {
-                eprintln!("File cannot be opened: {:?}", err);
-                panic!();
+    // Waits until a file in the set directory is closed by its writer, or for the next look at the set
+    fn wait_for_changes(&self, events: Option<&mut Events>) {
+        let Some(events) = events else {
+            thread::sleep(if self.is_store_local() { FOLLOW_INTERVAL } else { REMOTE_FOLLOW_INTERVAL });
+            return;
+        };
+        let since = Instant::now();
+        loop {
+            let wait = FOLLOW_INTERVAL.saturating_sub(since.elapsed());
+            if wait.is_zero() {
+                return;
+            }
+            match Self::check_file_access(events.wait(Some(wait))) {
+                Some(changes) if !changes.iter().any(|change| change.closed) => continue,
+                _ => return,
             }
         }
     }
 
+    // Every part is appended, synced and recorded in the journal before it is removed,
+    // so its bytes are always either in the part or safely in the merged file.
+    // The first part isn't copied at all, it just becomes the beginning of the merged file.
+    // The journal is written only when the first part is known to be good, until then the set is left as it was
+    fn merge_consuming_parts(&mut self, manifest: &Manifest, merged_filename: &Path, journal: Option<Journal>, journal_filename: &Path, start: u128) {
+        let temporary_filename = output::temporary_path(merged_filename);
+        let mut journal = match journal {
+            Some(journal) => {
+                self.report.message(&format!("Merge of {} was interrupted, it is continued", self.program_input.input_filename.display()));
+                Some(journal)
+            },
+            None => {
+                let missing = self.missing_parts(manifest);
+                if !missing.is_empty() {
+                    self.report.error(&format!("Parts {} are missing, parts are not removed while the set is incomplete", missing.join(", ")));
+                    return;
+                }
+                None
+            },
+        };
+        let create_journal = || Self::check_file_access(Journal::create(journal_filename, CONSUME_PARTS, Some(merged_filename)));
+
+        let mut buffer = self.make_buffer(u64::MAX);
+        for index in 1..=manifest.part_count() {
+            let part_filename = self.make_output_filename(index);
+            let Some(record) = manifest.part(index).cloned() else {
+                self.report.error(&format!("Part {} is not recorded in the manifest", index));
+                return;
+            };
+
+            if journal.as_ref().is_some_and(|journal| journal.done_part(index).is_some()) {
+                // Interrupted after the part was recorded, but maybe before it was removed
+                if fs::symlink_metadata(&part_filename).is_ok() {
+                    Self::check_file_access(fs::remove_file(&part_filename));
+                }
+                continue;
+            }
+            let done_size = journal.as_ref().map_or(0, Journal::done_size);
+            if record.offset != done_size {
+                self.report.error(&format!("Part {} starts at {}, but {} bytes are merged", index, record.offset, done_size));
+                return;
+            }
+
+            self.report_part_started(index, &part_filename, record.offset, record.size);
+            let is_moved = record.offset == 0 && manifest.added_size(index) == 0;
+            if fs::metadata(&part_filename).is_err() {
+                // Interrupted right after the first part became the merged file
+                let is_renamed = index == 1 && journal.is_some() && fs::metadata(&temporary_filename).is_ok_and(|metadata| metadata.len() == record.size);
+                if !is_renamed {
+                    self.report.error(&format!("Part {} is missing", part_filename.display()));
+                    return;
+                }
+            } else if is_moved {
+                if let Err(reason) = self.check_first_part(&part_filename, &record, &mut buffer) {
+                    self.report.error(&reason);
+                    return;
+                }
+                journal.get_or_insert_with(create_journal);
+                if !Self::move_first_part(&part_filename, &temporary_filename) {
+                    if let Err(reason) = self.append_part_to(&temporary_filename, &part_filename, &record, manifest, &mut buffer) {
+                        self.report.error(&reason);
+                        return;
+                    }
+                }
+            } else if let Err(reason) = self.append_part_to(&temporary_filename, &part_filename, &record, manifest, &mut buffer) {
+                // Nothing is consumed yet, so nothing is left behind
+                if journal.is_none() {
+                    let _ = fs::remove_file(&temporary_filename);
+                }
+                self.report.error(&reason);
+                return;
+            }
+
+            let journal = journal.get_or_insert_with(create_journal);
+            Self::check_file_access(journal.append_part(&record));
+            if fs::symlink_metadata(&part_filename).is_ok() {
+                Self::check_file_access(fs::remove_file(&part_filename));
+                Self::check_file_access(output::sync_directory(&part_filename));
+            }
+            self.report_part_finished(&record, &part_filename);
+            self.report.message(&format!("File {} is merged and removed, total kilobytes written - {}", 
+                part_filename.display(),
+                (record.offset + record.size) / 1024
+            ));
+        }
+
+        Self::check_file_access(output::commit(&temporary_filename, merged_filename, self.program_input.clobber));
+        Self::check_file_access(output::sync_directory(merged_filename));
+        Self::check_file_access(fs::remove_file(self.make_manifest_filename()));
+        if let Some(journal) = journal {
+            Self::check_file_access(journal.remove());
+        }
+        self.report.message(&format!("File {} was merged into {}", self.program_input.input_filename.display(), merged_filename.display()));
+
+        self.report_done(start, &[
+            ("file_name", json::string(&merged_filename.to_string_lossy())),
+            ("parts", manifest.part_count().to_string()),
+            ("bytes", manifest.total_size.to_string()),
+            ("sha256", json::optional_string(manifest.sha256.as_deref())),
+        ]);
+    }
+
+    // Indexes of parts of the set which are not in the store
+    fn missing_parts(&self, manifest: &Manifest) -> Vec<String> {
+        return (1..=manifest.part_count())
+            .filter(|&index| Self::check_file_access(self.store.stat(&self.make_set_name(&index.to_string()))).is_none())
+            .map(|index| index.to_string())
+            .collect();
+    }
+
+    // The first part is moved instead of copied when split added nothing to it, so it is checked before
+    fn check_first_part(&self, part_filename: &Path, record: &PartRecord, buffer: &mut [u8]) -> Result<(), String> {
+        let mut file = File::open(part_filename).map_err(|err| format!("Part {} cannot be opened, {}", part_filename.display(), err))?;
+        let mut part_hash = Sha256::new();
+        let mut left = record.size;
+        while left > 0 {
+            let length = min(left, buffer.len() as u64) as usize;
+            let size = Self::check_file_access(file.read(&mut buffer[..length]));
+            if size == 0 {
+                break;
+            }
+            part_hash.update(&buffer[..size]);
+            left -= size as u64;
+        }
+        let is_damaged = left > 0 || Self::check_file_access(file.metadata()).len() != record.size;
+        if is_damaged || record.sha256.as_deref().is_some_and(|sha256| sha256 != part_hash.finish_hex()) {
+            return Err(format!("Part {} is damaged, its SHA-256 doesn't match the manifest", part_filename.display()));
+        }
+        return Ok(());
+    }
+
+    // False when the part is on another file system and has to be copied
+    fn move_first_part(part_filename: &Path, merged_filename: &Path) -> bool {
+        if fs::rename(part_filename, merged_filename).is_err() {
+            return false;
+        }
+        Self::check_file_access(output::sync_directory(merged_filename));
+        return true;
+    }
+
+    // Puts the part at `record.offset` of the merged file and syncs it, checking its hash on the way
+    fn append_part_to(&self, merged_filename: &Path, part_filename: &Path, record: &PartRecord, manifest: &Manifest, buffer: &mut [u8]) -> Result<(), String> {
+        let mut part_hash = Sha256::new();
+        let mut file = Self::check_file_access(copy::open(OpenOptions::new().read(true), part_filename, self.program_input.direct));
+        let mut merged = Self::check_file_access(copy::open(
+            OpenOptions::new().write(true).create(true).truncate(false),
+            merged_filename,
+            self.program_input.direct
+        ));
+        // Bytes after the last recorded part are left by an interrupted append
+        Self::check_file_access(merged.set_len(record.offset));
+        Self::check_file_access(copy::copy_range(
+            &mut file, manifest.header_size(record.index), &mut merged, record.offset, record.size,
+            self.copy_options(), buffer, &mut [&mut part_hash],
+            |done| self.report_progress(record.index, done, record.offset + done, manifest.total_size)
+        ));
+        if record.sha256.as_deref().is_some_and(|sha256| sha256 != part_hash.finish_hex()) {
+            Self::check_file_access(merged.set_len(record.offset));
+            return Err(format!("Part {} is damaged, its SHA-256 doesn't match the manifest", part_filename.display()));
+        }
+        Self::check_file_access(merged.sync_all());
+        return Ok(());
+    }
+
+    pub fn verify(&mut self) -> bool {
+        let parts = self.find_parts();
+        if parts.is_empty() {
+            println!("There are no parts of {}", self.program_input.input_filename.display());
+            return false;
+        }
+        if let Some(part) = parts.iter().enumerate().find(|(i, part)| part.index != i + 1) {
+            println!("Part {} is missing", part.0 + 1);
+            return false;
+        }
+
+        let manifest = self.read_manifest();
+        let original_filename = self.original_filename(manifest.as_ref());
+        let original = OpenOptions::new()
+            .read(true)
+            .open(&original_filename);
+        let Ok(mut original) = original else {
+            if let Some(manifest) = manifest {
+                return self.verify_by_manifest(&parts, &manifest);
+            }
+
+            // Without the original all we know is that every part but the last has the same size
+            let fragment_size = parts[0].size;
+            for (i, part) in parts.iter().enumerate() {
+                if part.size > fragment_size || (i + 1 < parts.len() && part.size != fragment_size) {
+                    println!("File {} has unexpected size {} bytes, other parts are {} bytes", part.filename.display(), part.size, fragment_size);
+                    return false;
+                }
+            }
+            println!("File {} is not found, only sizes of {} parts were checked", 
+                original_filename.display(),
+                parts.len()
+            );
+            return true;
+        };
+
+        let original_size = Self::check_file_access(original.metadata()).len();
+        let header_size = |index| manifest.as_ref().map(|manifest| manifest.header_size(index)).unwrap_or(0);
+        let added_size = |index| manifest.as_ref().map(|manifest| manifest.added_size(index)).unwrap_or(0);
+        let parts_size: u64 = parts.iter().map(|part| part.size - added_size(part.index).min(part.size)).sum();
+        if original_size != parts_size {
+            println!("Parts have {} bytes in total, but file {} has {} bytes", 
+                parts_size,
+                original_filename.display(),
+                original_size
+            );
+            return false;
+        }
+
+        let mut buffer = self.make_buffer(u64::MAX);
+        let mut original_buffer = self.make_buffer(u64::MAX);
+        let mut offset = 0u64;
+
+        for part in &parts {
+            let file = Self::check_file_access(self.store.read(&self.make_set_name(&part.index.to_string()), header_size(part.index)));
+            let mut file = file.take(part.size - added_size(part.index).min(part.size));
+
+            loop {
+                let size = Self::check_file_access(file.read(&mut buffer));
+                if size == 0 {
+                    break;
+                }
+                Self::check_file_access(original.read_exact(&mut original_buffer[..size]));
+
+                if let Some(position) = (0..size).find(|&i| buffer[i] != original_buffer[i]) {
+                    println!("File {} differs from {} at byte {}", 
+                        part.filename.display(),
+                        original_filename.display(),
+                        offset + position as u64
+                    );
+                    return false;
+                }
+                offset += size as u64;
+            }
+            println!("File {} is ok", part.filename.display());
+        }
+
+        println!("All {} parts match {}", parts.len(), original_filename.display());
+        return true;
+    }
+
+    fn verify_by_manifest(&self, parts: &[FoundPart], manifest: &Manifest) -> bool {
+        if parts.len() != manifest.part_count() {
+            println!("There are {} parts, but {} were made", parts.len(), manifest.part_count());
+            return false;
+        }
+
+        let mut whole_hash = Sha256::new();
+        let mut buffer = self.make_buffer(u64::MAX);
+
+        for part in parts {
+            let record = manifest.part(part.index);
+            let added_size = manifest.added_size(part.index);
+            if let Some(record) = record.filter(|record| record.size + added_size != part.size) {
+                println!("File {} has {} bytes, but {} were written", part.filename.display(), part.size, record.size + added_size);
+                return false;
+            }
+
+            let file = Self::check_file_access(self.store.read(&self.make_set_name(&part.index.to_string()), manifest.header_size(part.index)));
+            let mut file = file.take(part.size - added_size.min(part.size));
+            let mut part_hash = Sha256::new();
+            loop {
+                let size = Self::check_file_access(file.read(&mut buffer));
+                if size == 0 {
+                    break;
+                }
+                part_hash.update(&buffer[..size]);
+                whole_hash.update(&buffer[..size]);
+            }
+
+            match record.and_then(|record| record.sha256.as_ref()) {
+                Some(expected) if *expected != part_hash.finish_hex() => {
+                    println!("File {} is damaged, its SHA-256 does not match", part.filename.display());
+                    return false;
+                },
+                Some(_) => println!("File {} is ok", part.filename.display()),
+                None => println!("File {} has no recorded hash, skipped", part.filename.display()),
+            }
+        }
+
+        if let Some(expected) = &manifest.sha256 {
+            if *expected != whole_hash.finish_hex() {
+                println!("Parts are fine one by one, but together they do not make {}", manifest.file_name.to_string_lossy());
+                return false;
+            }
+        }
+
+        println!("All {} parts match the manifest of {}", parts.len(), manifest.file_name.to_string_lossy());
+        return true;
+    }
+
+    pub fn info(&mut self) {
+        let manifest = self.read_manifest();
+        // Sizes are of the original bytes, without a repeated header
+        let parts: Vec<FoundPart> = self.find_parts().into_iter()
+            .map(|part| FoundPart{
+                size: part.size.saturating_sub(manifest.as_ref().map(|manifest| manifest.added_size(part.index)).unwrap_or(0)),
+                ..part
+            })
+            .collect();
+        if parts.is_empty() && manifest.is_none() {
+            println!("There are no parts of {}", self.program_input.input_filename.display());
+            return;
+        }
+
+        let fragment_size = manifest.as_ref()
+            .map(|manifest| manifest.fragment_size)
+            .unwrap_or_else(|| parts[0].size);
+        let last_index = parts.iter().map(|part| part.index)
+            .chain(manifest.as_ref().map(|manifest| manifest.part_count()))
+            .max()
+            .unwrap_or(0);
+        let planned_offsets: Vec<u64> = manifest.as_ref()
+            .map(|manifest| manifest.planned_sizes().iter()
+                .scan(0, |offset, size| { *offset += size; Some(*offset - size) })
+                .collect())
+            .unwrap_or_default();
+        let missing: Vec<usize> = (1..=last_index)
+            .filter(|index| !parts.iter().any(|part| part.index == *index))
+            .collect();
+
+        // Every index from 1 to the last one, with what is on disk and what was recorded
+        let rows: Vec<(usize, Option<&FoundPart>, u64, Option<&PartRecord>)> = (1..=last_index)
+            .map(|index| {
+                let found = parts.iter().find(|part| part.index == index);
+                let record = manifest.as_ref().and_then(|manifest| manifest.part(index));
+                let offset = record.map(|record| record.offset)
+                    .or(planned_offsets.get(index - 1).copied())
+                    .unwrap_or((index as u64 - 1) * fragment_size);
+                (index, found, offset, record)
+            })
+            .collect();
+
+        if self.program_input.json {
+            let rows: Vec<String> = rows.iter()
+                .map(|(index, found, offset, record)| {
+                    let size = found.map(|part| part.size);
+                    let expected_size = record.map(|record| record.size);
+                    json::object(&[
+                        ("index", index.to_string()),
+                        ("path", json::optional_string(found.map(|part| part.filename.to_string_lossy()).as_deref())),
+                        ("present", found.is_some().to_string()),
+                        ("size", size.map(|size| size.to_string()).unwrap_or("null".to_string())),
+                        ("expected_size", expected_size.map(|size| size.to_string()).unwrap_or("null".to_string())),
+                        ("offset", offset.to_string()),
+                        ("end", size.or(expected_size).map(|size| (offset + size).to_string()).unwrap_or("null".to_string())),
+                        ("sha256", json::optional_string(record.and_then(|record| record.sha256.as_deref()))),
+                    ])
+                })
+                .collect();
+
+            println!("{}", json::object(&[
+                ("file_name", json::optional_string(manifest.as_ref().map(|manifest| manifest.file_name.to_string_lossy()).as_deref())),
+                ("total_size", manifest.as_ref().map(|manifest| manifest.total_size.to_string()).unwrap_or("null".to_string())),
+                ("found_size", parts.iter().map(|part| part.size).sum::<u64>().to_string()),
+                ("fragment_size", fragment_size.to_string()),
+                ("part_count", manifest.as_ref().map(|manifest| manifest.part_count().to_string()).unwrap_or("null".to_string())),
+                ("found_parts", parts.len().to_string()),
+                ("missing", json::array(&missing.iter().map(|index| index.to_string()).collect::<Vec<_>>())),
+                ("compression", json::optional_string(manifest.as_ref().map(|manifest| manifest.compression.as_str()))),
+                ("encryption", json::optional_string(manifest.as_ref().map(|manifest| manifest.encryption.as_str()))),
+                ("sha256", json::optional_string(manifest.as_ref().and_then(|manifest| manifest.sha256.as_deref()))),
+                ("parts", json::array(&rows)),
+            ]));
+            return;
+        }
+
+        let unknown = "unknown, there is no manifest".to_string();
+        println!("File name:       {}", manifest.as_ref().map(|manifest| manifest.file_name.to_string_lossy().to_string()).unwrap_or(unknown.clone()));
+        println!("Total size:      {}", manifest.as_ref().map(|manifest| format!("{} bytes", manifest.total_size)).unwrap_or(unknown.clone()));
+        println!("Fragment size:   {} bytes", fragment_size);
+        println!("Parts:           {} found{}", 
+            parts.len(),
+            manifest.as_ref().map(|manifest| format!(" of {}", manifest.part_count())).unwrap_or_default()
+        );
+        println!("Missing parts:   {}", 
+            if missing.is_empty() { "none".to_string() } 
+            else { missing.iter().map(|index| index.to_string()).collect::<Vec<_>>().join(", ") }
+        );
+        if let Some(manifest) = &manifest {
+            println!("Compression:     {}", manifest.compression);
+            println!("Encryption:      {}", manifest.encryption);
+            println!("SHA-256:         {}", manifest.sha256.as_deref().unwrap_or("not recorded"));
+        }
+        println!();
+
+        let path_width = parts.iter().map(|part| part.filename.display().to_string().chars().count()).max().unwrap_or(0).max("missing".len());
+        println!("{:>5}  {:<path_width$}  {:>14}  {:<29}  SHA-256", "Part", "Path", "Size", "Range", path_width = path_width);
+        for (index, found, offset, record) in &rows {
+            let size = found.map(|part| part.size).or(record.map(|record| record.size));
+            let range = size.map(|size| format!("{}..{}", offset, offset + size)).unwrap_or("?".to_string());
+            let mut size = size.map(|size| size.to_string()).unwrap_or("?".to_string());
+            if let (Some(found), Some(record)) = (found, record) {
+                if found.size != record.size {
+                    size = format!("{} (!= {})", found.size, record.size);
+                }
+            }
+            println!("{:>5}  {:<path_width$}  {:>14}  {:<29}  {}", 
+                index,
+                found.map(|part| part.filename.display().to_string()).unwrap_or("missing".to_string()),
+                size,
+                range,
+                record.and_then(|record| record.sha256.as_deref()).unwrap_or("-"),
+                path_width = path_width
+            );
+        }
+    }
+
+    pub fn list(&mut self) {
+        for part in self.find_parts() {
+            println!("{}\t{}", part.filename.display(), part.size);
+        }
+    }
+
+    // Sends every part and then the manifest to a Telegram chat, parts which are already there are skipped
+    pub fn upload(&mut self) -> bool {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let Some((bot, chat)) = self.telegram_bot() else {
+            return false;
+        };
+        let Some(mut manifest) = self.read_manifest() else {
+            self.report.error(&format!("There is no manifest of {}, only sets with a manifest can be uploaded", self.program_input.input_filename.display()));
+            return false;
+        };
+
+        let parts = self.find_parts();
+        let part_count = manifest.part_count();
+        for index in 1..=part_count {
+            let expected_size = manifest.part(index).map(|record| record.size + manifest.added_size(index));
+            match parts.iter().find(|part| part.index == index) {
+                None => {
+                    self.report.error(&format!("Part {} is missing, only a whole set can be uploaded", self.make_output_filename(index).display()));
+                    return false;
+                },
+                Some(part) if expected_size.is_some_and(|size| size != part.size) => {
+                    self.report.error(&format!("File {} has {} bytes, but {} were written", part.filename.display(), part.size, expected_size.unwrap()));
+                    return false;
+                },
+                Some(part) if part.size > telegram::TRANSFER_LIMIT && bot.is_default_api() => {
+                    self.report.error(&format!(
+                        "File {} has {} bytes, but bots can download at most 20 MB from api.telegram.org, split with `-S 20MB` or use a local Bot API server with `--telegram-api`",
+                        part.filename.display(),
+                        part.size
+                    ));
+                    return false;
+                },
+                Some(_) => { },
+            }
+        }
+
+        let file_name = manifest.file_name.to_string_lossy().to_string();
+        let manifest_name = self.make_set_name("manifest");
+        let mut sent_count = 0;
+        for part in parts.iter().filter(|part| part.index <= part_count) {
+            let is_sent = telegram::sent_chat(&manifest) == Some(chat.as_str())
+                && telegram::sent_parts(&manifest).iter().any(|(index, _)| *index == part.index);
+            if is_sent {
+                self.report.message(&format!("File {} is already in the chat", part.filename.display()));
+                continue;
+            }
+
+            let name = self.make_set_name(&part.index.to_string());
+            let caption = format!("{}, part {} of {}", file_name, part.index, part_count);
+            let sent = telegram::retry(
+                || {
+                    let mut reader = self.store.read(&name, 0)?;
+                    bot.send_document(&chat, &name.to_string_lossy(), part.size, &mut reader, &caption)
+                },
+                |err, delay| self.report.warning(&format!("{} is not sent, {}, trying again in {} s", part.filename.display(), err, delay.as_secs()))
+            );
+            let sent = match sent {
+                Ok(sent) => sent,
+                Err(err) => {
+                    self.report.error(&format!("File {} cannot be sent, {}", part.filename.display(), err));
+                    return false;
+                },
+            };
+
+            // The manifest is rewritten after every part, so an interrupted upload goes on from here
+            telegram::record_sent_part(&mut manifest, &chat, part.index, &sent);
+            Self::check_file_access(manifest.write(self.store.as_ref(), &manifest_name, ClobberPolicy::Force));
+            sent_count += 1;
+            self.report.event("part_uploaded", &[
+                ("index", part.index.to_string()),
+                ("path", json::string(&part.filename.to_string_lossy())),
+                ("message_id", sent.message_id.to_string()),
+            ]);
+            self.report.message(&format!("File {} is sent, {} of {}", part.filename.display(), part.index, part_count));
+        }
+
+        let text = manifest.to_string();
+        let caption = format!("{}, manifest of {} parts", file_name, part_count);
+        let sent = telegram::retry(
+            || {
+                let sent = bot.send_document(&chat, &manifest_name.to_string_lossy(), text.len() as u64, &mut text.as_bytes(), &caption)?;
+                bot.pin(&chat, sent.message_id)?;
+                Ok(sent)
+            },
+            |err, delay| self.report.warning(&format!("manifest is not sent, {}, trying again in {} s", err, delay.as_secs()))
+        );
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(err) => {
+                self.report.error(&format!("Manifest {} cannot be sent, {}", self.make_manifest_filename().display(), err));
+                return false;
+            },
+        };
+        self.report.message(&format!(
+            "All {} parts of {} are in the chat, the manifest is pinned, it is message {} for `download --manifest-message` when another one is pinned",
+            part_count,
+            file_name,
+            sent.message_id
+        ));
+
+        self.report_done(start, &[
+            ("file_name", json::string(&file_name)),
+            ("chat", json::string(&chat)),
+            ("manifest_message_id", sent.message_id.to_string()),
+            ("parts", sent_count.to_string()),
+            ("part_count", part_count.to_string()),
+        ]);
+        return true;
+    }
+
+    // Fetches the set back by the manifest which is pinned in the chat, or by the message with it,
+    // parts which are already here are skipped
+    pub fn download(&mut self) -> bool {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let Some((bot, chat)) = self.telegram_bot() else {
+            return false;
+        };
+
+        let text = telegram::retry(
+            || {
+                let document = match self.program_input.manifest_message {
+                    Some(message_id) => bot.document(&chat, message_id)?,
+                    None => bot.pinned_document(&chat)?,
+                };
+                let Some((file_id, file_name)) = document else {
+                    return Ok(None);
+                };
+                if Self::split_set_filename(OsStr::new(&file_name)).is_none_or(|(_, tag)| tag != "manifest") {
+                    return Ok(None);
+                }
+                let mut text = String::new();
+                bot.download(&file_id)?.read_to_string(&mut text)?;
+                Ok(Some((file_name, text)))
+            },
+            |err, delay| self.report.warning(&format!("manifest is not fetched, {}, trying again in {} s", err, delay.as_secs()))
+        );
+        let (manifest_name, text) = match text {
+            Ok(Some(pinned)) => pinned,
+            Ok(None) => {
+                match self.program_input.manifest_message {
+                    Some(message_id) => self.report.error(&format!("Message {} in chat {} is not a manifest", message_id, chat)),
+                    None => self.report.error(&format!("There is no pinned manifest in chat {}", chat)),
+                }
+                return false;
+            },
+            Err(err) => {
+                self.report.error(&format!("Manifest cannot be fetched from chat {}, {}", chat, err));
+                return false;
+            },
+        };
+        let manifest = match Manifest::parse(&text) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                self.report.error(&format!("Manifest {} cannot be read, {}", manifest_name, err));
+                return false;
+            },
+        };
+        if Self::split_set_filename(OsStr::new(&manifest_name)).is_none_or(|(stem, _)| stem != self.set_stem) {
+            self.report.error(&format!("Manifest {} is not of {}, give the message with its manifest, which upload tells, in `--manifest-message`",
+                manifest_name,
+                self.set_stem.to_string_lossy()
+            ));
+            return false;
+        }
+        if self.program_input.clobber == ClobberPolicy::Default {
+            if let Err(reason) = self.check_existing_set(manifest.set_id.as_deref().unwrap_or(""), manifest.total_size) {
+                self.report.error(&format!("{}, use `--force` or `--backup` to overwrite them", reason));
+                return false;
+            }
+        }
+
+        let sent_parts = telegram::sent_parts(&manifest);
+        let part_count = manifest.part_count();
+        let mut buffer = self.make_buffer(u64::MAX);
+        let mut downloaded_count = 0;
+        for index in 1..=part_count {
+            let filename = self.make_output_filename(index);
+            let (Some(record), Some((_, sent))) = (manifest.part(index), sent_parts.iter().find(|(sent_index, _)| *sent_index == index)) else {
+                self.report.error(&format!("Part {} of {} was not sent to the chat", index, part_count));
+                return false;
+            };
+            let name = self.make_set_name(&index.to_string());
+            let header_size = manifest.header_size(index);
+            let expected_size = record.size + manifest.added_size(index);
+            match Self::check_file_access(self.store.stat(&name)) {
+                Some(size) if size == expected_size => {
+                    self.report.message(&format!("File {} is already here", filename.display()));
+                    continue;
+                },
+                Some(_) if self.program_input.clobber == ClobberPolicy::NoClobber => {
+                    self.report.error(&format!("File {} already exists and `--no-clobber` is given", filename.display()));
+                    return false;
+                },
+                _ => { },
+            }
+
+            self.report_part_started(index, &filename, record.offset, record.size);
+            let downloaded = telegram::retry(
+                || {
+                    let mut reader = bot.download(&sent.file_id)?;
+                    let mut writer = self.store.create(&name, expected_size, self.program_input.clobber)?;
+                    // Only bytes of the original are hashed, not headers and trailers around them
+                    let mut hash = Sha256::new();
+                    let mut copied = 0u64;
+                    loop {
+                        let size = reader.read(&mut buffer)?;
+                        if size == 0 {
+                            break;
+                        }
+                        let hashed_start = header_size.saturating_sub(copied).min(size as u64) as usize;
+                        let hashed_end = (header_size + record.size).saturating_sub(copied).min(size as u64) as usize;
+                        hash.update(&buffer[hashed_start..hashed_end.max(hashed_start)]);
+                        writer.write_all(&buffer[..size])?;
+                        copied += size as u64;
+                        self.report_progress(index, copied, copied, expected_size);
+                    }
+                    if copied != expected_size {
+                        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("{} bytes came, but {} were expected", copied, expected_size)));
+                    }
+                    if record.sha256.as_ref().is_some_and(|expected| *expected != hash.finish_hex()) {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "its SHA-256 does not match"));
+                    }
+                    writer.finish()
+                },
+                |err, delay| self.report.warning(&format!("{} is not downloaded, {}, trying again in {} s", filename.display(), err, delay.as_secs()))
+            );
+            if let Err(err) = downloaded {
+                self.report.error(&format!("File {} cannot be downloaded, {}", filename.display(), err));
+                return false;
+            }
+            downloaded_count += 1;
+            self.report_part_finished(record, &filename);
+            self.report.message(&format!("File {} is downloaded, {} of {}", filename.display(), index, part_count));
+        }
+
+        // Telegram keys stay, so the set can be fetched again without a new upload
+        Self::check_file_access(manifest.write(self.store.as_ref(), &self.make_set_name("manifest"), ClobberPolicy::Force));
+        self.sync_at_end();
+
+        let store = if self.is_store_local() { String::new() } else { format!(" --store {}", self.set_directory().display()) };
+        self.report.message(&format!("All {} parts of {} are here, put them together with `splimer merge{} {}`",
+            part_count,
+            manifest.file_name.to_string_lossy(),
+            store,
+            self.make_manifest_filename().display()
+        ));
+        self.report_done(start, &[
+            ("file_name", json::string(&manifest.file_name.to_string_lossy())),
+            ("manifest", json::string(&self.make_manifest_filename().to_string_lossy())),
+            ("parts", downloaded_count.to_string()),
+            ("part_count", part_count.to_string()),
+        ]);
+        return true;
+    }
+
+    // Bot and chat of `--telegram`, errors are reported
+    fn telegram_bot(&self) -> Option<(TelegramBot, String)> {
+        if !self.program_input.telegram {
+            self.report.error("Parts can only be sent to Telegram for now, add `--telegram`");
+            return None;
+        }
+        let Some(chat) = self.program_input.chat.clone() else {
+            self.report.error("There is no chat, add `--chat` with its id or `@channel`");
+            return None;
+        };
+        return match TelegramBot::new(self.program_input.telegram_api.as_deref()) {
+            Ok(bot) => Some((bot, chat)),
+            Err(err) => {
+                self.report.error(&format!("Telegram cannot be used, {}", err));
+                None
+            },
+        };
+    }
+
+    // All parts lying in the directory of the set, sorted by their numbers
+    fn find_parts(&self) -> Vec<FoundPart> {
+        let mut parts = Vec::new();
+
+        let files = match self.store.list() {
+            Ok(files) => files,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return parts,
+            Err(err) => {
+                self.report.warning(&format!("parts in {} cannot be listed, {}", self.set_directory().display(), err));
+                return parts;
+            },
+        };
+        for file in files {
+            let Some((stem, tag)) = Self::split_set_filename(&file.name) else {
+                continue;
+            };
+            let Ok(index) = tag.parse::<usize>() else {
+                continue;
+            };
+            if stem != self.set_stem || index == 0 {
+                continue;
+            }
+            parts.push(FoundPart{
+                index,
+                filename: self.make_output_filename(index),
+                size: file.size,
+            });
+        }
+
+        parts.sort_by_key(|part| part.index);
+        return parts;
+    }
+
+    // When pointed at one of the set files, the original is the file recorded in the manifest
+    fn original_filename(&self, manifest: Option<&Manifest>) -> PathBuf {
+        let path = &self.program_input.input_filename;
+        let is_set_file = path.file_name().and_then(Self::split_set_filename).is_some();
+
+        match manifest.filter(|_| is_set_file) {
+            Some(manifest) => path.with_file_name(&manifest.file_name),
+            None => path.clone(),
+        }
+    }
+
+    fn read_manifest(&self) -> Option<Manifest> {
+        let filename = self.make_manifest_filename();
+        match self.store.stat(&self.make_set_name("manifest")) {
+            Ok(Some(_)) => { },
+            Ok(None) => return None,
+            Err(err) => {
+                self.report.warning(&format!("manifest {} is ignored, {}", filename.display(), err));
+                return None;
+            },
+        }
+        match Manifest::read(self.store.as_ref(), &self.make_set_name("manifest")) {
+            Ok(manifest) => Some(manifest),
+            Err(err) => {
+                self.report.warning(&format!("manifest {} is ignored, {}", filename.display(), err));
+                None
+            }
+        }
+    }
+
+    // With `--part-number` parts are made one by one, so the manifest is filled in by several runs
+    fn manifest_for_split(&self, file_size: u64, part_sizes: &[u64], set_id: &str) -> Manifest {
+        let file_name = self.input_file_name();
+
+        if self.program_input.part_number.is_some() {
+            if let Some(manifest) = self.read_manifest().filter(|manifest|
+                manifest.set_id.as_deref() == Some(set_id) && 
+                manifest.file_name == file_name && 
+                manifest.total_size == file_size && 
+                manifest.planned_sizes() == part_sizes
+            ) {
+                return manifest;
+            }
+        }
+        let mut manifest = Manifest::new(file_name, file_size, 0);
+        manifest.set_planned_sizes(part_sizes);
+        manifest.set_id = Some(set_id.to_string());
+        return manifest;
+    }
+
+    // Identifies the source by its size, beginning and end, so it is cheap even for huge files
+    // and stays the same when the file is copied or touched
+    fn make_set_id(file: &mut File, file_size: u64) -> std::io::Result<String> {
+        const SAMPLE_SIZE: u64 = 64 * 1024;
+
+        let mut hash = Sha256::new();
+        hash.update(&file_size.to_le_bytes());
+
+        let mut sample = vec![0; min(SAMPLE_SIZE, file_size) as usize];
+        file.read_exact(&mut sample)?;
+        hash.update(&sample);
+        file.seek(SeekFrom::Start(file_size - sample.len() as u64))?;
+        file.read_exact(&mut sample)?;
+        hash.update(&sample);
+        file.seek(SeekFrom::Start(0))?;
+
+        return Ok(hash.finish_hex()[..16].to_string());
+    }
+
+    // Parts with the same name in the output directory may have been made from another file
+    fn check_existing_set(&self, set_id: &str, file_size: u64) -> Result<(), String> {
+        let manifest_filename = self.make_manifest_filename();
+        let manifest_name = self.make_set_name("manifest");
+        if !self.store.stat(&manifest_name).is_ok_and(|size| size.is_some()) {
+            return match self.find_parts().first() {
+                Some(part) => Err(format!("there are parts like {} without manifest", part.filename.display())),
+                None => Ok(()),
+            };
+        }
+
+        let manifest = Manifest::read(self.store.as_ref(), &manifest_name)
+            .map_err(|err| format!("manifest {} cannot be read, {}", manifest_filename.display(), err))?;
+        let is_same_source = match &manifest.set_id {
+            Some(id) => id == set_id,
+            // Manifests without `set_id` can only be compared by size
+            None => manifest.total_size == file_size,
+        };
+        if !is_same_source {
+            return Err(format!("parts in {} were made from another {} of {} bytes", 
+                self.readable_set_directory().display(),
+                manifest.file_name.to_string_lossy(),
+                manifest.total_size
+            ));
+        }
+        return Ok(());
+    }
+
+    // Files which split would replace, planned parts and the manifest when the whole file is split
+    fn existing_split_outputs(&self, plan: &SplitPlan) -> Vec<PathBuf> {
+        let mut filenames: Vec<PathBuf> = plan.parts.iter().map(|part| part.filename.clone()).collect();
+        if self.program_input.part_number.is_none() {
+            filenames.push(plan.manifest_filename.clone());
+        }
+        filenames.retain(|filename| {
+            let name = filename.file_name().unwrap_or(filename.as_os_str());
+            self.store.stat(name).is_ok_and(|size| size.is_some())
+        });
+        return filenames;
+    }
+
+    // Size of the manifest as it will be after split, with every planned part and hash in it
+    fn estimate_manifest_size(manifest: &Manifest, plan: &SplitPlan) -> u64 {
+        let mut manifest = manifest.clone();
+        let hash = "0".repeat(64);
+        for part in &plan.parts {
+            manifest.set_part(PartRecord{ index: part.index, offset: part.offset, size: part.size, sha256: Some(hash.clone()) });
+        }
+        manifest.sha256 = Some(hash);
+        return manifest.to_string().len() as u64;
+    }
+
+    // After splitting into fewer parts than before, the old tail would be picked up by merge
+    fn remove_stale_parts(&self, last_index: usize) {
+        for part in self.find_parts().iter().filter(|part| part.index > last_index) {
+            match self.store.delete(&self.make_set_name(&part.index.to_string()), self.program_input.clobber) {
+                Ok(_) => self.report.message(&format!("Stale part {} is removed", part.filename.display())),
+                Err(err) => self.report.warning(&format!("stale part {} cannot be removed, {}", part.filename.display(), err)),
+            }
+        }
+    }
+
+    // Copies `part` of `file` into its own file in the store, which is left open until `finish_part`
+    fn copy_part(&mut self, file: &mut File, part: &PlannedPart, buffer: &mut [u8], file_hash: Option<&mut Sha256>, bytes_before: u64, total_size: u64) {
+        self.report_part_started(part.index, &part.filename, part.offset, part.size);
+
+        let header: &[u8] = match self.part_headers.get(part.index - 1) {
+            Some(header) => header,
+            None if part.index > 1 => &self.repeated_header,
+            None => &[],
+        };
+        let trailer: &[u8] = self.part_trailers.get(part.index - 1).map(Vec::as_slice).unwrap_or(&[]);
+        let mut writer = Self::check_file_access(self.store.create(
+            &self.make_set_name(&part.index.to_string()),
+            header.len() as u64 + part.size + trailer.len() as u64,
+            self.program_input.clobber
+        ));
+        let mut part_hash = Sha256::new();
+        let mut hashes = vec![&mut part_hash];
+        hashes.extend(file_hash);
+        let progress = |done| self.report_progress(part.index, done, bytes_before + done, total_size);
+        let copied = match writer.file() {
+            Some(target) => {
+                Self::check_file_access(copy::write_all_at(target, 0, header));
+                let copied = copy::copy_range(
+                    file, part.offset, target, header.len() as u64, part.size,
+                    self.copy_options(), buffer, &mut hashes, progress
+                );
+                Self::check_file_access(copy::write_all_at(target, header.len() as u64 + part.size, trailer));
+                copied
+            },
+            // Remote parts are uploaded as they are read
+            None => {
+                Self::check_file_access(writer.write_all(header));
+                let copied = copy::copy_range_to_stream(file, part.offset, &mut writer, part.size, buffer, &mut hashes, progress);
+                Self::check_file_access(writer.write_all(trailer));
+                copied.map(|_| CopyMethod::Buffered)
+            },
+        };
+        self.copy_method = Some(Self::check_file_access(copied));
+        self.current_part = Some(writer);
+        self.current_file_hash = part_hash;
+    }
+
+    // No bigger than what is copied at once, but whole blocks for `O_DIRECT`
+    fn make_buffer(&self, limit: u64) -> Buffer {
+        let size = min(self.program_input.buffer_size as u64, limit) as usize;
+        if self.program_input.direct {
+            return Buffer::new(size.next_multiple_of(copy::DIRECT_ALIGNMENT));
+        }
+        return Buffer::new(size);
+    }
+
+    fn size_spec(&self) -> SizeSpec {
+        return SizeSpec{
+            limits: self.program_input.fragment_sizes.iter().map(|&size| size as u64).collect(),
+            rest: self.program_input.rest_part,
+            parts: self.program_input.parts,
+            strategy: self.program_input.strategy,
+        };
+    }
+
+    fn copy_options(&self) -> CopyOptions {
+        return CopyOptions{
+            zero_copy: self.program_input.zero_copy,
+            io: self.program_input.io,
+        };
+    }
+
+    fn report_part_started(&self, index: usize, filename: &Path, offset: u64, size: u64) {
+        self.report.event("part_started", &[
+            ("index", index.to_string()),
+            ("path", json::string(&filename.to_string_lossy())),
+            ("offset", offset.to_string()),
+            ("size", size.to_string()),
+        ]);
+    }
+
+    fn report_progress(&self, index: usize, part_bytes: u64, total_bytes: u64, total_size: u64) {
+        self.report.progress(&[
+            ("index", index.to_string()),
+            ("part_bytes", part_bytes.to_string()),
+            ("bytes", total_bytes.to_string()),
+            ("total_size", total_size.to_string()),
+        ]);
+    }
+
+    fn report_part_finished(&self, record: &PartRecord, filename: &Path) {
+        self.report.event("part_finished", &[
+            ("index", record.index.to_string()),
+            ("path", json::string(&filename.to_string_lossy())),
+            ("offset", record.offset.to_string()),
+            ("size", record.size.to_string()),
+            ("sha256", json::optional_string(record.sha256.as_deref())),
+        ]);
+    }
+
+    fn report_done(&self, start: u128, fields: &[(&str, String)]) {
+        let elapsed = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - start) as f64 / 1000f64;
+        let mut fields = fields.to_vec();
+        fields.push(("copy", json::optional_string(self.copy_method.map(|method| method.name()))));
+        fields.push(("elapsed", elapsed.to_string()));
+        self.report.event("done", &fields);
+        self.report.message(&format!("The job is done! Total passed {:?} s", elapsed));
+    }
+
+    fn record_part(&mut self, manifest: &mut Manifest, part: &PlannedPart) {
+        let hash = std::mem::replace(&mut self.current_file_hash, Sha256::new());
+        manifest.set_part(PartRecord{
+            index: part.index,
+            offset: part.offset,
+            size: part.size,
+            sha256: Some(hash.finish_hex()),
+        });
+    }
+
+    fn open_file_for_write(&mut self, filename: &Path) {
+        let temporary_filename = output::temporary_path(filename);
+        self.current_file_hash = Sha256::new();
+        self.current_file_to_write = Some(
+            Self::check_file_access(copy::open(
+                OpenOptions::new().write(true).truncate(true).create(true),
+                &temporary_filename,
+                self.program_input.direct
+            ))
+        );
+        self.current_file_paths = Some((temporary_filename, filename.to_path_buf()));
+    }
+
+    // Closes the file and removes it, it never gets its real name
+    fn discard_file(&mut self) {
+        self.current_file_to_write = None;
+        if let Some((temporary_filename, _)) = self.current_file_paths.take() {
+            Self::check_file_access(fs::remove_file(temporary_filename));
+        }
+    }
+
+    // Closes the file and moves it to its real name
+    fn finish_file(&mut self) {
+        self.current_file_to_write = None;
+        if let Some((temporary_filename, filename)) = self.current_file_paths.take() {
+            Self::check_file_access(output::commit(&temporary_filename, &filename, self.program_input.clobber));
+            match self.program_input.fsync {
+                FsyncPolicy::Never => { },
+                // The file itself is synced by `flush` before it is renamed
+                FsyncPolicy::PerPart => Self::check_file_access(output::sync_directory(&filename)),
+                FsyncPolicy::End => self.unsynced_files.push(filename),
+            }
+        }
+    }
+
+    // Parts whose hooks succeed are removed with `--delete-after-hook`, false when a hook fails
+    fn part_hooks_done(&mut self, done: Result<Vec<FinishedPart>, HookError>) -> bool {
+        let parts = match done {
+            Ok(parts) => parts,
+            Err(err) => {
+                self.report.error(&format!("Hook of {} fails, {}", err.part.path.display(), err.reason));
+                return false;
+            },
+        };
+        for part in parts.iter().filter(|_| self.program_input.delete_after_hook) {
+            match self.store.delete(&part.name, ClobberPolicy::Force) {
+                Ok(_) => self.report.message(&format!("File {} is removed after its hook", part.path.display())),
+                Err(err) => self.report.warning(&format!("{} cannot be removed after its hook, {}", part.path.display(), err)),
+            }
+        }
+        return true;
+    }
+
+    // Puts the written part under its real name in the store
+    fn finish_part(&mut self) {
+        if let Some(writer) = self.current_part.take() {
+            Self::check_file_access(writer.finish());
+        }
+    }
+
+    fn sync_at_end(&mut self) {
+        for filename in std::mem::take(&mut self.unsynced_files) {
+            Self::check_file_access(output::sync_file(&filename));
+            Self::check_file_access(output::sync_directory(&filename));
+        }
+        Self::check_file_access(self.store.sync());
+    }
+
+    fn sync_part(&mut self) {
+        if let Some(file) = self.current_part.as_mut().and_then(|writer| writer.file()) {
+            Self::check_file_access(file.sync_all());
+        }
+    }
+
+    // With `--fsync=per-part` a written part is on disk before anything else is done
+    fn flush(&mut self) {
+        if let Some(f) = &mut self.current_file_to_write {
+            Self::check_file_access(f.flush());
+            if self.program_input.fsync == FsyncPolicy::PerPart {
+                Self::check_file_access(f.sync_all());
+            }
+        }
+    }
+
+    fn make_output_filename(&self, fragment_number: usize) -> PathBuf {
+        return self.make_set_filename(&fragment_number.to_string());
+    }
+
+    fn make_manifest_filename(&self) -> PathBuf {
+        return self.make_set_filename("manifest");
+    }
+
+    fn make_set_filename(&self, tag: &str) -> PathBuf {
+        return self.set_directory().join(self.make_set_name(tag));
+    }
+
+    // Name of a set file in the store, `game.zip_[2].splm`
+    fn make_set_name(&self, tag: &str) -> OsString {
+        return paths::concat(&[
+            &self.set_stem,
+            OsStr::new("_["),
+            OsStr::new(tag),
+            OsStr::new("].splm"),
+        ]);
+    }
+
+    fn input_file_name(&self) -> OsString {
+        let path = &self.program_input.input_filename;
+        return path.file_name().unwrap_or(path.as_os_str()).to_os_string();
+    }
+
+    // Name shared by all files of a set, the whole input name like `game.tar.gz`.
+    // Sets made by older versions drop the extension, `game_[1].splm` for `game.zip`, they are still found.
+    // Anything but `split` may also be pointed at one of the set files, like `game.zip_[2].splm`
+    fn find_set_stem(program_input: &ProgramInput, store: &dyn PartStore) -> OsString {
+        let path = &program_input.input_filename;
+        let filename = path.file_name().unwrap_or(path.as_os_str()).to_os_string();
+        if program_input.command == Command::Split {
+            return filename;
+        }
+
+        if let Some((stem, _)) = Self::split_set_filename(&filename) {
+            return stem;
+        }
+
+        let set_exists = |stem: &OsStr| ["1", "manifest"].iter().any(|tag| {
+            let name = paths::concat(&[stem, OsStr::new("_["), OsStr::new(tag), OsStr::new("].splm")]);
+            store.stat(&name).is_ok_and(|size| size.is_some())
+        });
+        if let Some(legacy_stem) = path.file_stem() {
+            if legacy_stem != filename && !set_exists(&filename) && set_exists(legacy_stem) {
+                return legacy_stem.to_os_string();
+            }
+        }
+        return filename;
+    }
+
+    fn set_directory(&self) -> PathBuf {
+        return Self::set_directory_of(&self.program_input);
+    }
+
+    // Parts are in `--store` when it is given. Otherwise split and download write them into `--output-directory`,
+    // everything else looks for them next to the input. Remote stores are shown as their URLs
+    fn set_directory_of(program_input: &ProgramInput) -> PathBuf {
+        if let Some(store) = &program_input.store {
+            return PathBuf::from(store.to_string());
+        }
+        if let Some(dir) = program_input.output_directory.as_ref().filter(|_| matches!(program_input.command, Command::Split | Command::Download)) {
+            return dir.clone();
+        }
+        return program_input.input_filename.parent().map(Path::to_path_buf).unwrap_or_default();
+    }
+
+    fn readable_set_directory(&self) -> PathBuf {
+        return Self::readable_directory(&self.set_directory()).to_path_buf();
+    }
+
+    fn is_store_local(&self) -> bool {
+        return self.program_input.store.as_ref().is_none_or(StoreLocation::is_local);
+    }
+
+    // Parent of `file` is empty, but it cannot be listed
+    fn readable_directory(directory: &Path) -> &Path {
+        if directory.as_os_str().is_empty() {
+            return Path::new(".");
+        }
+        return directory;
+    }
+
+    // `game.zip_[2].splm` -> (`game.zip`, `2`), `game.zip_[manifest].splm` -> (`game.zip`, `manifest`)
+    fn split_set_filename(filename: &OsStr) -> Option<(OsString, String)> {
+        let (stem, tag) = paths::split_tagged_name(filename)?;
+        if tag == "manifest" || (!tag.is_empty() && tag.chars().all(|c| c.is_ascii_digit())) {
+            return Some((stem, tag));
+        }
+        return None;
+    }
+
+    // `--output-file` if it is given, otherwise the original name from the manifest
+    // in `--output-directory` or next to parts, and `filename_[merged].ext` for sets without manifest
+    fn make_merged_filename(&self, manifest: Option<&Manifest>) -> PathBuf {
+        if let Some(output_file) = &self.program_input.output_file {
+            return output_file.clone();
+        }
+
+        // Manifest comes from outside, so only a bare file name is trusted
+        let original_name = manifest
+            .map(|manifest| manifest.file_name.clone())
+            .filter(|name| Path::new(name).file_name() == Some(name.as_os_str()));
+
+        let filename = match original_name {
+            Some(name) => name,
+            None => {
+                let input_file_name = self.input_file_name();
+                let name = if Self::split_set_filename(&input_file_name).is_some() { &self.set_stem } else { &input_file_name };
+                let (stem, extension) = paths::split_extension(name);
+                paths::concat(&[&stem, OsStr::new("_[merged]"), &extension])
+            }
+        };
+        // Files merged from a remote store go into the current directory
+        let set_directory = if self.is_store_local() { self.set_directory() } else { PathBuf::new() };
+        let directory = self.program_input.output_directory.clone().unwrap_or(set_directory);
+
+        return directory.join(filename);
+    }
+
+    fn check_file_access<T, Error: std::fmt::Debug>(result: Result<T, Error>) -> T {
+        match result {
+            Ok(t) => t,
+            Err(err) => {
+                panic!("File cannot be opened: {:?}", err);
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseResult;
+    use crate::store;
+    use crate::testing::{self, TempDir};
+
+    fn run(arguments: &[&OsStr], action: impl FnOnce(&mut Splimer)) -> Splimer {
+        let arguments: Vec<OsString> = std::iter::once(OsStr::new("splimer")).chain(arguments.iter().copied()).map(OsStr::to_os_string).collect();
+        let ParseResult::Success(program_input) = ProgramInput::parse(&arguments) else {
+            panic!("arguments are not parsed");
+        };
+        let store = store::open(&Splimer::store_location(&program_input), program_input.fsync, program_input.direct).unwrap();
+        let mut splimer = Splimer::new(program_input, store);
+        action(&mut splimer);
+        return splimer;
+    }
+
+    // `data.bin` of 5000 bytes split into parts of 1 KiB
+    fn split_set(directory: &TempDir) -> Vec<u8> {
+        let data = testing::data(5000, 0);
+        fs::write(directory.join("data.bin"), &data).unwrap();
+        let splimer = run(&[OsStr::new("split"), directory.join("data.bin").as_os_str(), OsStr::new("-S"), OsStr::new("1k")], Splimer::split);
+        assert!(!splimer.has_failed());
+        fs::remove_file(directory.join("data.bin")).unwrap();
+        return data;
+    }
+
+    fn merge(directory: &TempDir, output: &str) -> Splimer {
+        return run(&[
+            OsStr::new("merge"),
+            directory.join("data.bin").as_os_str(),
+            OsStr::new("--output-file"),
+            directory.join(output).as_os_str(),
+        ], Splimer::merge);
+    }
+
+    fn file_names(directory: &TempDir) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        return names;
+    }
+
+    #[test]
+    fn merge_puts_parts_together() {
+        let directory = TempDir::new();
+        let data = split_set(&directory);
+        assert!(directory.join("data.bin_[5].splm").exists());
+
+        let splimer = merge(&directory, "merged.bin");
+        assert!(!splimer.has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn merge_stops_at_a_missing_part() {
+        let directory = TempDir::new();
+        split_set(&directory);
+        fs::remove_file(directory.join("data.bin_[3].splm")).unwrap();
+        let names = file_names(&directory);
+
+        let splimer = merge(&directory, "merged.bin");
+        assert!(splimer.has_failed());
+        assert!(!directory.join("merged.bin").exists());
+        assert_eq!(file_names(&directory), names);
+    }
+
+    #[test]
+    fn merge_drops_a_file_of_wrong_hash() {
+        let directory = TempDir::new();
+        split_set(&directory);
+        let part = directory.join("data.bin_[2].splm");
+        let mut bytes = fs::read(&part).unwrap();
+        bytes[100] ^= 0xFF;
+        fs::write(&part, bytes).unwrap();
+        let names = file_names(&directory);
+
+        let splimer = merge(&directory, "merged.bin");
+        assert!(splimer.has_failed());
+        assert!(!directory.join("merged.bin").exists());
+        assert_eq!(file_names(&directory), names);
+    }
+
+    // Splits `bytes` with the given options and merges them back with `--consume-parts`
+    fn consume_parts(name: &str, bytes: &[u8], options: &[&str]) {
+        let directory = TempDir::new();
+        fs::write(directory.join(name), bytes).unwrap();
+        let mut arguments = vec![OsString::from("split"), directory.join(name).into_os_string()];
+        arguments.extend(options.iter().map(OsString::from));
+        let arguments: Vec<&OsStr> = arguments.iter().map(OsString::as_os_str).collect();
+        assert!(!run(&arguments, Splimer::split).has_failed());
+        fs::remove_file(directory.join(name)).unwrap();
+        assert!(directory.join(&format!("{}_[2].splm", name)).exists());
+
+        assert!(!merge_consuming(&directory, name).has_failed());
+        assert!(fs::read(directory.join("merged")).unwrap() == bytes);
+        assert_eq!(file_names(&directory), vec!["merged".to_string()]);
+    }
+
+    fn merge_consuming(directory: &TempDir, name: &str) -> Splimer {
+        return run(&[
+            OsStr::new("merge"),
+            directory.join(name).as_os_str(),
+            OsStr::new("--consume-parts"),
+            OsStr::new("--output-file"),
+            directory.join("merged").as_os_str(),
+        ], Splimer::merge);
+    }
+
+    // Flips a byte of a part and gives back what it was
+    fn damage(path: &Path) -> Vec<u8> {
+        let original = fs::read(path).unwrap();
+        let mut bytes = original.clone();
+        bytes[10] ^= 0xFF;
+        fs::write(path, bytes).unwrap();
+        return original;
+    }
+
+    #[test]
+    fn consume_parts_leaves_the_set_when_the_first_part_is_damaged() {
+        let directory = TempDir::new();
+        let data = split_set(&directory);
+        let original = damage(&directory.join("data.bin_[1].splm"));
+        let names = file_names(&directory);
+
+        assert!(merge_consuming(&directory, "data.bin").has_failed());
+        assert_eq!(file_names(&directory), names);
+
+        // Without a journal a plain merge is not refused
+        fs::write(directory.join("data.bin_[1].splm"), original).unwrap();
+        assert!(!merge(&directory, "merged").has_failed());
+        assert_eq!(fs::read(directory.join("merged")).unwrap(), data);
+    }
+
+    #[test]
+    fn consume_parts_leaves_archive_sets_when_the_first_part_is_damaged() {
+        let directory = TempDir::new();
+        fs::write(directory.join("a.tar"), testing::tar_archive(&[("a.bin", &testing::data(3000, 1)), ("b.bin", &testing::data(900, 2))])).unwrap();
+        assert!(!run(&[OsStr::new("split"), directory.join("a.tar").as_os_str(), OsStr::new("-S"), OsStr::new("4k"), OsStr::new("--boundary=tar")], Splimer::split).has_failed());
+        fs::remove_file(directory.join("a.tar")).unwrap();
+        damage(&directory.join("a.tar_[1].splm"));
+        let names = file_names(&directory);
+
+        assert!(merge_consuming(&directory, "a.tar").has_failed());
+        assert_eq!(file_names(&directory), names);
+    }
+
+    #[test]
+    fn consume_parts_goes_on_after_an_interrupted_run() {
+        let directory = TempDir::new();
+        let data = split_set(&directory);
+        // The run stops at the third part, after the first two are moved into the merged file
+        let original = damage(&directory.join("data.bin_[3].splm"));
+        assert!(merge_consuming(&directory, "data.bin").has_failed());
+        assert!(directory.join("data.bin_[journal].splm").exists());
+        assert!(!directory.join("data.bin_[1].splm").exists());
+        assert!(!directory.join("data.bin_[2].splm").exists());
+
+        // A plain merge cannot finish it, the removed parts are only in the journal's merged file
+        assert!(merge(&directory, "other").has_failed());
+        assert!(!directory.join("other").exists());
+
+        fs::write(directory.join("data.bin_[3].splm"), original).unwrap();
+        assert!(!merge_consuming(&directory, "data.bin").has_failed());
+        assert_eq!(fs::read(directory.join("merged")).unwrap(), data);
+        assert_eq!(file_names(&directory), vec!["merged".to_string()]);
+    }
+
+    #[test]
+    fn consume_parts_of_tar_sets() {
+        let (a, b, c) = (testing::data(3000, 1), testing::data(2000, 2), testing::data(100, 3));
+        let archive = testing::tar_archive(&[("a.bin", &a), ("b.bin", &b), ("c.bin", &c)]);
+        consume_parts("a.tar", &archive, &["-S", "4k", "--boundary=tar"]);
+        // Members which don't fit go on in the next parts after `M` headers
+        consume_parts("a.tar", &archive, &["-S", "2k", "--boundary=tar"]);
+    }
+
+    #[test]
+    fn consume_parts_of_zip_sets() {
+        let entries: Vec<(String, Vec<u8>)> = (0..40).map(|i| (format!("file{}.bin", i), testing::data(100 + i * 37, i as u32))).collect();
+        let entries: Vec<(&str, &[u8])> = entries.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect();
+        let archive = testing::zip_archive(&entries);
+        consume_parts("a.zip", &archive, &["-S", "4k", "--boundary=zip"]);
+        // Entries bigger than a part are cut into plain byte parts
+        consume_parts("a.zip", &archive, &["-S", "1k", "--boundary=zip"]);
+    }
+
+    // Splits `data.bin` of 5000 bytes into parts of 1 KiB with a hook
+    fn split_with_hook(directory: &TempDir, options: &[&str]) -> Splimer {
+        return split_data(directory, &testing::data(5000, 0), options);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn split_stops_when_a_part_hook_fails() {
+        let directory = TempDir::new();
+        let splimer = split_with_hook(&directory, &["--on-part-done", "test {index} -ne 2", "--wait-for-hook"]);
+        assert!(splimer.has_failed());
+        assert!(directory.join("data.bin_[2].splm").exists());
+        assert!(!directory.join("data.bin_[3].splm").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_for_hook_runs_hooks_one_by_one() {
+        let directory = TempDir::new();
+        let busy = directory.join("busy").to_string_lossy().to_string();
+        let order = directory.join("order").to_string_lossy().to_string();
+        let command = format!("test ! -e '{busy}' && touch '{busy}' && sleep 0.05 && rm '{busy}' && echo {{index}} >> '{order}'");
+        let splimer = split_with_hook(&directory, &["--on-part-done", &command, "--wait-for-hook"]);
+        assert!(!splimer.has_failed());
+        assert_eq!(fs::read_to_string(directory.join("order")).unwrap(), "1\n2\n3\n4\n5\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn delete_after_hook_removes_parts_whose_hooks_are_done() {
+        let directory = TempDir::new();
+        let splimer = split_with_hook(&directory, &["--on-part-done", "cp {path} {path}.copy", "--delete-after-hook", "--max-pending", "2"]);
+        assert!(!splimer.has_failed());
+        for index in 1..=5 {
+            assert!(!directory.join(&format!("data.bin_[{}].splm", index)).exists());
+            assert!(directory.join(&format!("data.bin_[{}].splm.copy", index)).exists());
+        }
+        assert!(directory.join("data.bin_[manifest].splm").exists());
+    }
+
+    #[test]
+    fn part_callback_is_called_for_every_part() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let directory = TempDir::new();
+        fs::write(directory.join("data.bin"), testing::data(5000, 0)).unwrap();
+        let parts = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&parts);
+        let splimer = run(&[OsStr::new("split"), directory.join("data.bin").as_os_str(), OsStr::new("-S"), OsStr::new("1k")], |splimer| {
+            splimer.on_part_done(Box::new(move |part| {
+                seen.borrow_mut().push((part.index, part.total));
+                if part.index == 3 {
+                    return Err(std::io::Error::other("upload is refused"));
+                }
+                return Ok(());
+            }));
+            splimer.split();
+        });
+        assert!(splimer.has_failed());
+        assert_eq!(*parts.borrow(), vec![(1, 5), (2, 5), (3, 5)]);
+    }
+
+    // Bot API which keeps sent documents, the message of a document is `100 + its number`.
+    // `failures` are statuses which the next sendDocument requests are answered with
+    #[derive(Default)]
+    struct BotApi {
+        documents: Vec<(String, Vec<u8>)>,
+        pinned: Option<i64>,
+        failures: Vec<u16>,
+        forwarded: usize,
+    }
+
+    impl BotApi {
+        fn message(&self, message_id: i64) -> String {
+            let (name, _) = &self.documents[message_id as usize - 100];
+            return format!("{{\"message_id\": {}, \"document\": {{\"file_id\": \"file-{}\", \"file_name\": \"{}\"}}}}", message_id, message_id, name);
+        }
+
+        fn answer(&mut self, request: testing::HttpRequest) -> Vec<u8> {
+            let body = String::from_utf8_lossy(&request.body).to_string();
+            let field = |name: &str| body.split('&').find_map(|pair| pair.strip_prefix(&format!("{}=", name))).map(str::to_string);
+            let message_id = || field("message_id").unwrap().parse::<i64>().unwrap();
+            let result = match request.target.rsplit('/').next().unwrap() {
+                "sendDocument" if !self.failures.is_empty() => {
+                    return match self.failures.remove(0) {
+                        429 => testing::http_response(429, br#"{"ok": false, "error_code": 429, "description": "Too Many Requests", "parameters": {"retry_after": 0}}"#),
+                        status => testing::http_response(status, b"<html>Bad Gateway</html>"),
+                    };
+                },
+                "sendDocument" => {
+                    let name = body.split("filename=\"").nth(1).unwrap().split('"').next().unwrap().to_string();
+                    let position = |pattern: &[u8]| request.body.windows(pattern.len()).rposition(|window| window == pattern).unwrap();
+                    let start = position(b"application/octet-stream\r\n\r\n") + b"application/octet-stream\r\n\r\n".len();
+                    let end = position(b"\r\n--");
+                    self.documents.push((name, request.body[start..end].to_vec()));
+                    self.message(99 + self.documents.len() as i64)
+                },
+                "pinChatMessage" => {
+                    self.pinned = Some(message_id());
+                    "true".to_string()
+                },
+                "getChat" => match self.pinned {
+                    Some(pinned) => format!("{{\"id\": 42, \"pinned_message\": {}}}", self.message(pinned)),
+                    None => "{\"id\": 42}".to_string(),
+                },
+                "forwardMessage" => {
+                    self.forwarded += 1;
+                    self.message(message_id()).replacen(&message_id().to_string(), "900", 1)
+                },
+                "deleteMessage" => "true".to_string(),
+                "getFile" => format!("{{\"file_path\": \"documents/{}\"}}", field("file_id").unwrap().trim_start_matches("file-")),
+                number if request.method == "GET" => {
+                    let (_, bytes) = &self.documents[number.parse::<usize>().unwrap() - 100];
+                    return testing::http_response(200, bytes);
+                },
+                method => panic!("{} is not expected", method),
+            };
+            return testing::http_response(200, format!("{{\"ok\": true, \"result\": {}}}", result).as_bytes());
+        }
+    }
+
+    fn telegram(command: &str, input: &Path, api: &str, options: &[&str]) -> Splimer {
+        std::env::set_var("SPLIMER_TELEGRAM_TOKEN", "123:token");
+        let mut arguments = vec![OsString::from(command), input.as_os_str().to_os_string()];
+        arguments.extend(["--telegram", "--chat", "42", "--telegram-api", api].iter().chain(options).map(OsString::from));
+        let arguments: Vec<&OsStr> = arguments.iter().map(OsString::as_os_str).collect();
+        return run(&arguments, |splimer| {
+            match command {
+                "upload" => splimer.upload(),
+                _ => splimer.download(),
+            };
+        });
+    }
+
+    #[test]
+    fn telegram_upload_and_download() {
+        use std::sync::{Arc, Mutex};
+
+        let directory = TempDir::new();
+        let data = split_set(&directory);
+        let bot = Arc::new(Mutex::new(BotApi{ failures: vec![429, 502], ..BotApi::default() }));
+        let server = Arc::clone(&bot);
+        let api = testing::serve(move |request| server.lock().unwrap().answer(request));
+
+        // Too many requests and a failing server are waited out
+        assert!(!telegram("upload", &directory.join("data.bin"), &api, &[]).has_failed());
+        assert_eq!(bot.lock().unwrap().documents.len(), 6);
+        assert_eq!(bot.lock().unwrap().pinned, Some(105));
+        assert_eq!(bot.lock().unwrap().documents[2].1, fs::read(directory.join("data.bin_[3].splm")).unwrap());
+
+        // An interrupted upload goes on after the last part written into the manifest
+        let manifest_filename = directory.join("data.bin_[manifest].splm");
+        let manifest = fs::read_to_string(&manifest_filename).unwrap();
+        assert!(manifest.contains("telegram_part=5 104 file-104\n"));
+        fs::write(&manifest_filename, manifest.replace("telegram_part=5 104 file-104\n", "")).unwrap();
+        assert!(!telegram("upload", &directory.join("data.bin"), &api, &[]).has_failed());
+        let names: Vec<String> = bot.lock().unwrap().documents[6..].iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(names, ["data.bin_[5].splm", "data.bin_[manifest].splm"]);
+        assert_eq!(bot.lock().unwrap().pinned, Some(107));
+
+        let restored = TempDir::new();
+        assert!(!telegram("download", &restored.join("data.bin"), &api, &[]).has_failed());
+        assert!(!merge(&restored, "merged.bin").has_failed());
+        assert_eq!(fs::read(restored.join("merged.bin")).unwrap(), data);
+
+        // With another message pinned, the manifest is found by its message
+        bot.lock().unwrap().pinned = Some(100);
+        let restored = TempDir::new();
+        assert!(telegram("download", &restored.join("data.bin"), &api, &[]).has_failed());
+        assert!(!telegram("download", &restored.join("data.bin"), &api, &["--manifest-message", "107"]).has_failed());
+        assert_eq!(bot.lock().unwrap().forwarded, 1);
+        assert!(!merge(&restored, "merged.bin").has_failed());
+        assert_eq!(fs::read(restored.join("merged.bin")).unwrap(), data);
+    }
+
+    // Writes `data` to `data.bin` and splits it into parts of 1 KiB with the given options
+    fn split_data(directory: &TempDir, data: &[u8], options: &[&str]) -> Splimer {
+        fs::write(directory.join("data.bin"), data).unwrap();
+        let mut arguments = vec![OsString::from("split"), directory.join("data.bin").into_os_string(), OsString::from("-S"), OsString::from("1k")];
+        arguments.extend(options.iter().map(OsString::from));
+        let arguments: Vec<&OsStr> = arguments.iter().map(OsString::as_os_str).collect();
+        return run(&arguments, Splimer::split);
+    }
+
+    fn merge_with(directory: &TempDir, output: &str, options: &[&str]) -> Splimer {
+        let mut arguments = vec![OsString::from("merge"), directory.join("data.bin").into_os_string(), OsString::from("--output-file"), directory.join(output).into_os_string()];
+        arguments.extend(options.iter().map(OsString::from));
+        let arguments: Vec<&OsStr> = arguments.iter().map(OsString::as_os_str).collect();
+        return run(&arguments, Splimer::merge);
+    }
+
+    fn has_temporary_files(directory: &TempDir) -> bool {
+        return file_names(directory).iter().any(|name| name.ends_with(".splimer-tmp"));
+    }
+
+    #[test]
+    fn split_keeps_parts_of_another_file_unless_told_otherwise() {
+        let directory = TempDir::new();
+        let old = testing::data(5000, 0);
+        assert!(!split_data(&directory, &old, &[]).has_failed());
+        let old_part = fs::read(directory.join("data.bin_[1].splm")).unwrap();
+        let names = file_names(&directory);
+
+        let new = testing::data(3000, 1);
+        assert!(split_data(&directory, &new, &[]).has_failed());
+        assert!(split_data(&directory, &new, &["--no-clobber"]).has_failed());
+        assert_eq!(file_names(&directory), names);
+        assert_eq!(fs::read(directory.join("data.bin_[1].splm")).unwrap(), old_part);
+    }
+
+    #[test]
+    fn split_with_backup_keeps_replaced_parts() {
+        let directory = TempDir::new();
+        let old = testing::data(5000, 0);
+        assert!(!split_data(&directory, &old, &[]).has_failed());
+        let old_part = fs::read(directory.join("data.bin_[1].splm")).unwrap();
+        let old_manifest = fs::read(directory.join("data.bin_[manifest].splm")).unwrap();
+
+        let new = testing::data(3000, 1);
+        assert!(!split_data(&directory, &new, &["--backup"]).has_failed());
+        assert_eq!(fs::read(directory.join("data.bin_[1].splm~")).unwrap(), old_part);
+        assert_eq!(fs::read(directory.join("data.bin_[manifest].splm~")).unwrap(), old_manifest);
+        // Stale parts of the old set are kept as backups as well
+        assert!(!directory.join("data.bin_[5].splm").exists());
+        assert!(directory.join("data.bin_[5].splm~").exists());
+        assert!(!has_temporary_files(&directory));
+
+        fs::remove_file(directory.join("data.bin")).unwrap();
+        assert!(!merge(&directory, "merged.bin").has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), new);
+    }
+
+    #[test]
+    fn split_with_force_replaces_parts() {
+        let directory = TempDir::new();
+        assert!(!split_data(&directory, &testing::data(5000, 0), &[]).has_failed());
+
+        let new = testing::data(3000, 1);
+        assert!(!split_data(&directory, &new, &["--force"]).has_failed());
+        assert!(!file_names(&directory).iter().any(|name| name.ends_with('~')));
+        assert!(!directory.join("data.bin_[4].splm").exists());
+        assert!(!has_temporary_files(&directory));
+
+        fs::remove_file(directory.join("data.bin")).unwrap();
+        assert!(!merge(&directory, "merged.bin").has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), new);
+    }
+
+    #[test]
+    fn merge_into_an_existing_file() {
+        let directory = TempDir::new();
+        let data = split_set(&directory);
+        fs::write(directory.join("merged.bin"), b"old").unwrap();
+
+        assert!(merge_with(&directory, "merged.bin", &[]).has_failed());
+        assert!(merge_with(&directory, "merged.bin", &["--no-clobber"]).has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), b"old");
+
+        assert!(!merge_with(&directory, "merged.bin", &["--backup"]).has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin~")).unwrap(), b"old");
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+
+        fs::write(directory.join("merged.bin"), b"old").unwrap();
+        assert!(!merge_with(&directory, "merged.bin", &["--force"]).has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+        assert_eq!(fs::read(directory.join("merged.bin~")).unwrap(), b"old");
+        assert!(!has_temporary_files(&directory));
+    }
+
+    #[test]
+    fn io_options_keep_the_data() {
+        // Sizes which are not multiples of the block or buffer size leave unaligned ends for `--direct`
+        let data = testing::data(100_003, 2);
+        let option_sets: &[&[&str]] = &[
+            &["--fsync", "never"],
+            &["--fsync", "per-part"],
+            &["--fsync", "end"],
+            &["--direct"],
+            &["--direct", "--no-zero-copy", "--buffer-size", "4k"],
+            &["--buffer-size", "4k", "--no-zero-copy"],
+            &["--buffer-size", "5000"],
+        ];
+        for options in option_sets {
+            let directory = TempDir::new();
+            fs::write(directory.join("data.bin"), &data).unwrap();
+            let mut arguments = vec!["split", "-S", "30001"];
+            arguments.extend_from_slice(options);
+            let mut arguments: Vec<OsString> = arguments.iter().map(OsString::from).collect();
+            arguments.insert(1, directory.join("data.bin").into_os_string());
+            let arguments: Vec<&OsStr> = arguments.iter().map(OsString::as_os_str).collect();
+            assert!(!run(&arguments, Splimer::split).has_failed(), "split with {:?}", options);
+            fs::remove_file(directory.join("data.bin")).unwrap();
+            assert!(directory.join("data.bin_[4].splm").exists());
+
+            assert!(!merge_with(&directory, "merged.bin", options).has_failed(), "merge with {:?}", options);
+            assert!(fs::read(directory.join("merged.bin")).unwrap() == data, "data with {:?}", options);
+            assert!(!has_temporary_files(&directory));
+        }
+    }
+
+    // Waits for a file which another thread makes, a test fails rather than hangs when it never comes
+    fn wait_for_file(path: &Path) {
+        let since = Instant::now();
+        while !path.exists() {
+            assert!(since.elapsed() < Duration::from_secs(10), "{} is not made", path.display());
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn follow_splits_a_growing_file() {
+        let directory = TempDir::new();
+        let data = testing::data(5000, 3);
+        let mut file = File::create(directory.join("data.bin")).unwrap();
+        let first_part = directory.join("data.bin_[1].splm");
+        let writer = {
+            let data = data.clone();
+            thread::spawn(move || {
+                for (index, chunk) in data.chunks(700).enumerate() {
+                    // The first part is written while the file still grows
+                    if index == 3 {
+                        wait_for_file(&first_part);
+                    }
+                    file.write_all(chunk).unwrap();
+                    thread::sleep(Duration::from_millis(20));
+                }
+            })
+        };
+
+        let splimer = run(&[
+            OsStr::new("split"),
+            directory.join("data.bin").as_os_str(),
+            OsStr::new("-S"),
+            OsStr::new("1k"),
+            OsStr::new("--follow"),
+            OsStr::new("--follow-timeout"),
+            OsStr::new("500ms"),
+        ], Splimer::split);
+        writer.join().unwrap();
+        assert!(!splimer.has_failed());
+        assert!(directory.join("data.bin_[5].splm").exists());
+        assert!(!directory.join("data.bin_[6].splm").exists());
+        assert!(!Manifest::read(splimer.store.as_ref(), &splimer.make_set_name("manifest")).unwrap().following);
+
+        fs::remove_file(directory.join("data.bin")).unwrap();
+        assert!(!merge(&directory, "merged.bin").has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn follow_finishes_when_the_file_stops_growing() {
+        let directory = TempDir::new();
+        let data = testing::data(2500, 4);
+        // The file is kept open, so only the timeout ends the split
+        let mut file = File::create(directory.join("data.bin")).unwrap();
+        file.write_all(&data).unwrap();
+
+        let since = Instant::now();
+        let splimer = run(&[
+            OsStr::new("split"),
+            directory.join("data.bin").as_os_str(),
+            OsStr::new("-S"),
+            OsStr::new("1k"),
+            OsStr::new("--follow"),
+            OsStr::new("--follow-timeout"),
+            OsStr::new("300ms"),
+        ], Splimer::split);
+        assert!(since.elapsed() >= Duration::from_millis(300));
+        assert!(!splimer.has_failed());
+        assert!(directory.join("data.bin_[3].splm").exists());
+        drop(file);
+
+        fs::remove_file(directory.join("data.bin")).unwrap();
+        assert!(!merge(&directory, "merged.bin").has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn wait_merges_parts_which_come_out_of_order() {
+        let source = TempDir::new();
+        let data = split_set(&source);
+        let directory = TempDir::new();
+        let arriving = directory.path().to_path_buf();
+        let sender = thread::spawn(move || {
+            let part = |tag: &str| format!("data.bin_[{}].splm", tag);
+            for tag in ["5", "3", "manifest", "1"] {
+                thread::sleep(Duration::from_millis(30));
+                fs::rename(source.join(&part(tag)), arriving.join(part(tag))).unwrap();
+            }
+            // A part which is still written, and one which is damaged until it is sent again
+            let second = fs::read(source.join(&part("2"))).unwrap();
+            let mut target = File::create(arriving.join(part("2"))).unwrap();
+            target.write_all(&second[..500]).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            target.write_all(&second[500..]).unwrap();
+            drop(target);
+            let fourth = fs::read(source.join(&part("4"))).unwrap();
+            let mut damaged = fourth.clone();
+            damaged[10] ^= 0xFF;
+            fs::write(arriving.join(part("4")), damaged).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            fs::write(arriving.join(part("4")), fourth).unwrap();
+        });
+
+        let splimer = merge_with(&directory, "merged.bin", &["--wait", "--follow-timeout", "5s"]);
+        sender.join().unwrap();
+        assert!(!splimer.has_failed());
+        assert_eq!(fs::read(directory.join("merged.bin")).unwrap(), data);
+        assert!(!has_temporary_files(&directory));
+    }
+
+    #[test]
+    fn wait_fails_when_parts_stop_coming() {
+        let directory = TempDir::new();
+        split_set(&directory);
+        fs::remove_file(directory.join("data.bin_[4].splm")).unwrap();
+
+        let splimer = merge_with(&directory, "merged.bin", &["--wait", "--follow-timeout", "300ms"]);
+        assert!(splimer.has_failed());
+        assert!(!directory.join("merged.bin").exists());
+        assert!(!has_temporary_files(&directory));
+    }
 }
@@ -0,0 +1,280 @@
+// Parts and the manifest are kept in a store, which is a local directory unless `--store` points elsewhere:
+// `s3://bucket/prefix` for S3 and compatible stores like MinIO, `webdav://host/path` or `webdavs://`
+// for WebDAV and `sftp://user@host/path` for SFTP. Split uploads parts straight from the source
+// and merge reads them back, nothing is kept on the local disk on the way.
+// Like local files, remote ones get their names only when they are complete.
+
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::copy;
+use crate::http::Url;
+use crate::output::{self, ClobberPolicy, FsyncPolicy};
+use crate::s3::S3Store;
+use crate::sftp::SftpStore;
+use crate::webdav::WebDavStore;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StoreLocation {
+    Local(PathBuf),
+    S3{ bucket: String, prefix: String },
+    WebDav(String),
+    Sftp(String),
+}
+
+impl StoreLocation {
+    pub fn parse(location: &str) -> Option<StoreLocation> {
+        let Some((scheme, rest)) = location.split_once("://") else {
+            return Some(StoreLocation::Local(PathBuf::from(location)));
+        };
+        return match scheme {
+            "file" => Some(StoreLocation::Local(PathBuf::from(rest))),
+            "s3" => {
+                let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+                if bucket.is_empty() {
+                    return None;
+                }
+                Some(StoreLocation::S3{ bucket: bucket.to_string(), prefix: prefix.trim_matches('/').to_string() })
+            },
+            "webdav" | "webdavs" => Url::parse(location).map(|_| StoreLocation::WebDav(location.to_string())),
+            "sftp" => Url::parse(location).map(|_| StoreLocation::Sftp(location.to_string())),
+            _ => None,
+        };
+    }
+
+    pub fn is_local(&self) -> bool {
+        return matches!(self, StoreLocation::Local(_));
+    }
+}
+
+impl fmt::Display for StoreLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            StoreLocation::Local(directory) => write!(f, "{}", directory.display()),
+            StoreLocation::S3{ bucket, prefix } if prefix.is_empty() => write!(f, "s3://{}", bucket),
+            StoreLocation::S3{ bucket, prefix } => write!(f, "s3://{}/{}", bucket, prefix),
+            StoreLocation::WebDav(url) | StoreLocation::Sftp(url) => write!(f, "{}", without_password(url.trim_end_matches('/'))),
+        };
+    }
+}
+
+// Locations are printed with every part name, so a password in the URL is left out
+fn without_password(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    let Some(at) = rest[..authority_end].rfind('@') else {
+        return url.to_string();
+    };
+    let user = rest[..at].split(':').next().unwrap_or("");
+    return format!("{}://{}{}", scheme, user, &rest[at..]);
+}
+
+pub struct StoredFile {
+    pub name: OsString,
+    pub size: u64,
+}
+
+// A file which is being written, it is in the store under its name only after `finish`
+pub trait PartWriter: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+
+    // Local files are written by the kernel or io_uring, not through `Write`
+    fn file(&mut self) -> Option<&mut File> {
+        return None;
+    }
+}
+
+pub trait PartStore {
+    // `size` bytes are going to be written, remote stores need to know that beforehand
+    fn create(&self, name: &OsStr, size: u64, policy: ClobberPolicy) -> io::Result<Box<dyn PartWriter>>;
+    // Reads from `offset` to the end
+    fn read(&self, name: &OsStr, offset: u64) -> io::Result<Box<dyn Read>>;
+    fn list(&self) -> io::Result<Vec<StoredFile>>;
+    // Size of the file, `None` when there is no such file
+    fn stat(&self, name: &OsStr) -> io::Result<Option<u64>>;
+    fn delete(&self, name: &OsStr, policy: ClobberPolicy) -> io::Result<()>;
+
+    // Small files like the manifest are written in one go
+    fn write(&self, name: &OsStr, contents: &[u8], policy: ClobberPolicy) -> io::Result<()> {
+        let mut writer = self.create(name, contents.len() as u64, policy)?;
+        writer.write_all(contents)?;
+        return writer.finish();
+    }
+
+    fn read_to_string(&self, name: &OsStr) -> io::Result<String> {
+        let mut text = String::new();
+        self.read(name, 0)?.read_to_string(&mut text)?;
+        return Ok(text);
+    }
+
+    // Where a file lies on the local disk, so it can be copied without going through the store
+    fn local_path(&self, _name: &OsStr) -> Option<PathBuf> {
+        return None;
+    }
+
+    // Makes everything written so far durable, with `--fsync=end` it is only done here
+    fn sync(&self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+pub fn open(location: &StoreLocation, fsync: FsyncPolicy, direct: bool) -> io::Result<Box<dyn PartStore>> {
+    return Ok(match location {
+        StoreLocation::Local(directory) => Box::new(LocalDirStore::new(directory, fsync, direct)),
+        StoreLocation::S3{ bucket, prefix } => Box::new(S3Store::new(bucket, prefix)?),
+        StoreLocation::WebDav(url) => Box::new(WebDavStore::new(url)?),
+        StoreLocation::Sftp(url) => Box::new(SftpStore::new(url)?),
+    });
+}
+
+// Names in remote stores are text
+pub fn remote_name(name: &OsStr) -> io::Result<&str> {
+    return name.to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not UTF-8, it cannot be stored remotely", name.to_string_lossy())));
+}
+
+// Name under which a file is written before it is complete, like `output::temporary_path`
+pub fn temporary_name(name: &str) -> String {
+    return format!(".{}.splimer-tmp", name);
+}
+
+pub struct LocalDirStore {
+    directory: PathBuf,
+    fsync: FsyncPolicy,
+    direct: bool,
+    // Files which are synced at the very end with `--fsync=end`
+    unsynced_files: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+struct LocalPartWriter {
+    file: File,
+    temporary_filename: PathBuf,
+    filename: PathBuf,
+    policy: ClobberPolicy,
+    fsync: FsyncPolicy,
+    unsynced_files: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+impl LocalDirStore {
+    pub fn new(directory: &Path, fsync: FsyncPolicy, direct: bool) -> LocalDirStore {
+        return LocalDirStore{ directory: directory.to_path_buf(), fsync, direct, unsynced_files: Rc::new(RefCell::new(Vec::new())) };
+    }
+
+    // Parent of `file` is empty, but it cannot be listed
+    fn readable_directory(&self) -> &Path {
+        if self.directory.as_os_str().is_empty() {
+            return Path::new(".");
+        }
+        return &self.directory;
+    }
+}
+
+impl PartStore for LocalDirStore {
+    fn create(&self, name: &OsStr, _size: u64, policy: ClobberPolicy) -> io::Result<Box<dyn PartWriter>> {
+        let filename = self.directory.join(name);
+        let temporary_filename = output::temporary_path(&filename);
+        let file = copy::open(OpenOptions::new().write(true).truncate(true).create(true), &temporary_filename, self.direct)?;
+        return Ok(Box::new(LocalPartWriter{
+            file,
+            temporary_filename,
+            filename,
+            policy,
+            fsync: self.fsync,
+            unsynced_files: Rc::clone(&self.unsynced_files),
+        }));
+    }
+
+    fn read(&self, name: &OsStr, offset: u64) -> io::Result<Box<dyn Read>> {
+        let mut file = File::open(self.directory.join(name))?;
+        file.seek(SeekFrom::Start(offset))?;
+        return Ok(Box::new(file));
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(self.readable_directory())?.flatten() {
+            if let Ok(metadata) = entry.metadata() {
+                files.push(StoredFile{ name: entry.file_name(), size: metadata.len() });
+            }
+        }
+        return Ok(files);
+    }
+
+    fn stat(&self, name: &OsStr) -> io::Result<Option<u64>> {
+        let path = self.directory.join(name);
+        return match fs::symlink_metadata(&path) {
+            Ok(metadata) => Ok(Some(fs::metadata(&path).unwrap_or(metadata).len())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        };
+    }
+
+    fn delete(&self, name: &OsStr, policy: ClobberPolicy) -> io::Result<()> {
+        return output::discard(&self.directory.join(name), policy);
+    }
+
+    fn local_path(&self, name: &OsStr) -> Option<PathBuf> {
+        return Some(self.directory.join(name));
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        for filename in self.unsynced_files.take() {
+            match output::sync_file(&filename) {
+                // Removed by now, like after `--delete-after-hook`
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                result => result?,
+            }
+            output::sync_directory(&filename)?;
+        }
+        return Ok(());
+    }
+}
+
+impl Write for LocalPartWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self.file.write(buffer) {
+            // The unaligned end is written without `O_DIRECT`
+            Err(err) if copy::is_misaligned(&err) => {
+                copy::clear_direct(&self.file)?;
+                return self.file.write(buffer);
+            },
+            result => return result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.file.flush();
+    }
+}
+
+impl PartWriter for LocalPartWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.file.flush()?;
+        if self.fsync == FsyncPolicy::PerPart {
+            self.file.sync_all()?;
+        }
+        output::commit(&self.temporary_filename, &self.filename, self.policy)?;
+        match self.fsync {
+            FsyncPolicy::Never => { },
+            FsyncPolicy::PerPart => output::sync_directory(&self.filename)?,
+            FsyncPolicy::End => {
+                let mut unsynced_files = self.unsynced_files.borrow_mut();
+                if !unsynced_files.contains(&self.filename) {
+                    unsynced_files.push(self.filename.clone());
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn file(&mut self) -> Option<&mut File> {
+        return Some(&mut self.file);
+    }
+}
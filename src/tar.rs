@@ -0,0 +1,344 @@
+// `--boundary=tar` cuts parts only between members of a tar archive and adds the two zero blocks
+// which end an archive to every part, so each part is a tar archive of its own.
+// A member which doesn't fit into a part goes on in the next ones like on GNU tar volumes:
+// each of them starts with an `M` header which tells how much of the member was in the previous ones,
+// so `tar -xM -f part1 -f part2` puts it together. Headers and trailers are left out by merge.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const BLOCK_SIZE: u64 = 512;
+// Two zero blocks end an archive
+const TRAILER_SIZE: u64 = 2 * BLOCK_SIZE;
+
+const TYPE_FLAG: usize = 156;
+const SIZE_FIELD: usize = 124;
+const CHECKSUM_FIELD: usize = 148;
+// GNU fields of `M` headers and of old sparse headers
+const OFFSET_FIELD: usize = 369;
+const IS_EXTENDED: usize = 482;
+const EXTENSION_IS_EXTENDED: usize = 504;
+
+struct Member {
+    // Its first header, which may be a long name or pax header
+    offset: u64,
+    // Where its data starts, its size and where the next member starts
+    data_offset: u64,
+    data_size: u64,
+    end: u64,
+    main_header: Vec<u8>,
+}
+
+pub struct TarPart {
+    pub size: u64,
+    pub header: Vec<u8>,
+    pub trailer_size: u64,
+}
+
+impl TarPart {
+    pub fn trailer(&self) -> Vec<u8> {
+        return vec![0; self.trailer_size as usize];
+    }
+}
+
+pub struct TarLayout {
+    members: Vec<Member>,
+}
+
+fn bad_archive(reason: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("it is not a tar archive splimer can read, {}", reason));
+}
+
+fn blocks(size: u64) -> u64 {
+    return size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+}
+
+// Octal digits ended by a space or zero, or a big-endian number after the 0x80 byte for big sizes
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first() == Some(&0x80) {
+        return Some(field[1..].iter().fold(0u64, |number, &byte| number << 8 | byte as u64));
+    }
+    let digits = std::str::from_utf8(field).ok()?.trim_matches(|c| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    return u64::from_str_radix(digits, 8).ok();
+}
+
+fn write_number(field: &mut [u8], number: u64) {
+    let digits = field.len() - 1;
+    if number < 1 << (3 * digits) {
+        field[..digits].copy_from_slice(format!("{:0width$o}", number, width = digits).as_bytes());
+        field[digits] = 0;
+        return;
+    }
+    field.fill(0);
+    field[0] = 0x80;
+    let length = field.len();
+    field[length - 8..].copy_from_slice(&number.to_be_bytes());
+}
+
+fn checksum(header: &[u8]) -> u64 {
+    // The checksum field is counted as spaces
+    return header.iter().enumerate()
+        .map(|(i, &byte)| if (CHECKSUM_FIELD..CHECKSUM_FIELD + 8).contains(&i) { b' ' as u64 } else { byte as u64 })
+        .sum();
+}
+
+fn read_block(file: &mut File, offset: u64) -> io::Result<Vec<u8>> {
+    let mut block = vec![0; BLOCK_SIZE as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut block)?;
+    return Ok(block);
+}
+
+impl TarLayout {
+    pub fn read(file: &mut File, file_size: u64) -> io::Result<TarLayout> {
+        let mut members = Vec::new();
+        let mut offset = 0;
+        let mut member_offset = None;
+        while offset + BLOCK_SIZE <= file_size {
+            let header = read_block(file, offset)?;
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            if parse_number(&header[CHECKSUM_FIELD..CHECKSUM_FIELD + 8]) != Some(checksum(&header)) {
+                return Err(bad_archive(format!("header at byte {} has a wrong checksum", offset)));
+            }
+            let size = parse_number(&header[SIZE_FIELD..SIZE_FIELD + 12])
+                .ok_or_else(|| bad_archive(format!("header at byte {} has a wrong size", offset)))?;
+            let type_flag = header[TYPE_FLAG];
+            let start = *member_offset.get_or_insert(offset);
+
+            // Long names and pax headers belong to the member which follows them
+            if matches!(type_flag, b'L' | b'K' | b'x') {
+                offset += BLOCK_SIZE + blocks(size);
+                continue;
+            }
+
+            // Old GNU sparse members keep the rest of their map in extension blocks
+            let mut data_offset = offset + BLOCK_SIZE;
+            let mut is_extended = type_flag == b'S' && header[IS_EXTENDED] != 0;
+            while is_extended {
+                is_extended = read_block(file, data_offset)?[EXTENSION_IS_EXTENDED] != 0;
+                data_offset += BLOCK_SIZE;
+            }
+            // Links, devices, directories and pipes have no data, whatever their size says
+            let data_size = if matches!(type_flag, b'1'..=b'6') { 0 } else { size };
+            let end = data_offset + blocks(data_size);
+            if end > file_size {
+                return Err(bad_archive(format!("member at byte {} goes past the end of the file", start)));
+            }
+            members.push(Member{
+                offset: start,
+                data_offset,
+                data_size,
+                end,
+                main_header: header,
+            });
+            member_offset = None;
+            offset = end;
+        }
+        if members.is_empty() {
+            return Err(bad_archive("there are no members".to_string()));
+        }
+        return Ok(TarLayout{ members });
+    }
+
+    // Members in turn while they fit into a part together with the end of archive.
+    // A member bigger than a part starts a part of its own and goes on in the next ones,
+    // the zero blocks at the end of the archive go into the last part
+    pub fn plan_parts(&self, file_size: u64, limit: impl Fn(usize) -> u64) -> io::Result<Vec<TarPart>> {
+        let mut parts: Vec<TarPart> = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        let mut header = Vec::new();
+        for member in &self.members {
+            let part_limit = limit(parts.len());
+            if header.len() as u64 + member.end - start + TRAILER_SIZE <= part_limit {
+                end = member.end;
+                continue;
+            }
+            if end > start {
+                parts.push(TarPart{ size: end - start, header: std::mem::take(&mut header), trailer_size: TRAILER_SIZE });
+                start = end;
+            }
+            if member.end - member.offset + TRAILER_SIZE <= limit(parts.len()) {
+                end = member.end;
+                continue;
+            }
+
+            // The first piece has the headers of the member, the others start with `M` headers
+            while header.len() as u64 + member.end - start + TRAILER_SIZE > limit(parts.len()) {
+                let room = limit(parts.len()).saturating_sub(header.len() as u64) / BLOCK_SIZE * BLOCK_SIZE;
+                let size = room.min(member.end - start);
+                if start + size <= member.data_offset.max(start) {
+                    return Err(bad_archive(format!("headers of member at byte {} don't fit into a part", member.offset)));
+                }
+                parts.push(TarPart{ size, header: std::mem::take(&mut header), trailer_size: 0 });
+                start += size;
+                if start == member.end {
+                    break;
+                }
+                header = member.continuation_header(start - member.data_offset);
+            }
+            end = member.end;
+        }
+
+        // Zero blocks which end the archive and the padding to its record size
+        if header.len() as u64 + file_size - start <= limit(parts.len()) {
+            end = file_size;
+        }
+        if end > start {
+            let trailer_size = if end == file_size { 0 } else { TRAILER_SIZE };
+            parts.push(TarPart{ size: end - start, header: std::mem::take(&mut header), trailer_size });
+            start = end;
+        }
+        while start < file_size {
+            let size = limit(parts.len()).max(1).min(file_size - start);
+            parts.push(TarPart{ size, header: Vec::new(), trailer_size: 0 });
+            start += size;
+        }
+        return Ok(parts);
+    }
+}
+
+impl Member {
+    // `M` header which GNU tar writes at the start of a volume which goes on with this member,
+    // `done` bytes of its data were in the previous ones. Like GNU tar, a long name is cut to 100 bytes
+    fn continuation_header(&self, done: u64) -> Vec<u8> {
+        let mut header = vec![0u8; BLOCK_SIZE as usize];
+        // Name, mode, owner and modification time stay as they were
+        header[..124].copy_from_slice(&self.main_header[..124]);
+        header[136..148].copy_from_slice(&self.main_header[136..148]);
+        write_number(&mut header[SIZE_FIELD..SIZE_FIELD + 12], self.data_size - done);
+        header[TYPE_FLAG] = b'M';
+        header[257..265].copy_from_slice(b"ustar  \0");
+        header[265..329].copy_from_slice(&self.main_header[265..329]);
+        write_number(&mut header[OFFSET_FIELD..OFFSET_FIELD + 12], done);
+        let sum = checksum(&header);
+        header[CHECKSUM_FIELD..CHECKSUM_FIELD + 7].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header[CHECKSUM_FIELD + 7] = b' ';
+        return header;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn header(name: &str, size: u64, type_flag: u8) -> Vec<u8> {
+        let mut header = vec![0u8; BLOCK_SIZE as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_number(&mut header[100..108], 0o644);
+        write_number(&mut header[SIZE_FIELD..SIZE_FIELD + 12], size);
+        header[TYPE_FLAG] = type_flag;
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        let sum = checksum(&header);
+        header[CHECKSUM_FIELD..CHECKSUM_FIELD + 8].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        return header;
+    }
+
+    fn member(archive: &mut Vec<u8>, name: &str, size: u64, type_flag: u8) {
+        archive.extend_from_slice(&header(name, size, type_flag));
+        archive.resize(archive.len() + blocks(size) as usize, b'x');
+    }
+
+    fn layout(directory: &TempDir, name: &str, bytes: &[u8]) -> io::Result<TarLayout> {
+        let mut file = directory.file(name, bytes);
+        return TarLayout::read(&mut file, bytes.len() as u64);
+    }
+
+    fn sizes(parts: &[TarPart]) -> Vec<(u64, usize, u64)> {
+        return parts.iter().map(|part| (part.size, part.header.len(), part.trailer_size)).collect();
+    }
+
+    #[test]
+    fn members() {
+        let directory = TempDir::new();
+        let mut bytes = Vec::new();
+        member(&mut bytes, "a.txt", 100, b'0');
+        // A long name goes with the member which follows it
+        member(&mut bytes, "././@LongLink", 10, b'L');
+        member(&mut bytes, "b.txt", 600, b'0');
+        member(&mut bytes, "dir", 0, b'5');
+        bytes.resize(bytes.len() + TRAILER_SIZE as usize, 0);
+        let tar = layout(&directory, "archive.tar", &bytes).unwrap();
+
+        let spans: Vec<(u64, u64, u64, u64)> = tar.members.iter().map(|member| (member.offset, member.data_offset, member.data_size, member.end)).collect();
+        assert_eq!(spans, vec![(0, 512, 100, 1024), (1024, 2560, 600, 3584), (3584, 4096, 0, 4096)]);
+    }
+
+    #[test]
+    fn parts_end_between_members() {
+        let directory = TempDir::new();
+        let mut bytes = Vec::new();
+        member(&mut bytes, "a.txt", 100, b'0');
+        member(&mut bytes, "b.txt", 600, b'0');
+        member(&mut bytes, "c.txt", 10, b'0');
+        bytes.resize(bytes.len() + TRAILER_SIZE as usize, 0);
+        let tar = layout(&directory, "archive.tar", &bytes).unwrap();
+
+        assert_eq!(sizes(&tar.plan_parts(4608, |_| 10000).unwrap()), vec![(4608, 0, 0)]);
+        // Every part but the last gets zero blocks of its own, the last one has those of the archive
+        assert_eq!(sizes(&tar.plan_parts(4608, |_| 3000).unwrap()), vec![(1024, 0, 1024), (1536, 0, 1024), (2048, 0, 0)]);
+    }
+
+    #[test]
+    fn big_members_go_on_in_the_next_parts() {
+        let directory = TempDir::new();
+        let mut bytes = Vec::new();
+        member(&mut bytes, "a.txt", 100, b'0');
+        member(&mut bytes, "big.bin", 3000, b'0');
+        member(&mut bytes, "c.txt", 10, b'0');
+        bytes.resize(bytes.len() + TRAILER_SIZE as usize, 0);
+        let tar = layout(&directory, "archive.tar", &bytes).unwrap();
+
+        let parts = tar.plan_parts(6656, |_| 2048).unwrap();
+        assert_eq!(sizes(&parts), vec![(1024, 0, 1024), (2048, 0, 0), (1536, 512, 0), (2048, 0, 0)]);
+
+        // The third part goes on with the data of `big.bin` after its first 1536 bytes
+        let continuation = &parts[2].header;
+        assert_eq!(continuation[TYPE_FLAG], b'M');
+        assert_eq!(&continuation[..7], b"big.bin");
+        assert_eq!(parse_number(&continuation[SIZE_FIELD..SIZE_FIELD + 12]), Some(3000 - 1536));
+        assert_eq!(parse_number(&continuation[OFFSET_FIELD..OFFSET_FIELD + 12]), Some(1536));
+
+        // With its header the part is an archive which tar can read
+        let mut part = continuation.clone();
+        part.extend_from_slice(&bytes[3072..4608]);
+        let part_tar = layout(&directory, "part3.tar", &part).unwrap();
+        assert_eq!(part_tar.members.len(), 1);
+        assert_eq!(part_tar.members[0].end, 2048);
+    }
+
+    #[test]
+    fn numbers() {
+        let mut field = [0u8; 12];
+        write_number(&mut field, 0o1234);
+        assert_eq!(&field, b"00000001234\0");
+        assert_eq!(parse_number(&field), Some(0o1234));
+        write_number(&mut field, 1 << 40);
+        assert_eq!(field[0], 0x80);
+        assert_eq!(parse_number(&field), Some(1 << 40));
+        assert_eq!(parse_number(b"   17 \0"), Some(0o17));
+        assert_eq!(parse_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"0009\0"), None);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let directory = TempDir::new();
+        let mut bytes = header("a.txt", 0, b'0');
+        bytes[0] = b'b';
+        bytes.resize(bytes.len() + TRAILER_SIZE as usize, 0);
+        assert_eq!(layout(&directory, "checksum.tar", &bytes).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(layout(&directory, "empty.tar", &[0; 1024]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        member(&mut bytes, "a.txt", 1000, b'0');
+        bytes.truncate(1024);
+        assert_eq!(layout(&directory, "short.tar", &bytes).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,273 @@
+// Telegram Bot API, which sends parts to a chat as documents and fetches them back.
+// The bot token is taken from `SPLIMER_TELEGRAM_TOKEN`, so it stays out of the shell history.
+// `--telegram-api` points to another server, like a local Bot API server, which takes documents
+// of up to 2000 MB, where api.telegram.org takes 50 MB from bots and gives back 20 MB.
+//
+// What is sent is written into the manifest, so an interrupted upload goes on where it stopped
+// and the manifest which is pinned in the chat tells download where every part is.
+// Only the latest pinned message is known to bots, older sets are fetched by the message of their manifest:
+//
+//     telegram_chat=-1001234567890
+//     telegram_part=1 42 BQACAgIAAxkDAAI...
+//
+// `telegram_part=` is the index of a part, the message with it and the id of its file.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::http::{self, Request, Response, Url};
+use crate::json::{self, Value};
+use crate::manifest::Manifest;
+
+pub const DEFAULT_API: &str = "https://api.telegram.org";
+// Largest file bots can get back from api.telegram.org. Bots may send up to 50 MB there,
+// but a set is only of use when it can be downloaded again
+pub const TRANSFER_LIMIT: u64 = 20 * 1000 * 1000;
+const MAX_ATTEMPTS: u32 = 5;
+
+const CHAT_KEY: &str = "telegram_chat";
+const PART_KEY: &str = "telegram_part";
+
+pub struct TelegramBot {
+    api: Url,
+    token: String,
+}
+
+pub struct SentDocument {
+    pub message_id: i64,
+    pub file_id: String,
+}
+
+// Error which the Bot API answers with
+#[derive(Debug)]
+struct ApiError {
+    code: i64,
+    description: String,
+    // Seconds to wait after too many requests
+    retry_after: Option<u64>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Telegram answers {}: {}", self.code, self.description);
+    }
+}
+
+impl Error for ApiError { }
+
+impl TelegramBot {
+    pub fn new(api: Option<&str>) -> io::Result<TelegramBot> {
+        let token = env::var("SPLIMER_TELEGRAM_TOKEN").ok().filter(|token| !token.is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "`SPLIMER_TELEGRAM_TOKEN` is not set, it should be the token of a bot"))?;
+        let api = api.unwrap_or(DEFAULT_API);
+        let api = Url::parse(api)
+            .filter(|url| matches!(url.scheme.as_str(), "http" | "https"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Telegram API \"{}\" is not an http or https URL", api)))?;
+        if api.is_tls() && !cfg!(feature = "tls") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} needs https, but splimer is built without the `tls` feature, rebuild it with `--features tls` or give an http `--telegram-api`", api.host)
+            ));
+        }
+        return Ok(TelegramBot{ api, token });
+    }
+
+    pub fn is_default_api(&self) -> bool {
+        return self.api.to_http(&self.api.path) == DEFAULT_API;
+    }
+
+    // Requests go to `/bot<token>/<method>` and files come from `/file/bot<token>/<path>`,
+    // the token is sent as it is, `:` in it is fine in a path
+    fn start(&self, method: &'static str, prefix: &str, path: &str, content_type: Option<String>, content_length: u64) -> io::Result<http::Upload> {
+        let headers = content_type.map(|content_type| vec![("Content-Type".to_string(), content_type)]).unwrap_or_default();
+        let target = format!("{}/{}{}/{}", http::encode_path(&self.api.path), prefix, self.token, http::encode_path(path));
+        return http::start(&self.api, Request{ method, target, headers }, content_length);
+    }
+
+    // `method` with form fields, its `result` is returned
+    fn call(&self, method: &str, fields: &[(&str, &str)]) -> io::Result<Value> {
+        let body = fields.iter()
+            .map(|(name, value)| format!("{}={}", http::encode_component(name), http::encode_component(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut upload = self.start(
+            "POST",
+            "bot",
+            method,
+            Some("application/x-www-form-urlencoded".to_string()),
+            body.len() as u64
+        )?;
+        upload.write_all(body.as_bytes())?;
+        return Self::result(upload.finish()?);
+    }
+
+    fn result(response: Response) -> io::Result<Value> {
+        let status = response.status as i64;
+        let text = response.text()?;
+        let Some(answer) = json::parse(&text) else {
+            let description: String = text.chars().take(300).collect();
+            return Err(io::Error::other(ApiError{ code: status, description: description.trim().to_string(), retry_after: None }));
+        };
+        if answer.get("ok").and_then(Value::as_bool) != Some(true) {
+            return Err(io::Error::other(ApiError{
+                code: answer.get("error_code").and_then(Value::as_i64).unwrap_or(status),
+                description: answer.get("description").and_then(Value::as_str).unwrap_or("").to_string(),
+                retry_after: answer.get("parameters").and_then(|parameters| parameters.get("retry_after")).and_then(Value::as_i64).map(|seconds| seconds.max(0) as u64),
+            }));
+        }
+        return answer.get("result").cloned().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Telegram answers without a result"));
+    }
+
+    // Sends `size` bytes of `document` as a file named `name`, the body is streamed as it is read
+    pub fn send_document(&self, chat: &str, name: &str, size: u64, document: &mut dyn Read, caption: &str) -> io::Result<SentDocument> {
+        let boundary = format!("splimer-{:x}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos());
+        let field = |name: &str, value: &str| format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+        let head = field("chat_id", chat)
+            + &field("caption", caption)
+            + &field("disable_content_type_detection", "true")
+            + &format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"document\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                boundary,
+                name.replace('"', "%22")
+            );
+        let tail = format!("\r\n--{}--\r\n", boundary);
+
+        let mut upload = self.start(
+            "POST",
+            "bot",
+            "sendDocument",
+            Some(format!("multipart/form-data; boundary={}", boundary)),
+            head.len() as u64 + size + tail.len() as u64
+        )?;
+        upload.write_all(head.as_bytes())?;
+        let copied = io::copy(&mut document.take(size), &mut upload)?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} has {} bytes, but {} were expected", name, copied, size)));
+        }
+        upload.write_all(tail.as_bytes())?;
+
+        let message = Self::result(upload.finish()?)?;
+        let message_id = message.get("message_id").and_then(Value::as_i64);
+        let file_id = message.get("document").and_then(|document| document.get("file_id")).and_then(Value::as_str);
+        return match (message_id, file_id) {
+            (Some(message_id), Some(file_id)) => Ok(SentDocument{ message_id, file_id: file_id.to_string() }),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Telegram answers sendDocument without a message or file id")),
+        };
+    }
+
+    pub fn pin(&self, chat: &str, message_id: i64) -> io::Result<()> {
+        self.call("pinChatMessage", &[("chat_id", chat), ("message_id", &message_id.to_string()), ("disable_notification", "true")])?;
+        return Ok(());
+    }
+
+    // File id and name of the document in the pinned message of the chat
+    pub fn pinned_document(&self, chat: &str) -> io::Result<Option<(String, String)>> {
+        let chat = self.call("getChat", &[("chat_id", chat)])?;
+        return Ok(chat.get("pinned_message").and_then(message_document));
+    }
+
+    // File id and name of the document in a message. Bots cannot read messages of a chat,
+    // but they get the forwarded copy of a message back, the copy is removed then
+    pub fn document(&self, chat: &str, message_id: i64) -> io::Result<Option<(String, String)>> {
+        let copy = self.call("forwardMessage", &[
+            ("chat_id", chat),
+            ("from_chat_id", chat),
+            ("message_id", &message_id.to_string()),
+            ("disable_notification", "true"),
+        ])?;
+        if let Some(copy_id) = copy.get("message_id").and_then(Value::as_i64) {
+            // The copy which stays when the bot may not delete messages does no harm
+            let _ = self.call("deleteMessage", &[("chat_id", chat), ("message_id", &copy_id.to_string())]);
+        }
+        return Ok(message_document(&copy));
+    }
+
+    pub fn download(&self, file_id: &str) -> io::Result<Box<dyn Read>> {
+        let file = self.call("getFile", &[("file_id", file_id)])?;
+        let file_path = file.get("file_path").and_then(Value::as_str)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Telegram answers getFile without a file path, the file may be too big for bots"))?;
+        // A local Bot API server gives paths on its own disk
+        if Path::new(file_path).is_absolute() {
+            return Ok(Box::new(File::open(file_path)?));
+        }
+        let response = self.start("GET", "file/bot", file_path, None, 0)?.finish()?;
+        if response.status != 200 {
+            return Err(Self::result(response).err().unwrap_or_else(|| io::Error::other("Telegram doesn't give the file")));
+        }
+        return Ok(response.into_body());
+    }
+}
+
+fn message_document(message: &Value) -> Option<(String, String)> {
+    let document = message.get("document")?;
+    let file_id = document.get("file_id").and_then(Value::as_str).unwrap_or("");
+    let file_name = document.get("file_name").and_then(Value::as_str).unwrap_or("");
+    return Some((file_id.to_string(), file_name.to_string()));
+}
+
+// Runs `attempt` until it succeeds, waiting after errors which may pass, like a lost connection
+// or too many requests. `on_retry` is told what went wrong and how long it waits
+pub fn retry<T>(mut attempt: impl FnMut() -> io::Result<T>, mut on_retry: impl FnMut(&io::Error, Duration)) -> io::Result<T> {
+    let mut number = 1;
+    loop {
+        let err = match attempt() {
+            Ok(result) => return Ok(result),
+            Err(err) => err,
+        };
+        let backoff = Duration::from_secs(1 << (number - 1));
+        let delay = match err.get_ref().and_then(|inner| inner.downcast_ref::<ApiError>()) {
+            Some(ApiError{ code: 429, retry_after, .. }) => Some(retry_after.map(Duration::from_secs).unwrap_or(backoff)),
+            Some(ApiError{ code: 500..=599, .. }) => Some(backoff),
+            Some(_) => None,
+            None => match err.kind() {
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                io::ErrorKind::BrokenPipe | io::ErrorKind::TimedOut | io::ErrorKind::UnexpectedEof | io::ErrorKind::Interrupted => Some(backoff),
+                _ => None,
+            },
+        };
+        match delay {
+            Some(delay) if number < MAX_ATTEMPTS => {
+                on_retry(&err, delay);
+                thread::sleep(delay);
+                number += 1;
+            },
+            _ => return Err(err),
+        }
+    }
+}
+
+// Chat which parts of the set were sent to
+pub fn sent_chat(manifest: &Manifest) -> Option<&str> {
+    return manifest.extra.iter().find(|(key, _)| key == CHAT_KEY).map(|(_, value)| value.as_str());
+}
+
+// Parts which were sent, by their indexes
+pub fn sent_parts(manifest: &Manifest) -> Vec<(usize, SentDocument)> {
+    return manifest.extra.iter()
+        .filter(|(key, _)| key == PART_KEY)
+        .filter_map(|(_, value)| {
+            let mut fields = value.split(' ');
+            let index = fields.next()?.parse().ok()?;
+            let message_id = fields.next()?.parse().ok()?;
+            let file_id = fields.next()?.to_string();
+            Some((index, SentDocument{ message_id, file_id }))
+        })
+        .collect();
+}
+
+pub fn record_sent_part(manifest: &mut Manifest, chat: &str, index: usize, sent: &SentDocument) {
+    // Parts sent to another chat are of no use here
+    if sent_chat(manifest) != Some(chat) {
+        manifest.extra.retain(|(key, _)| key != CHAT_KEY && key != PART_KEY);
+        manifest.extra.push((CHAT_KEY.to_string(), chat.to_string()));
+    }
+    let prefix = format!("{} ", index);
+    manifest.extra.retain(|(key, value)| key != PART_KEY || !value.starts_with(&prefix));
+    manifest.extra.push((PART_KEY.to_string(), format!("{} {} {}", index, sent.message_id, sent.file_id)));
+}
@@ -0,0 +1,157 @@
+// Helpers for tests which need real files, like boundaries which are looked for in a file
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Directory of one test, it is removed with everything in it when the test ends
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("splimer-test-{}-{}", process::id(), id));
+        fs::create_dir_all(&path).unwrap();
+        return TempDir{ path };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        return self.path.join(name);
+    }
+
+    // Writes `bytes` into a new file and opens it for reading
+    pub fn file(&self, name: &str, bytes: &[u8]) -> File {
+        fs::write(self.join(name), bytes).unwrap();
+        return File::open(self.join(name)).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// Tar archive of plain files, as `tar cf` writes it without padding to a record
+pub fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (name, data) in files {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = b'0';
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        // The checksum is counted with its own field as spaces
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len().div_ceil(512) * 512, 0);
+    }
+    bytes.resize(bytes.len() + 1024, 0);
+    return bytes;
+}
+
+// Bytes which differ from file to file, so a part in a wrong place is noticed
+pub fn data(size: usize, seed: u32) -> Vec<u8> {
+    return (0..size as u32).map(|i| (i.wrapping_mul(7).wrapping_add(seed) % 251) as u8).collect();
+}
+
+// Zip archive of stored entries without checksums, which is all splimer looks at
+pub fn zip_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in entries {
+        let offset = bytes.len() as u32;
+        bytes.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 14]);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(data);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&[0; 16]);
+        directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        directory.extend_from_slice(&[0; 12]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+    let directory_offset = bytes.len() as u32;
+    bytes.extend_from_slice(&directory);
+    bytes.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&directory_offset.to_le_bytes());
+    bytes.extend_from_slice(&[0; 2]);
+    return bytes;
+}
+
+pub struct HttpRequest {
+    pub method: String,
+    pub target: String,
+    pub body: Vec<u8>,
+}
+
+// HTTP server on a free local port, every request is answered with the whole response `answer` gives.
+// Its address is returned, like `http://127.0.0.1:40000`
+pub fn serve(mut answer: impl FnMut(HttpRequest) -> Vec<u8> + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut words = line.split(' ');
+            let method = words.next().unwrap_or("").to_string();
+            let target = words.next().unwrap_or("").to_string();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = stream.write_all(&answer(HttpRequest{ method, target, body }));
+        }
+    });
+    return address;
+}
+
+pub fn http_response(status: u16, body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len()).into_bytes();
+    response.extend_from_slice(body);
+    return response;
+}
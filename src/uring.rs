@@ -0,0 +1,244 @@
+// io_uring backend for `--io=uring`, built with `--features io-uring`.
+// Several reads and writes are in flight at once, each one in its own registered buffer.
+// Reads may finish in any order, but bytes are hashed and written strictly in order.
+
+use std::fs::File;
+use std::io;
+
+use crate::sha256::Sha256;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod ring {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use io_uring::{opcode, types, IoUring};
+
+    use crate::copy::{self, Buffer};
+    use crate::sha256::Sha256;
+
+    // Requests in flight, each one has a buffer of `--buffer-size` bytes
+    const QUEUE_DEPTH: usize = 8;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum SlotState {
+        Free,
+        Reading,
+        Read,
+        Writing,
+    }
+
+    struct Slot {
+        state: SlotState,
+        // Where the chunk starts, counted from the beginning of the range
+        offset: u64,
+        length: usize,
+        // Bytes of the chunk which are read or written so far
+        done: usize,
+        // The request is sent while files are still opened with `O_DIRECT`
+        is_direct: bool,
+    }
+
+    pub fn is_available() -> bool {
+        return IoUring::new(2).is_ok();
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_range(
+        source: &File,
+        source_offset: u64,
+        target: &File,
+        target_offset: u64,
+        length: u64,
+        chunk_size: usize,
+        hashes: &mut [&mut Sha256],
+        progress: &mut dyn FnMut(u64)
+    ) -> io::Result<()> {
+        // Buffers are made before the ring, so they are dropped after it
+        let mut buffers: Vec<Buffer> = (0..QUEUE_DEPTH).map(|_| Buffer::new(chunk_size)).collect();
+        let mut ring = IoUring::new(QUEUE_DEPTH as u32 * 2)
+            .map_err(|err| io::Error::new(io::ErrorKind::Unsupported, err))?;
+        let iovecs: Vec<libc::iovec> = buffers.iter_mut()
+            .map(|buffer| libc::iovec{ iov_base: buffer.as_mut_ptr().cast(), iov_len: buffer.len() })
+            .collect();
+        // SAFETY: buffers are not moved, and they are dropped only when no request uses them,
+        // after the ring or after every request is completed, see below
+        unsafe { ring.submitter().register_buffers(&iovecs) }
+            .map_err(|err| io::Error::new(io::ErrorKind::Unsupported, err))?;
+
+        let source_fd = types::Fd(source.as_raw_fd());
+        let target_fd = types::Fd(target.as_raw_fd());
+        let mut slots: Vec<Slot> = (0..QUEUE_DEPTH)
+            .map(|_| Slot{ state: SlotState::Free, offset: 0, length: 0, done: 0, is_direct: false })
+            .collect();
+
+        let mut next_read = 0u64;
+        let mut next_write = 0u64;
+        let mut written = 0u64;
+        let mut is_direct_cleared = false;
+        // Requests which are sent and not completed yet
+        let mut in_flight = 0usize;
+
+        // Puts the rest of the slot's read or write into the queue
+        let submit = |ring: &mut IoUring, buffers: &mut [Buffer], slots: &mut [Slot], index: usize, is_direct_cleared: bool, in_flight: &mut usize| -> io::Result<()> {
+            let slot = &mut slots[index];
+            slot.is_direct = !is_direct_cleared;
+            let buffer = buffers[index][slot.done..].as_mut_ptr();
+            let length = (slot.length - slot.done) as u32;
+            let entry = match slot.state {
+                SlotState::Reading => opcode::ReadFixed::new(source_fd, buffer, length, index as u16)
+                    .offset(source_offset + slot.offset + slot.done as u64)
+                    .build(),
+                _ => opcode::WriteFixed::new(target_fd, buffer, length, index as u16)
+                    .offset(target_offset + slot.offset + slot.done as u64)
+                    .build(),
+            };
+            // SAFETY: the buffer is registered and stays untouched until the request is completed
+            unsafe { ring.submission().push(&entry.user_data(index as u64)) }
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+            *in_flight += 1;
+            return Ok(());
+        };
+
+        let result = (|| -> io::Result<()> {
+            while written < length {
+                // Every free buffer takes the next chunk to read
+                for index in 0..QUEUE_DEPTH {
+                    if slots[index].state == SlotState::Free && next_read < length {
+                        slots[index] = Slot{
+                            state: SlotState::Reading,
+                            offset: next_read,
+                            length: (length - next_read).min(chunk_size as u64) as usize,
+                            done: 0,
+                            is_direct: false,
+                        };
+                        next_read += slots[index].length as u64;
+                        submit(&mut ring, &mut buffers, &mut slots, index, is_direct_cleared, &mut in_flight)?;
+                    }
+                }
+
+                // A signal only wakes the wait up, the requests are still in flight
+                match ring.submit_and_wait(1) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    result => result?,
+                };
+                let completed: Vec<(usize, i32)> = ring.completion()
+                    .map(|entry| (entry.user_data() as usize, entry.result()))
+                    .collect();
+                in_flight -= completed.len();
+
+                for (index, result) in completed {
+                    if result == -libc::EINTR || result == -libc::EAGAIN {
+                        submit(&mut ring, &mut buffers, &mut slots, index, is_direct_cleared, &mut in_flight)?;
+                        continue;
+                    }
+                    // Unaligned requests are refused with `O_DIRECT`, they are repeated without it,
+                    // and so is every other request which was sent before it is cleared
+                    if result == -libc::EINVAL && slots[index].is_direct {
+                        if !is_direct_cleared {
+                            copy::clear_direct(source)?;
+                            copy::clear_direct(target)?;
+                            is_direct_cleared = true;
+                        }
+                        submit(&mut ring, &mut buffers, &mut slots, index, is_direct_cleared, &mut in_flight)?;
+                        continue;
+                    }
+                    if result < 0 {
+                        return Err(io::Error::from_raw_os_error(-result));
+                    }
+                    if result == 0 {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    }
+
+                    let slot = &mut slots[index];
+                    slot.done += result as usize;
+                    if slot.done < slot.length {
+                        submit(&mut ring, &mut buffers, &mut slots, index, is_direct_cleared, &mut in_flight)?;
+                        continue;
+                    }
+                    match slot.state {
+                        SlotState::Reading => slot.state = SlotState::Read,
+                        _ => {
+                            written += slot.length as u64;
+                            slot.state = SlotState::Free;
+                            progress(written);
+                        },
+                    }
+                }
+
+                // Chunks which are read are hashed and written in the order of the file
+                while let Some(index) = slots.iter().position(|slot| slot.state == SlotState::Read && slot.offset == next_write) {
+                    let slot = &mut slots[index];
+                    for hash in hashes.iter_mut() {
+                        hash.update(&buffers[index][..slot.length]);
+                    }
+                    next_write += slot.length as u64;
+                    slot.state = SlotState::Writing;
+                    slot.done = 0;
+                    submit(&mut ring, &mut buffers, &mut slots, index, is_direct_cleared, &mut in_flight)?;
+                }
+            }
+            return Ok(());
+        })();
+
+        // The kernel may still write into buffers of requests which are in flight after an error
+        while result.is_err() && in_flight > 0 {
+            match ring.submit_and_wait(1) {
+                Ok(_) => in_flight -= ring.completion().count(),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => { },
+                Err(_) => {
+                    // They cannot be waited for, so they are never freed
+                    std::mem::forget(buffers);
+                    return result;
+                },
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+mod ring {
+    use std::fs::File;
+    use std::io;
+
+    use crate::sha256::Sha256;
+
+    pub fn is_available() -> bool {
+        return false;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_range(
+        _source: &File,
+        _source_offset: u64,
+        _target: &File,
+        _target_offset: u64,
+        _length: u64,
+        _chunk_size: usize,
+        _hashes: &mut [&mut Sha256],
+        _progress: &mut dyn FnMut(u64)
+    ) -> io::Result<()> {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "splimer is built without io_uring"));
+    }
+}
+
+pub fn is_available() -> bool {
+    return ring::is_available();
+}
+
+// Fails with `Unsupported` when there is no io_uring, then the usual loop is used
+#[allow(clippy::too_many_arguments)]
+pub fn copy_range(
+    source: &File,
+    source_offset: u64,
+    target: &File,
+    target_offset: u64,
+    length: u64,
+    chunk_size: usize,
+    hashes: &mut [&mut Sha256],
+    progress: &mut dyn FnMut(u64)
+) -> io::Result<()> {
+    return ring::copy_range(source, source_offset, target, target_offset, length, chunk_size, hashes, progress);
+}
@@ -0,0 +1,395 @@
+// `splimer watch <dir>` splits files which are dropped into a directory, like release archives of a build server.
+// A file is split when it stops changing for `--settle` seconds, with split options given to watch as the profile.
+// Parts are made in `<outbox>/.splimer-work` and moved into the outbox, `<dir>/outbox` by default,
+// only when the whole set is ready. Every file which is handled is written into the state file:
+//
+//     done 5000000 1760000000123456789 release-1.2.zip
+//
+// which is its status, size, modification time in nanoseconds and name, so after a restart
+// only new and changed files are split. `--status-file` is rewritten with what watch is doing.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::json;
+use crate::notify::Events;
+use crate::output::{self, ClobberPolicy};
+use crate::parser::{Command, ProgramInput};
+use crate::paths;
+use crate::report::{self, Reporter};
+use crate::splimer::Splimer;
+use crate::store;
+
+const STATE_FILENAME: &str = ".splimer-watch";
+const WORK_DIRECTORY: &str = ".splimer-work";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct FileStamp {
+    size: u64,
+    // Nanoseconds since the epoch
+    modified: u128,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FileStatus {
+    Done,
+    Failed,
+    // Smaller than one part, so there is nothing to split
+    Skipped,
+}
+
+impl FileStatus {
+    fn from_name(name: &str) -> Option<FileStatus> {
+        return match name {
+            "done" => Some(FileStatus::Done),
+            "failed" => Some(FileStatus::Failed),
+            "skipped" => Some(FileStatus::Skipped),
+            _ => None,
+        };
+    }
+
+    fn name(&self) -> &'static str {
+        return match self {
+            FileStatus::Done => "done",
+            FileStatus::Failed => "failed",
+            FileStatus::Skipped => "skipped",
+        };
+    }
+}
+
+struct Watch {
+    profile: ProgramInput,
+    directory: PathBuf,
+    outbox: PathBuf,
+    work_directory: PathBuf,
+    state_filename: PathBuf,
+    status_filename: Option<PathBuf>,
+    settle: Duration,
+    // Files which are handled, by name
+    state: BTreeMap<OsString, (FileStatus, FileStamp)>,
+    // Files which are not handled yet, with the last time they changed
+    candidates: BTreeMap<OsString, (FileStamp, Instant)>,
+    // Last handled file
+    last: Option<(OsString, FileStatus)>,
+    report: Reporter,
+}
+
+pub fn watch(program_input: ProgramInput) -> bool {
+    let directory = program_input.input_filename.clone();
+    let outbox = program_input.outbox.clone().unwrap_or(directory.join("outbox"));
+    let report = Reporter::new(program_input.output_format);
+    if !directory.is_dir() {
+        report.error(&format!("Directory {} is not found", directory.display()));
+        return false;
+    }
+    let mut watch = Watch{
+        work_directory: outbox.join(WORK_DIRECTORY),
+        state_filename: program_input.state_file.clone().unwrap_or(directory.join(STATE_FILENAME)),
+        status_filename: program_input.status_file.clone(),
+        settle: program_input.settle,
+        state: BTreeMap::new(),
+        candidates: BTreeMap::new(),
+        last: None,
+        profile: program_input,
+        directory,
+        outbox,
+        report,
+    };
+    if let Err(err) = fs::create_dir_all(&watch.work_directory) {
+        watch.report.error(&format!("Outbox {} cannot be made, {}", watch.outbox.display(), err));
+        return false;
+    }
+    if let Err(err) = watch.read_state() {
+        watch.report.error(&format!("State file {} cannot be read, {}", watch.state_filename.display(), err));
+        return false;
+    }
+    let mut events = match Events::new(&watch.directory) {
+        Ok(events) => events,
+        Err(err) => {
+            watch.report.error(&format!("Directory {} cannot be watched, {}", watch.directory.display(), err));
+            return false;
+        },
+    };
+
+    watch.report.message(&format!("Watching {}, parts go to {}", watch.directory.display(), watch.outbox.display()));
+    watch.report.event("watching", &[
+        ("directory", json::string(&watch.directory.to_string_lossy())),
+        ("outbox", json::string(&watch.outbox.to_string_lossy())),
+    ]);
+    watch.scan();
+    watch.write_status(None);
+    loop {
+        // Changing files are looked at every second, until they settle
+        let timeout = if watch.candidates.is_empty() { None } else { Some(Duration::from_secs(1)) };
+        match events.wait(timeout) {
+            Ok(Some(changes)) => {
+                for change in changes {
+                    watch.notice(&change.name);
+                }
+            },
+            Ok(None) => watch.scan(),
+            Err(err) => {
+                watch.report.error(&format!("Directory {} cannot be watched, {}", watch.directory.display(), err));
+                return false;
+            },
+        }
+        watch.split_settled();
+    }
+}
+
+impl Watch {
+    fn scan(&mut self) {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) => {
+                self.report.warning(&format!("{} cannot be listed, {}", self.directory.display(), err));
+                return;
+            },
+        };
+        for entry in entries.flatten() {
+            self.notice(&entry.file_name());
+        }
+    }
+
+    // A file is split once it is not changed for `--settle`, so the clock starts again on every change
+    fn notice(&mut self, name: &OsStr) {
+        let path = self.directory.join(name);
+        let is_own = self.state_filename == path || self.status_filename.as_ref().is_some_and(|status| *status == path);
+        if is_own || name.as_encoded_bytes().starts_with(b".") || paths::split_tagged_name(name).is_some() {
+            return;
+        }
+        let Some(stamp) = Self::stamp(&path) else {
+            self.candidates.remove(name);
+            return;
+        };
+        if self.state.get(name).is_some_and(|(_, handled)| *handled == stamp) {
+            return;
+        }
+        match self.candidates.get(name) {
+            Some((seen, _)) if *seen == stamp => { },
+            _ => {
+                self.candidates.insert(name.to_os_string(), (stamp, Instant::now()));
+            },
+        }
+    }
+
+    fn split_settled(&mut self) {
+        let names: Vec<OsString> = self.candidates.keys().cloned().collect();
+        for name in names {
+            // Files may grow without events when they are written through a mapping
+            self.notice(&name);
+            let Some(&(stamp, since)) = self.candidates.get(&name) else {
+                continue;
+            };
+            if since.elapsed() < self.settle {
+                continue;
+            }
+            self.candidates.remove(&name);
+            let status = self.split(&name);
+            self.state.insert(name.clone(), (status, stamp));
+            self.last = Some((name, status));
+            if let Err(err) = self.write_state() {
+                self.report.warning(&format!("state file {} cannot be written, {}", self.state_filename.display(), err));
+            }
+            self.write_status(None);
+        }
+    }
+
+    fn split(&mut self, name: &OsStr) -> FileStatus {
+        let path = self.directory.join(name);
+        self.write_status(Some(name));
+        self.report.event("file_started", &[("path", json::string(&path.to_string_lossy()))]);
+        if let Err(err) = self.clear_work_directory() {
+            return self.failed(&path, &format!("{} cannot be cleared, {}", self.work_directory.display(), err));
+        }
+
+        let mut program_input = self.profile.clone();
+        program_input.command = Command::Split;
+        program_input.input_filename = path.clone();
+        program_input.output_directory = Some(self.work_directory.clone());
+        let location = Splimer::store_location(&program_input);
+        let store = match store::open(&location, program_input.fsync, program_input.direct) {
+            Ok(store) => store,
+            Err(err) => return self.failed(&path, &err.to_string()),
+        };
+        let mut splimer = Splimer::new(program_input, store);
+        // Split panics when a file cannot be read or written, that is one failed file for watch,
+        // so the panic is told only as the reason, not printed as a crash
+        let reason: Arc<Mutex<Option<String>>> = Arc::default();
+        let previous_hook = panic::take_hook();
+        let panic_reason = reason.clone();
+        panic::set_hook(Box::new(move |info| {
+            *panic_reason.lock().unwrap() = Some(report::panic_message(info.payload()).to_string());
+        }));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| splimer.split()));
+        panic::set_hook(previous_hook);
+        if result.is_err() {
+            let reason = reason.lock().unwrap().take().unwrap_or_else(|| "split fails".to_string());
+            return self.failed(&path, &reason);
+        }
+        if splimer.has_failed() {
+            return self.failed(&path, "split fails");
+        }
+
+        let mut files: Vec<OsString> = match fs::read_dir(&self.work_directory) {
+            Ok(entries) => entries.flatten()
+                .map(|entry| entry.file_name())
+                .filter(|file| paths::split_tagged_name(file).is_some())
+                .collect(),
+            Err(err) => return self.failed(&path, &err.to_string()),
+        };
+        if files.is_empty() {
+            self.report.message(&format!("File {} is smaller than one part, it is left as it is", path.display()));
+            self.report.event("file_skipped", &[("path", json::string(&path.to_string_lossy()))]);
+            return FileStatus::Skipped;
+        }
+        // The manifest goes last, so a set in the outbox is complete when its manifest is there
+        files.sort_by_key(|file| paths::split_tagged_name(file).is_some_and(|(_, tag)| tag == "manifest"));
+        if let Err(err) = self.move_to_outbox(name, &files) {
+            return self.failed(&path, &format!("parts cannot be moved to {}, {}", self.outbox.display(), err));
+        }
+
+        let parts = files.len() - 1;
+        self.report.message(&format!("File {} is split into {} parts in {}", path.display(), parts, self.outbox.display()));
+        self.report.event("file_done", &[
+            ("path", json::string(&path.to_string_lossy())),
+            ("parts", parts.to_string()),
+            ("outbox", json::string(&self.outbox.to_string_lossy())),
+        ]);
+        return FileStatus::Done;
+    }
+
+    fn failed(&self, path: &Path, reason: &str) -> FileStatus {
+        self.report.error(&format!("File {} is not split, {}", path.display(), reason));
+        return FileStatus::Failed;
+    }
+
+    // Leftovers of an interrupted split
+    fn clear_work_directory(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.work_directory)?.flatten() {
+            if entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Parts of an older version of the file are replaced, a shorter set would leave some of them behind
+    fn move_to_outbox(&self, name: &OsStr, files: &[OsString]) -> io::Result<()> {
+        for entry in fs::read_dir(&self.outbox)?.flatten() {
+            if paths::split_tagged_name(&entry.file_name()).is_some_and(|(stem, _)| stem == name) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        for file in files {
+            fs::rename(self.work_directory.join(file), self.outbox.join(file))?;
+        }
+        return output::sync_directory(&self.outbox.join(name));
+    }
+
+    fn stamp(path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok().filter(|metadata| metadata.is_file())?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0);
+        return Some(FileStamp{ size: metadata.len(), modified });
+    }
+
+    fn read_state(&mut self) -> io::Result<()> {
+        let text = match fs::read_to_string(&self.state_filename) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        self.state = Self::parse_state(&text)?;
+        return Ok(());
+    }
+
+    fn parse_state(text: &str) -> io::Result<BTreeMap<OsString, (FileStatus, FileStamp)>> {
+        let mut state = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(4, ' ').collect();
+            let entry = match fields[..] {
+                [status, size, modified, name] => FileStatus::from_name(status)
+                    .zip(size.parse().ok())
+                    .zip(modified.parse().ok())
+                    .zip(paths::decode(name)),
+                _ => None,
+            };
+            let Some((((status, size), modified), name)) = entry else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("cannot parse line {}: \"{}\"", number + 1, line)));
+            };
+            state.insert(name, (status, FileStamp{ size, modified }));
+        }
+        return Ok(state);
+    }
+
+    fn write_state(&self) -> io::Result<()> {
+        return output::write_file(&self.state_filename, Self::format_state(&self.state).as_bytes(), ClobberPolicy::Force);
+    }
+
+    fn format_state(state: &BTreeMap<OsString, (FileStatus, FileStamp)>) -> String {
+        let mut text = String::from("# splimer watch state: status size modified name\n");
+        for (name, (status, stamp)) in state {
+            text += &format!("{} {} {} {}\n", status.name(), stamp.size, stamp.modified, paths::encode(name));
+        }
+        return text;
+    }
+
+    // `key=value` lines like the manifest, for people and monitoring
+    fn write_status(&self, current: Option<&OsStr>) {
+        let Some(status_filename) = &self.status_filename else {
+            return;
+        };
+        let count = |status| self.state.values().filter(|(handled, _)| *handled == status).count();
+        let mut text = format!("state={}\n", if current.is_some() { "splitting" } else { "watching" });
+        if let Some(current) = current {
+            text += &format!("file={}\n", paths::encode(current));
+        }
+        text += &format!("waiting={}\n", self.candidates.len());
+        text += &format!("done={}\nfailed={}\nskipped={}\n", count(FileStatus::Done), count(FileStatus::Failed), count(FileStatus::Skipped));
+        if let Some((name, status)) = &self.last {
+            text += &format!("last={} {}\n", status.name(), paths::encode(name));
+        }
+        text += &format!("updated={}\n", SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+        if let Err(err) = output::write_file(status_filename, text.as_bytes(), ClobberPolicy::Force) {
+            self.report.warning(&format!("status file {} cannot be written, {}", status_filename.display(), err));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trip() {
+        let mut state = BTreeMap::new();
+        state.insert(OsString::from("release 1.2.zip"), (FileStatus::Done, FileStamp{ size: 5000000, modified: 1760000000123456789 }));
+        state.insert(OsString::from("100%\nnew.bin"), (FileStatus::Failed, FileStamp{ size: 0, modified: 0 }));
+        state.insert(OsString::from("small.txt"), (FileStatus::Skipped, FileStamp{ size: 10, modified: 1 }));
+
+        let text = Watch::format_state(&state);
+        assert!(text.contains("done 5000000 1760000000123456789 release 1.2.zip\n"));
+        assert!(text.contains("failed 0 0 100%25%0Anew.bin\n"));
+        assert_eq!(Watch::parse_state(&text).unwrap(), state);
+    }
+
+    #[test]
+    fn broken_states() {
+        assert!(Watch::parse_state("").unwrap().is_empty());
+        assert!(Watch::parse_state("# comment only\n\n").unwrap().is_empty());
+        for line in ["done 10 1", "finished 10 1 a.zip", "done ten 1 a.zip", "done 10 -1 a.zip", "done 10 1 a%zz.zip"] {
+            let err = Watch::parse_state(&format!("# header\n{}\n", line)).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), format!("cannot parse line 2: \"{}\"", line));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// WebDAV collections, `webdav://host/path` over http and `webdavs://host/path` over https.
+// The user and password may be given in the URL, or the password in `SPLIMER_WEBDAV_PASSWORD`
+// to keep it out of the shell history. Files are uploaded under a temporary name and moved into place.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+
+use crate::http::{self, Request, Upload, Url};
+use crate::output::ClobberPolicy;
+use crate::store::{self, PartStore, PartWriter, StoredFile};
+use crate::xml;
+
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+    <propfind xmlns=\"DAV:\"><prop><getcontentlength/><resourcetype/></prop></propfind>";
+
+#[derive(Clone)]
+pub struct WebDavStore {
+    url: Url,
+    authorization: Option<String>,
+}
+
+struct WebDavWriter {
+    store: WebDavStore,
+    upload: Upload,
+    temporary_path: String,
+    path: String,
+}
+
+impl WebDavStore {
+    pub fn new(location: &str) -> io::Result<WebDavStore> {
+        let url = Url::parse(location)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a WebDAV URL", location)))?;
+        let password = url.password.clone().or_else(|| env::var("SPLIMER_WEBDAV_PASSWORD").ok());
+        let authorization = url.user.as_ref()
+            .map(|user| format!("Basic {}", http::base64(format!("{}:{}", user, password.unwrap_or_default()).as_bytes())));
+        return Ok(WebDavStore{ url, authorization });
+    }
+
+    fn path(&self, name: &OsStr) -> io::Result<String> {
+        return Ok(format!("{}/{}", self.url.path, store::remote_name(name)?));
+    }
+
+    fn request(&self, method: &'static str, path: &str, mut headers: Vec<(String, String)>, content_length: u64) -> io::Result<Upload> {
+        if let Some(authorization) = &self.authorization {
+            headers.push(("Authorization".to_string(), authorization.clone()));
+        }
+        return http::start(&self.url, Request{ method, target: http::encode_path(path), headers }, content_length);
+    }
+
+    fn what(&self, action: &str, path: &str) -> String {
+        return format!("{} of {}", action, self.url.to_http(path));
+    }
+}
+
+impl PartStore for WebDavStore {
+    fn create(&self, name: &OsStr, size: u64, _policy: ClobberPolicy) -> io::Result<Box<dyn PartWriter>> {
+        let path = self.path(name)?;
+        let temporary_path = format!("{}/{}", self.url.path, store::temporary_name(store::remote_name(name)?));
+        let upload = self.request("PUT", &temporary_path, Vec::new(), size)?;
+        return Ok(Box::new(WebDavWriter{ store: self.clone(), upload, temporary_path, path }));
+    }
+
+    fn read(&self, name: &OsStr, offset: u64) -> io::Result<Box<dyn Read>> {
+        let path = self.path(name)?;
+        let headers = if offset > 0 { vec![("Range".to_string(), format!("bytes={}-", offset))] } else { Vec::new() };
+        let response = self.request("GET", &path, headers, 0)?.finish()?;
+        // Nothing is left after `offset`
+        if response.status == 416 {
+            return Ok(Box::new(io::empty()));
+        }
+        let response = response.expect(&[200, 206], &self.what("Download", &path))?;
+        // Servers which don't do ranges send the whole file
+        let is_whole = response.status == 200;
+        let mut body = response.into_body();
+        if is_whole && offset > 0 {
+            io::copy(&mut (&mut body).take(offset), &mut io::sink())?;
+        }
+        return Ok(body);
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        let path = format!("{}/", self.url.path);
+        let headers = vec![
+            ("Depth".to_string(), "1".to_string()),
+            ("Content-Type".to_string(), "application/xml; charset=utf-8".to_string()),
+        ];
+        let mut upload = self.request("PROPFIND", &path, headers, PROPFIND_BODY.len() as u64)?;
+        upload.write_all(PROPFIND_BODY.as_bytes())?;
+        let response = upload.finish()?;
+        if response.status == 404 {
+            return Ok(Vec::new());
+        }
+        let text = response.expect(&[207], &self.what("Listing", &path))?.text()?;
+
+        let mut files = Vec::new();
+        for entry in xml::elements(&text, "response") {
+            // The collection itself and collections inside it are not files
+            let is_collection = xml::element(entry, "resourcetype").is_some_and(|kind| !xml::elements(kind, "collection").is_empty());
+            let Some(href) = xml::element(entry, "href").and_then(|href| http::decode(xml::unescape(href).trim())) else {
+                continue;
+            };
+            let Some(name) = href.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty()) else {
+                continue;
+            };
+            if is_collection {
+                continue;
+            }
+            let size = xml::element(entry, "getcontentlength").and_then(|size| size.trim().parse::<u64>().ok()).unwrap_or(0);
+            files.push(StoredFile{ name: name.into(), size });
+        }
+        return Ok(files);
+    }
+
+    fn stat(&self, name: &OsStr) -> io::Result<Option<u64>> {
+        let path = self.path(name)?;
+        let response = self.request("HEAD", &path, Vec::new(), 0)?.finish()?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        let response = response.expect(&[200], &self.what("Lookup", &path))?;
+        return Ok(Some(response.header("content-length").and_then(|length| length.parse().ok()).unwrap_or(0)));
+    }
+
+    fn delete(&self, name: &OsStr, _policy: ClobberPolicy) -> io::Result<()> {
+        let path = self.path(name)?;
+        let response = self.request("DELETE", &path, Vec::new(), 0)?.finish()?;
+        if response.status != 404 {
+            response.expect(&[200, 204], &self.what("Removal", &path))?;
+        }
+        return Ok(());
+    }
+}
+
+impl Write for WebDavWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        return self.upload.write(buffer);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.upload.flush();
+    }
+}
+
+impl PartWriter for WebDavWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let WebDavWriter{ store, upload, temporary_path, path } = *self;
+        upload.finish()?.expect(&[200, 201, 204], &store.what("Upload", &temporary_path))?;
+        let headers = vec![
+            ("Destination".to_string(), store.url.to_http(&path)),
+            ("Overwrite".to_string(), "T".to_string()),
+        ];
+        store.request("MOVE", &temporary_path, headers, 0)?
+            .finish()?
+            .expect(&[201, 204], &store.what("Move", &temporary_path))?;
+        return Ok(());
+    }
+}
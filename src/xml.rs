@@ -0,0 +1,149 @@
+// Just enough XML to read listings of object stores. Elements are found by their local name,
+// whatever namespace prefix they have, like `<D:href>` of WebDAV
+
+struct Tag<'a> {
+    local_name: &'a str,
+    is_closing: bool,
+    is_empty: bool,
+    // Where the tag starts and where it ends
+    start: usize,
+    end: usize,
+}
+
+fn next_tag(text: &str, from: usize) -> Option<Tag<'_>> {
+    let mut position = from;
+    loop {
+        let start = position + text[position..].find('<')?;
+        let end = start + text[start..].find('>')? + 1;
+        let inner = &text[start + 1..end - 1];
+        // Declarations, comments and processing instructions are skipped
+        if inner.starts_with('?') || inner.starts_with('!') {
+            position = end;
+            continue;
+        }
+        let is_closing = inner.starts_with('/');
+        let is_empty = inner.ends_with('/');
+        let name = inner.trim_start_matches('/').split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+        return Some(Tag{ local_name, is_closing, is_empty, start, end });
+    }
+}
+
+// Contents of all `name` elements, an element inside another one of the same name is a part of it
+pub fn elements<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+    let mut contents = Vec::new();
+    let mut position = 0;
+    while let Some(tag) = next_tag(text, position) {
+        position = tag.end;
+        if tag.local_name != name || tag.is_closing {
+            continue;
+        }
+        if tag.is_empty {
+            contents.push("");
+            continue;
+        }
+        let mut depth = 1;
+        while let Some(inner) = next_tag(text, position) {
+            position = inner.end;
+            if inner.local_name != name || inner.is_empty {
+                continue;
+            }
+            depth += if inner.is_closing { -1 } else { 1 };
+            if depth == 0 {
+                contents.push(&text[tag.end..inner.start]);
+                break;
+            }
+        }
+    }
+    return contents;
+}
+
+pub fn element<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    return elements(text, name).into_iter().next();
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(ampersand) = rest.find('&') {
+        unescaped += &rest[..ampersand];
+        rest = &rest[ampersand..];
+        let Some(semicolon) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semicolon];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").or(entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|decimal| decimal.parse::<u32>().ok()))
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[semicolon + 1..];
+            },
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    unescaped += rest;
+    return unescaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s3_listing() {
+        let listing = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>parts/</Prefix>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents><Key>parts/a.bin_[1].splm</Key><Size>1024</Size><StorageClass>STANDARD</StorageClass></Contents>
+  <Contents><Key>parts/Tom &amp; Jerry&#x21;.bin</Key><Size>5</Size></Contents>
+  <CommonPrefixes><Prefix>parts/old/</Prefix></CommonPrefixes>
+</ListBucketResult>"#;
+        let contents = elements(listing, "Contents");
+        assert_eq!(contents.len(), 2);
+        assert_eq!(element(contents[0], "Key"), Some("parts/a.bin_[1].splm"));
+        assert_eq!(element(contents[0], "Size"), Some("1024"));
+        assert_eq!(unescape(element(contents[1], "Key").unwrap()), "parts/Tom & Jerry!.bin");
+        assert_eq!(element(listing, "IsTruncated"), Some("true"));
+        assert_eq!(element(listing, "Missing"), None);
+    }
+
+    #[test]
+    fn webdav_listing() {
+        let listing = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/dav/parts/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/parts/a.bin_%5b1%5d.splm</d:href>
+    <d:propstat><d:prop><d:getcontentlength>1024</d:getcontentlength><d:resourcetype/></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let responses = elements(listing, "response");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(element(responses[0], "collection"), Some(""));
+        assert_eq!(element(responses[1], "collection"), None);
+        assert_eq!(element(responses[1], "href"), Some("/dav/parts/a.bin_%5b1%5d.splm"));
+        assert_eq!(element(responses[1], "getcontentlength"), Some("1024"));
+        // Elements inside elements of the same name belong to the outer one
+        assert_eq!(elements("<a>1<a>2</a></a><a>3</a>", "a"), vec!["1<a>2</a>", "3"]);
+        assert_eq!(unescape("&lt;&#60;&bogus;&"), "<<&bogus;&");
+    }
+}
@@ -0,0 +1,356 @@
+// `--boundary=zip` puts whole entries of a zip archive into parts and appends a central directory
+// of those entries to every part, so each part is a zip archive which opens on its own.
+// Bytes of entries are not changed, so a part is still a slice of the original followed by a trailer,
+// which merge leaves out. The original central directory stays as it is in the part of the last entries.
+// An entry which doesn't fit into a part is split into plain byte parts.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_END_LOCATOR: u32 = 0x07064b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE: usize = 56;
+const ZIP64_END_LOCATOR_SIZE: usize = 20;
+const CENTRAL_DIRECTORY_HEADER_SIZE: usize = 46;
+// End of central directory may be followed by a comment of up to 65535 bytes
+const MAX_END_SEARCH: u64 = END_OF_CENTRAL_DIRECTORY_SIZE as u64 + 0xFFFF;
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+
+struct Entry {
+    // Where its local header starts and where the next one starts
+    offset: u64,
+    end: u64,
+    // Its record of the central directory, as it is in the archive
+    central_record: Vec<u8>,
+    // Where the local header offset lies in `central_record`, 8 bytes in zip64 extra field or 4 bytes
+    offset_field: (usize, usize),
+}
+
+pub struct ZipLayout {
+    entries: Vec<Entry>,
+}
+
+pub struct ZipPart {
+    pub offset: u64,
+    pub size: u64,
+    // Entries of a part which is an archive, empty for a plain byte part
+    entries: Range<usize>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    return u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    return u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    return u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+}
+
+fn bad_archive(reason: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("it is not a zip archive splimer can read, {}", reason));
+}
+
+fn read_at(file: &mut File, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    return Ok(bytes);
+}
+
+impl ZipLayout {
+    pub fn read(file: &mut File, file_size: u64) -> io::Result<ZipLayout> {
+        let search_start = file_size.saturating_sub(MAX_END_SEARCH);
+        let tail = read_at(file, search_start, (file_size - search_start) as usize)?;
+        let end_position = (0..tail.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE - 1))
+            .rev()
+            .find(|&i| u32_at(&tail, i) == END_OF_CENTRAL_DIRECTORY)
+            .ok_or_else(|| bad_archive("there is no end of central directory"))?;
+        let end = &tail[end_position..];
+        if u16_at(end, 4) != 0 || u16_at(end, 6) != 0 {
+            return Err(bad_archive("multi-disk archives are not supported"));
+        }
+        let mut entry_count = u16_at(end, 10) as u64;
+        let mut directory_size = u32_at(end, 12) as u64;
+        let mut directory_offset = u32_at(end, 16) as u64;
+
+        // Zip64 keeps the real values in its own record, found through the locator right before
+        let end_offset = search_start + end_position as u64;
+        if entry_count == 0xFFFF || directory_size == 0xFFFF_FFFF || directory_offset == 0xFFFF_FFFF {
+            let locator_offset = end_offset.checked_sub(ZIP64_END_LOCATOR_SIZE as u64)
+                .ok_or_else(|| bad_archive("there is no zip64 locator"))?;
+            let locator = read_at(file, locator_offset, ZIP64_END_LOCATOR_SIZE)?;
+            if u32_at(&locator, 0) != ZIP64_END_LOCATOR {
+                return Err(bad_archive("there is no zip64 locator"));
+            }
+            let record = read_at(file, u64_at(&locator, 8), ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE)?;
+            if u32_at(&record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+                return Err(bad_archive("zip64 end of central directory is broken"));
+            }
+            entry_count = u64_at(&record, 32);
+            directory_size = u64_at(&record, 40);
+            directory_offset = u64_at(&record, 48);
+        }
+        if directory_offset.checked_add(directory_size).is_none_or(|directory_end| directory_end > end_offset) {
+            return Err(bad_archive("central directory lies outside of the file"));
+        }
+
+        let directory = read_at(file, directory_offset, directory_size as usize)?;
+        let mut entries = Vec::new();
+        let mut position = 0;
+        for _ in 0..entry_count {
+            if position + CENTRAL_DIRECTORY_HEADER_SIZE > directory.len() || u32_at(&directory, position) != CENTRAL_DIRECTORY_HEADER {
+                return Err(bad_archive("central directory is broken"));
+            }
+            let record_size = CENTRAL_DIRECTORY_HEADER_SIZE
+                + u16_at(&directory, position + 28) as usize
+                + u16_at(&directory, position + 30) as usize
+                + u16_at(&directory, position + 32) as usize;
+            let record = directory.get(position..position + record_size)
+                .ok_or_else(|| bad_archive("central directory is broken"))?
+                .to_vec();
+            let offset_field = Self::offset_field(&record)?;
+            let offset = match offset_field.1 {
+                8 => u64_at(&record, offset_field.0),
+                _ => u32_at(&record, offset_field.0) as u64,
+            };
+            entries.push(Entry{ offset, end: 0, central_record: record, offset_field });
+            position += record_size;
+        }
+
+        entries.sort_by_key(|entry| entry.offset);
+        for i in 0..entries.len() {
+            entries[i].end = entries.get(i + 1).map(|entry| entry.offset).unwrap_or(directory_offset);
+            if entries[i].end < entries[i].offset {
+                return Err(bad_archive("entries overlap the central directory"));
+            }
+        }
+        return Ok(ZipLayout{ entries });
+    }
+
+    // Local header offset is 4 bytes at 42, unless it is 0xFFFFFFFF and lies in zip64 extra field
+    fn offset_field(record: &[u8]) -> io::Result<(usize, usize)> {
+        if u32_at(record, 42) != 0xFFFF_FFFF {
+            return Ok((42, 4));
+        }
+        let extra_start = CENTRAL_DIRECTORY_HEADER_SIZE + u16_at(record, 28) as usize;
+        let extra_end = extra_start + u16_at(record, 30) as usize;
+        let mut position = extra_start;
+        while position + 4 <= extra_end {
+            let id = u16_at(record, position);
+            let size = u16_at(record, position + 2) as usize;
+            if id == ZIP64_EXTRA_FIELD {
+                // Sizes go first, each one only if it doesn't fit into its own field
+                let skipped = [24, 20].iter().filter(|&&at| u32_at(record, at) == 0xFFFF_FFFF).count() * 8;
+                if skipped + 8 > size {
+                    break;
+                }
+                return Ok((position + 4 + skipped, 8));
+            }
+            position += 4 + size;
+        }
+        return Err(bad_archive("zip64 extra field is broken"));
+    }
+
+    // Entries in turn while they fit into a part together with their central directory.
+    // The original central directory goes into the part of the last entries, where readers skip it
+    // as it lies before the part's own one. Whatever doesn't fit is cut into plain byte parts
+    pub fn plan_parts(&self, file_size: u64, limit: impl Fn(usize) -> u64) -> Vec<ZipPart> {
+        let mut parts = Vec::new();
+        // Bytes before the first entry go with it
+        let mut start = 0;
+        let mut first = 0;
+        while first < self.entries.len() {
+            let part_limit = limit(parts.len());
+            let fits = |entry_count: usize, end: u64, directory_size: u64| {
+                return end - start + Self::trailer_size(entry_count, end - start, directory_size) <= part_limit;
+            };
+            let mut last = first;
+            let mut directory_size = 0;
+            while last < self.entries.len() {
+                let record_size = self.entries[last].central_record.len() as u64;
+                if !fits(last + 1 - first, self.entries[last].end, directory_size + record_size) {
+                    break;
+                }
+                directory_size += record_size;
+                last += 1;
+            }
+            // The last entry goes into a part of its own when there is no room for the original central directory
+            if last == self.entries.len() && last - first > 1 && !fits(last - first, file_size, directory_size) {
+                last -= 1;
+                directory_size -= self.entries[last].central_record.len() as u64;
+            }
+            if last > first {
+                let mut end = self.entries[last - 1].end;
+                if last == self.entries.len() && fits(last - first, file_size, directory_size) {
+                    end = file_size;
+                }
+                parts.push(ZipPart{ offset: start, size: end - start, entries: first..last });
+                start = end;
+                first = last;
+                continue;
+            }
+            Self::plan_byte_parts(&mut parts, start, self.entries[first].end, &limit);
+            start = self.entries[first].end;
+            first += 1;
+        }
+        Self::plan_byte_parts(&mut parts, start, file_size, &limit);
+        return parts;
+    }
+
+    fn plan_byte_parts(parts: &mut Vec<ZipPart>, mut start: u64, end: u64, limit: &impl Fn(usize) -> u64) {
+        while start < end {
+            let size = limit(parts.len()).max(1).min(end - start);
+            parts.push(ZipPart{ offset: start, size, entries: 0..0 });
+            start += size;
+        }
+    }
+
+    // Zip64 records are needed when a count or an offset doesn't fit into the plain end of central directory
+    fn is_zip64(entry_count: usize, directory_offset: u64, directory_size: u64) -> bool {
+        return entry_count >= 0xFFFF || directory_offset >= 0xFFFF_FFFF || directory_size >= 0xFFFF_FFFF;
+    }
+
+    fn trailer_size(entry_count: usize, directory_offset: u64, directory_size: u64) -> u64 {
+        let mut size = directory_size + END_OF_CENTRAL_DIRECTORY_SIZE as u64;
+        if Self::is_zip64(entry_count, directory_offset, directory_size) {
+            size += (ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE + ZIP64_END_LOCATOR_SIZE) as u64;
+        }
+        return size;
+    }
+
+    // Central directory of the part's entries with offsets counted from the part's beginning
+    pub fn trailer(&self, part: &ZipPart) -> Vec<u8> {
+        if part.entries.is_empty() {
+            return Vec::new();
+        }
+        let mut trailer = Vec::new();
+        for entry in &self.entries[part.entries.clone()] {
+            let mut record = entry.central_record.clone();
+            let offset = entry.offset - part.offset;
+            match entry.offset_field {
+                (at, 8) => record[at..at + 8].copy_from_slice(&offset.to_le_bytes()),
+                (at, _) => record[at..at + 4].copy_from_slice(&(offset as u32).to_le_bytes()),
+            }
+            trailer.extend_from_slice(&record);
+        }
+
+        let entry_count = part.entries.len() as u64;
+        let directory_size = trailer.len() as u64;
+        let directory_offset = part.size;
+        let is_zip64 = Self::is_zip64(part.entries.len(), directory_offset, directory_size);
+        if is_zip64 {
+            let record_offset = directory_offset + directory_size;
+            trailer.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+            trailer.extend_from_slice(&(ZIP64_END_OF_CENTRAL_DIRECTORY_SIZE as u64 - 12).to_le_bytes());
+            // Made by and needed to extract: version 4.5, which has zip64
+            trailer.extend_from_slice(&45u16.to_le_bytes());
+            trailer.extend_from_slice(&45u16.to_le_bytes());
+            trailer.extend_from_slice(&[0; 8]);
+            trailer.extend_from_slice(&entry_count.to_le_bytes());
+            trailer.extend_from_slice(&entry_count.to_le_bytes());
+            trailer.extend_from_slice(&directory_size.to_le_bytes());
+            trailer.extend_from_slice(&directory_offset.to_le_bytes());
+
+            trailer.extend_from_slice(&ZIP64_END_LOCATOR.to_le_bytes());
+            trailer.extend_from_slice(&[0; 4]);
+            trailer.extend_from_slice(&record_offset.to_le_bytes());
+            trailer.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        let clamp_u16 = |value: u64| if is_zip64 { 0xFFFF } else { value as u16 };
+        let clamp_u32 = |value: u64| if is_zip64 { 0xFFFF_FFFF } else { value as u32 };
+        trailer.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        trailer.extend_from_slice(&[0; 4]);
+        trailer.extend_from_slice(&clamp_u16(entry_count).to_le_bytes());
+        trailer.extend_from_slice(&clamp_u16(entry_count).to_le_bytes());
+        trailer.extend_from_slice(&clamp_u32(directory_size).to_le_bytes());
+        trailer.extend_from_slice(&clamp_u32(directory_offset).to_le_bytes());
+        trailer.extend_from_slice(&[0; 2]);
+        return trailer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    fn layout(directory: &TempDir, name: &str, bytes: &[u8]) -> io::Result<ZipLayout> {
+        let mut file = directory.file(name, bytes);
+        return ZipLayout::read(&mut file, bytes.len() as u64);
+    }
+
+    fn spans(parts: &[ZipPart]) -> Vec<(u64, u64, Range<usize>)> {
+        return parts.iter().map(|part| (part.offset, part.size, part.entries.clone())).collect();
+    }
+
+    #[test]
+    fn entries_go_into_parts_of_their_own() {
+        let directory = TempDir::new();
+        // Every entry takes 45 bytes, its central record 51 bytes
+        let bytes = testing::zip_archive(&[("a.txt", &[b'a'; 10]), ("b.txt", &[b'b'; 10]), ("c.txt", &[b'c'; 10])]);
+        assert_eq!(bytes.len(), 310);
+        let zip = layout(&directory, "archive.zip", &bytes).unwrap();
+
+        assert_eq!(spans(&zip.plan_parts(310, |_| 1000)), vec![(0, 310, 0..3)]);
+        // The original central directory of 175 bytes doesn't fit next to all of them, only next to the last one
+        assert_eq!(spans(&zip.plan_parts(310, |_| 350)), vec![(0, 90, 0..2), (90, 220, 2..3)]);
+        assert_eq!(spans(&zip.plan_parts(310, |_| 150)), vec![
+            (0, 45, 0..1),
+            (45, 45, 1..2),
+            (90, 45, 2..3),
+            (135, 150, 0..0),
+            (285, 25, 0..0),
+        ]);
+        // An entry which doesn't fit with its central directory is cut into plain bytes
+        assert_eq!(spans(&zip.plan_parts(310, |_| 100)), vec![
+            (0, 45, 0..0),
+            (45, 45, 0..0),
+            (90, 45, 0..0),
+            (135, 100, 0..0),
+            (235, 75, 0..0),
+        ]);
+    }
+
+    #[test]
+    fn parts_are_archives() {
+        let directory = TempDir::new();
+        let bytes = testing::zip_archive(&[("a.txt", &[b'a'; 10]), ("b.txt", &[b'b'; 20]), ("c.txt", &[b'c'; 30])]);
+        let zip = layout(&directory, "archive.zip", &bytes).unwrap();
+        let parts = zip.plan_parts(bytes.len() as u64, |_| 250);
+        assert_eq!(spans(&parts)[..2], [(0, 100, 0..2), (100, 65, 2..3)]);
+        assert!(zip.trailer(&parts[2]).is_empty());
+        let folded = zip.plan_parts(bytes.len() as u64, |_| 350);
+        assert_eq!(spans(&folded), vec![(0, 100, 0..2), (100, 240, 2..3)]);
+
+        for (i, part) in parts[..2].iter().chain(&folded[1..]).enumerate() {
+            let mut part_bytes = bytes[part.offset as usize..(part.offset + part.size) as usize].to_vec();
+            part_bytes.extend_from_slice(&zip.trailer(part));
+            assert!(part_bytes.len() <= 350);
+            let part_zip = layout(&directory, &format!("part{}.zip", i), &part_bytes).unwrap();
+            assert_eq!(part_zip.entries.len(), part.entries.len());
+            assert_eq!(part_zip.entries[0].offset, 0);
+            assert_eq!(part_zip.entries.last().unwrap().end, part.size);
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let directory = TempDir::new();
+        let err = layout(&directory, "text.txt", b"just some text, not an archive").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = testing::zip_archive(&[("a.txt", b"a")]);
+        // Central directory offset points past the end of central directory
+        let at = bytes.len() - 6;
+        bytes[at..at + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(layout(&directory, "broken.zip", &bytes).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
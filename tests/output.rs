@@ -0,0 +1,121 @@
+#![allow(clippy::needless_return)]
+
+// `--output-format=jsonl` as a script sees it: events in stdout, text for people in stderr
+// and an exit status which tells whether the command failed
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+fn splimer(directory: &PathBuf, arguments: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_splimer"))
+        .current_dir(directory)
+        .args(arguments)
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .output()
+        .unwrap();
+}
+
+fn directory(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("splimer-output-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    return path;
+}
+
+// Event name of every line, every line has to be a JSON object
+fn events(output: &Output) -> Vec<String> {
+    return String::from_utf8_lossy(&output.stdout).lines().map(|line| {
+        assert!(line.starts_with("{\"event\": \"") && line.ends_with('}'), "not an event: {}", line);
+        return line["{\"event\": \"".len()..].split('"').next().unwrap().to_string();
+    }).collect();
+}
+
+fn line_of<'a>(output: &'a Output, event: &str) -> &'a str {
+    let start = format!("{{\"event\": \"{}\"", event);
+    return std::str::from_utf8(&output.stdout).unwrap().lines().find(|line| line.starts_with(&start)).unwrap();
+}
+
+#[test]
+fn split_sends_events_to_stdout_and_text_to_stderr() {
+    let directory = directory("split");
+    fs::write(directory.join("a.bin"), vec![7u8; 5000]).unwrap();
+    let output = splimer(&directory, &["split", "a.bin", "-S", "2k", "--output-format=jsonl"]);
+    assert!(output.status.success());
+
+    let events: Vec<String> = events(&output).into_iter().filter(|event| event != "progress").collect();
+    assert_eq!(events, [
+        "part_started", "part_finished",
+        "part_started", "part_finished",
+        "part_started", "part_finished",
+        "done",
+    ]);
+    let finished = line_of(&output, "part_finished");
+    assert!(finished.starts_with("{\"event\": \"part_finished\", \"index\": 1, \"path\": \"a.bin_[1].splm\", \"offset\": 0, \"size\": 2048, \"sha256\": \""));
+    let done = line_of(&output, "done");
+    assert!(done.starts_with("{\"event\": \"done\", \"file_name\": \"a.bin\", \"manifest\": \"a.bin_[manifest].splm\", \"parts\": 3, \"part_count\": 3, \"bytes\": 5000, "));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("File a.bin_[1].splm is written"));
+    assert!(stderr.contains("The job is done!"));
+    let _ = fs::remove_dir_all(&directory);
+}
+
+#[test]
+fn errors_are_events_and_fail_the_command() {
+    let directory = directory("errors");
+    fs::write(directory.join("a.bin"), vec![7u8; 5000]).unwrap();
+    let output = splimer(&directory, &["split", "a.bin", "-S", "2k", "--wait-for-hook", "--output-format=jsonl"]);
+    assert!(!output.status.success());
+    assert_eq!(events(&output), ["error"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: `--delete-after-hook`, `--wait-for-hook`"));
+
+    let output = splimer(&directory, &["split", "a.bin", "-S", "2k", "--store", "s3://bucket/parts", "--output-format=jsonl"]);
+    assert!(!output.status.success());
+    assert!(line_of(&output, "error").starts_with("{\"event\": \"error\", \"message\": \"Store s3://bucket/parts cannot be used: "));
+    assert!(!directory.join("a.bin_[1].splm").exists());
+    let _ = fs::remove_dir_all(&directory);
+}
+
+// `"size": N` of every part in the plan of `--dry-run --json`
+fn planned_sizes(output: &Output) -> Vec<u64> {
+    let plan = String::from_utf8_lossy(&output.stdout);
+    return plan.split("\"size\": ").skip(1)
+        .map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap())
+        .collect();
+}
+
+fn required_space(output: &Output) -> u64 {
+    let plan = String::from_utf8_lossy(&output.stdout);
+    let rest = plan.split("\"required_space\": ").nth(1).unwrap();
+    return rest.split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap();
+}
+
+#[test]
+fn dry_run_plans_the_parts_which_split_writes() {
+    let directory = directory("dry-run");
+    let mut csv = String::from("id,name,value\n");
+    for i in 0..400 {
+        csv.push_str(&format!("{},name {},{}\n", i, i, i * 37));
+    }
+    fs::write(directory.join("a.csv"), &csv).unwrap();
+    let arguments = ["split", "a.csv", "-S", "2k", "--boundary=csv", "--csv-header"];
+
+    let plan = splimer(&directory, &[&arguments[..], &["--dry-run", "--json"]].concat());
+    assert!(plan.status.success());
+    let output = splimer(&directory, &arguments);
+    assert!(output.status.success());
+
+    // Every part but the first one has the header row, which the plan counts too
+    let written: Vec<u64> = (1..).map(|index| directory.join(format!("a.csv_[{}].splm", index)))
+        .take_while(|path| path.exists())
+        .map(|path| fs::metadata(path).unwrap().len())
+        .collect();
+    assert!(written.len() > 2);
+    assert_eq!(planned_sizes(&plan), written);
+    let manifest_size = fs::metadata(directory.join("a.csv_[manifest].splm")).unwrap().len();
+    assert!(required_space(&plan) >= written.iter().sum::<u64>() + manifest_size);
+    assert!(required_space(&plan) < written.iter().sum::<u64>() + manifest_size + 64);
+    let _ = fs::remove_dir_all(&directory);
+}